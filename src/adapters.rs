@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::units::ValueUnit;
+
+/// Mapping of an export whose columns differ from the standard input, picked with
+/// `--input-schema`. Applies to csv and Parquet inputs, JSON inputs always use the relay API
+/// field names.
+///
+/// A TOML profile looks like
+///
+/// ```toml
+/// value_unit = "eth"
+///
+/// [columns]
+/// proposer_fee_recipient = "feeRecipient"
+/// value = "value_eth"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputSchema {
+    /// Column of the export by standard column name, columns not listed keep their name.
+    #[serde(default)]
+    pub columns: BTreeMap<String, String>,
+    /// Unit of the `value` column.
+    #[serde(default)]
+    pub value_unit: ValueUnit,
+}
+
+impl InputSchema {
+    /// Exports with camelCase field names, as some relay dumps use.
+    fn camel_case() -> Self {
+        let columns = [
+            ("proposer_fee_recipient", "proposerFeeRecipient"),
+            ("block_hash", "blockHash"),
+            ("block_number", "blockNumber"),
+            ("builder_pubkey", "builderPubkey"),
+        ];
+        Self {
+            columns: columns
+                .into_iter()
+                .map(|(standard, export)| (standard.to_string(), export.to_string()))
+                .collect(),
+            value_unit: ValueUnit::Wei,
+        }
+    }
+
+    /// Payloads delivered as exported by relayscan. Its other columns already have the standard
+    /// names, the claimed value is the bid.
+    fn relayscan() -> Self {
+        Self {
+            columns: [("value".to_string(), "value_claimed_wei".to_string())]
+                .into_iter()
+                .collect(),
+            value_unit: ValueUnit::Wei,
+        }
+    }
+
+    /// Historical payload dumps of the bloXroute relays, which name the slot and fee recipient
+    /// columns differently and give the value in ETH.
+    fn bloxroute() -> Self {
+        let columns = [
+            ("slot", "slot_number"),
+            ("proposer_fee_recipient", "fee_recipient"),
+            ("value", "reward"),
+        ];
+        Self {
+            columns: columns
+                .into_iter()
+                .map(|(standard, export)| (standard.to_string(), export.to_string()))
+                .collect(),
+            value_unit: ValueUnit::Eth,
+        }
+    }
+
+    /// Historical payload dumps of the Eden relay, with camelCase field names and the value in
+    /// gwei.
+    fn eden() -> Self {
+        Self {
+            value_unit: ValueUnit::Gwei,
+            ..Self::camel_case()
+        }
+    }
+
+    /// Resolves a bundled schema by name, otherwise reads `schema` as a path to a TOML profile.
+    pub fn resolve(schema: &str) -> eyre::Result<Self> {
+        match schema {
+            "standard" => Ok(Self::default()),
+            "camel-case" => Ok(Self::camel_case()),
+            "relayscan" => Ok(Self::relayscan()),
+            "bloxroute" => Ok(Self::bloxroute()),
+            "eden" => Ok(Self::eden()),
+            path => {
+                let profile = std::fs::read_to_string(Path::new(path)).map_err(|e| {
+                    eyre::eyre!(
+                        "{path} is neither a known input schema nor a readable profile: {e}"
+                    )
+                })?;
+                Ok(toml::from_str(&profile)?)
+            }
+        }
+    }
+
+    /// Header of the export with its columns renamed to the standard names.
+    pub fn standard_header(&self, header: &csv::StringRecord) -> csv::StringRecord {
+        header
+            .iter()
+            .map(|column| {
+                self.columns
+                    .iter()
+                    .find(|(_, export)| *export == column)
+                    .map_or(column, |(standard, _)| standard.as_str())
+            })
+            .collect()
+    }
+
+    /// Converts a `value` field to wei.
+    pub fn value_in_wei(&self, value: &str) -> eyre::Result<String> {
+        Ok(self.value_unit.parse_u256(value.trim())?.to_string())
+    }
+}
@@ -0,0 +1,108 @@
+use ethers::types::{I256, U256};
+
+use crate::OutputFileEntry;
+
+/// Posts a message to a Slack or Discord compatible webhook for suspicious rows.
+#[derive(Debug, Clone)]
+pub struct Alerter {
+    client: reqwest::Client,
+    url: String,
+    /// Underpaid rows only alert when the shortfall is at least this large.
+    min_shortfall: U256,
+}
+
+impl Alerter {
+    pub fn new(url: String, min_shortfall: U256) -> Self {
+        Self {
+            client: crate::rpc::http_client(),
+            url,
+            min_shortfall,
+        }
+    }
+
+    fn message(&self, entry: &OutputFileEntry) -> Option<String> {
+        if entry.era == "pre_merge" {
+            return None;
+        }
+        if entry.payment_type == "unknown" {
+            return Some(format!(
+                "slot {} (block {}): proposer payment could not be identified ({}), bid {} wei, balance diff {} wei",
+                entry.slot,
+                entry.block_number,
+                entry.unknown_reason.as_deref().unwrap_or("no reason"),
+                entry.bid_value,
+                entry.balance_diff
+            ));
+        }
+        if entry.underpaid == Some(true) && -entry.delta_wei >= I256::from_raw(self.min_shortfall) {
+            return Some(format!(
+                "slot {} (block {}): proposer underpaid by {} wei, bid {} wei, observed {} wei",
+                entry.slot,
+                entry.block_number,
+                -entry.delta_wei,
+                entry.bid_value,
+                entry.observed_payment
+            ));
+        }
+        if entry.block_hash_match == Some(false) {
+            return Some(format!(
+                "slot {} (block {}): delivered block hash is not the canonical block",
+                entry.slot, entry.block_number
+            ));
+        }
+        if entry.relay_value_mismatch {
+            return Some(format!(
+                "slot {} (block {}): input bid {} wei doesn't match the {} wei the relay reports",
+                entry.slot,
+                entry.block_number,
+                entry.bid_value,
+                entry.relay_value.unwrap_or_default()
+            ));
+        }
+        if entry.wrong_recipient {
+            return Some(format!(
+                "slot {} (block {}): block paid a fee recipient other than the expected {:?}",
+                entry.slot,
+                entry.block_number,
+                entry.expected_fee_recipient.unwrap_or_default()
+            ));
+        }
+        if entry.registration_mismatch {
+            return Some(format!(
+                "slot {} (block {}): block paid a fee recipient other than the registered {:?}",
+                entry.slot,
+                entry.block_number,
+                entry.registered_fee_recipient.unwrap_or_default()
+            ));
+        }
+        None
+    }
+
+    /// Sends an alert if `entry` warrants one. Delivery failures are logged, not returned.
+    pub async fn check(&self, entry: &OutputFileEntry) {
+        let Some(message) = self.message(entry) else {
+            return;
+        };
+        // `text` is read by Slack, `content` by Discord
+        let body = serde_json::json!({
+            "text": message,
+            "content": message,
+            "slot": entry.slot,
+            "block_number": entry.block_number,
+            "payment_type": entry.payment_type,
+            "bid_value": entry.bid_value.to_string(),
+            "observed_payment": entry.observed_payment.to_string(),
+            "delta_wei": entry.delta_wei.to_string(),
+        });
+        let res = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = res {
+            tracing::warn!(error = %e, slot = entry.slot, "failed to send alert");
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+
+use ethers::types::{Address, Block, Bytes, Transaction, TransactionReceipt, U256};
+use serde::Serialize;
+
+use crate::trace::Traces;
+
+/// Everything a row was computed from, as fetched from the node.
+#[derive(Debug, Serialize)]
+pub struct ArchivedEntry<'a> {
+    pub block_number: u64,
+    pub fee_recipient: Address,
+    pub block: &'a Block<Transaction>,
+    pub receipts: &'a [TransactionReceipt],
+    pub balance_before: Option<U256>,
+    pub balance_after: Option<U256>,
+    pub fee_recipient_code: &'a Bytes,
+    /// With `--light-blocks` the bodies of the txs before this index weren't fetched, their value
+    /// and input are zero here rather than the real ones.
+    pub bodies_from: usize,
+    /// `None` when the node couldn't trace the block.
+    pub traces: Option<&'a Traces>,
+}
+
+/// Directory of gzipped JSON files with the raw data of every computed entry, so a disputed
+/// classification can be checked later without a node that still has the block's state.
+///
+/// Entries answered from the results cache are not archived again.
+#[derive(Debug, Clone)]
+pub struct Archive {
+    pub dir: PathBuf,
+}
+
+impl Archive {
+    fn path(&self, block_number: u64, fee_recipient: Address) -> PathBuf {
+        self.dir
+            .join(block_number.to_string())
+            .join(format!("{fee_recipient:?}.json.gz"))
+    }
+
+    pub async fn write(&self, entry: &ArchivedEntry<'_>) -> eyre::Result<()> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        serde_json::to_writer(&mut encoder, entry)?;
+        let compressed = encoder.finish()?;
+
+        let path = self.path(entry.block_number, entry.fee_recipient);
+        tokio::fs::create_dir_all(path.parent().expect("archive path has a parent")).await?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, compressed).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use futures::stream::StreamExt;
+
+use crate::file::{output_header, output_record, read_entries, Rng};
+use crate::rpc::EthProvider;
+use crate::streams::DialectArgs;
+use crate::{process_input_entry, read_output_file_as, ProcessingOptions};
+
+/// Filled by opt-in lookups an audit doesn't repeat, copied from the stored row before comparing.
+const SKIPPED_COLUMNS: &[&str] = &[
+    "proposer_index",
+    "proposer_pubkey",
+    "relay_value",
+    "relay_value_mismatch",
+    "best_bid_value",
+    "bid_margin",
+    "winning_bid_ms_into_slot",
+    "registered_fee_recipient",
+    "registration_mismatch",
+    "expected_fee_recipient",
+    "wrong_recipient",
+];
+
+/// Recomputes `sample` random rows of `output` from `input` and prints every column that differs
+/// from the stored row. Fails when any row diverges or can't be recomputed.
+#[allow(clippy::too_many_arguments)]
+pub async fn audit(
+    provider: &EthProvider,
+    options: &ProcessingOptions,
+    output: &Path,
+    input: &Path,
+    dialect: &DialectArgs,
+    sample: usize,
+    seed: u64,
+    rpc_parallel: usize,
+) -> eyre::Result<()> {
+    let mut entries = read_output_file_as(output, dialect.output(output))?;
+    // a missed slot has no block to recompute
+    entries.retain(|entry| !entry.is_missed());
+    let mut rng = Rng::new(seed);
+    let sample = sample.min(entries.len());
+    // partial Fisher-Yates, the sample ends up at the front
+    for i in 0..sample {
+        let j = i + rng.below(entries.len() - i);
+        entries.swap(i, j);
+    }
+    entries.truncate(sample);
+
+    let mut inputs = HashMap::new();
+    for row in read_entries(input, &dialect.input(input)?)? {
+        let row = row?;
+        inputs.insert(row.slot, row);
+    }
+
+    let header = output_header()?;
+    let mut results = futures::stream::iter(entries)
+        .map(|stored| {
+            let input = inputs.remove(&stored.slot);
+            async move {
+                let input =
+                    input.ok_or_else(|| eyre::eyre!("slot {} not in the input", stored.slot))?;
+                let mut recomputed = process_input_entry(provider, options, input).await?;
+                recomputed.proposer_index = stored.proposer_index;
+                recomputed.proposer_pubkey = stored.proposer_pubkey.clone();
+                recomputed.relay_value = stored.relay_value;
+                recomputed.relay_value_mismatch = stored.relay_value_mismatch;
+                recomputed.best_bid_value = stored.best_bid_value;
+                recomputed.bid_margin = stored.bid_margin;
+                recomputed.winning_bid_ms_into_slot = stored.winning_bid_ms_into_slot;
+                recomputed.registered_fee_recipient = stored.registered_fee_recipient;
+                recomputed.registration_mismatch = stored.registration_mismatch;
+                recomputed.expected_fee_recipient = stored.expected_fee_recipient;
+                recomputed.wrong_recipient = stored.wrong_recipient;
+                Ok::<_, eyre::Report>((stored, recomputed))
+            }
+        })
+        .buffer_unordered(rpc_parallel.max(1));
+
+    let mut diverged = 0;
+    let mut failed = 0;
+    while let Some(result) = results.next().await {
+        let (stored, recomputed) = match result {
+            Ok(rows) => rows,
+            Err(e) => {
+                failed += 1;
+                println!("failed: {e:#}");
+                continue;
+            }
+        };
+        let (old, new) = (output_record(&stored)?, output_record(&recomputed)?);
+        let diffs: Vec<_> = header
+            .iter()
+            .zip(old.iter().zip(new.iter()))
+            .filter(|(column, (old, new))| old != new && !SKIPPED_COLUMNS.contains(column))
+            .collect();
+        if diffs.is_empty() {
+            continue;
+        }
+        diverged += 1;
+        println!("slot {}:", stored.slot);
+        for (column, (old, new)) in diffs {
+            println!("  {column}: {old} -> {new}");
+        }
+    }
+
+    println!("audited:  {sample}");
+    println!("diverged: {diverged}");
+    println!("failed:   {failed}");
+    if diverged + failed > 0 {
+        eyre::bail!("{diverged} rows diverged and {failed} failed");
+    }
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeaderData {
+    header: SignedHeader,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedHeader {
+    message: HeaderMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeaderMessage {
+    proposer_index: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockData {
+    message: BlockMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockMessage {
+    body: BlockBody,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockBody {
+    /// Missing before the merge.
+    execution_payload: Option<ExecutionPayload>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExecutionPayload {
+    block_number: String,
+    fee_recipient: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProposerDuty {
+    pubkey: String,
+    validator_index: String,
+    slot: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValidatorData {
+    validator: Validator,
+}
+
+#[derive(Debug, Deserialize)]
+struct Validator {
+    pubkey: String,
+}
+
+/// Minimal beacon node API client.
+#[derive(Debug)]
+pub struct BeaconClient {
+    client: reqwest::Client,
+    url: String,
+    /// Validator pubkeys never change for an index, so they are looked up once.
+    pubkeys: Mutex<HashMap<u64, String>>,
+    /// Proposer duties by epoch, looked up once for all slots of the epoch.
+    duties: Mutex<HashMap<u64, Vec<ProposerDuty>>>,
+}
+
+impl BeaconClient {
+    pub fn new(url: &str) -> Self {
+        Self {
+            client: crate::rpc::http_client(),
+            url: url.trim_end_matches('/').to_string(),
+            pubkeys: Mutex::new(HashMap::new()),
+            duties: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> eyre::Result<Option<T>> {
+        let response = self
+            .client
+            .get(format!("{}{}", self.url, path))
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response: Response<T> = response.error_for_status()?.json().await?;
+        Ok(Some(response.data))
+    }
+
+    /// Proposer of the block at `slot`, `None` if the slot was missed.
+    pub async fn proposer_index(&self, slot: u64) -> eyre::Result<Option<u64>> {
+        let header: Option<HeaderData> =
+            self.get(&format!("/eth/v1/beacon/headers/{slot}")).await?;
+        match header {
+            Some(header) => Ok(Some(header.header.message.proposer_index.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Number and fee recipient of the execution block proposed at `slot`, `None` if the slot was
+    /// missed or is before the merge.
+    pub async fn execution_block(
+        &self,
+        slot: u64,
+    ) -> eyre::Result<Option<(u64, ethers::types::Address)>> {
+        let block: Option<BlockData> = self.get(&format!("/eth/v2/beacon/blocks/{slot}")).await?;
+        match block.and_then(|block| block.message.body.execution_payload) {
+            Some(payload) => Ok(Some((
+                payload.block_number.parse()?,
+                payload.fee_recipient.parse()?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Index and pubkey of the validator scheduled to propose at `slot`, known for missed slots
+    /// too. Nodes may only serve duties of recent epochs, the proposer of an older slot they
+    /// reject is unknown.
+    pub async fn proposer_duty(
+        &self,
+        slot: u64,
+        slots_per_epoch: u64,
+    ) -> eyre::Result<Option<(u64, String)>> {
+        let epoch = slot / slots_per_epoch;
+        let cached = self.duties.lock().unwrap().get(&epoch).cloned();
+        let duties = match cached {
+            Some(duties) => duties,
+            None => {
+                let duties: Vec<ProposerDuty> = match self
+                    .get(&format!("/eth/v1/validator/duties/proposer/{epoch}"))
+                    .await
+                {
+                    Ok(duties) => duties.unwrap_or_default(),
+                    Err(e) if is_rejected(&e) => {
+                        tracing::debug!(epoch, "proposer duties unavailable: {e}");
+                        Vec::new()
+                    }
+                    Err(e) => return Err(e),
+                };
+                self.duties.lock().unwrap().insert(epoch, duties.clone());
+                duties
+            }
+        };
+        for duty in duties {
+            if duty.slot.parse::<u64>()? == slot {
+                return Ok(Some((duty.validator_index.parse()?, duty.pubkey)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn validator_pubkey(&self, index: u64) -> eyre::Result<String> {
+        if let Some(pubkey) = self.pubkeys.lock().unwrap().get(&index) {
+            return Ok(pubkey.clone());
+        }
+        let validator: ValidatorData = self
+            .get(&format!("/eth/v1/beacon/states/head/validators/{index}"))
+            .await?
+            .ok_or_else(|| eyre::eyre!("validator {index} not found"))?;
+        self.pubkeys
+            .lock()
+            .unwrap()
+            .insert(index, validator.validator.pubkey.clone());
+        Ok(validator.validator.pubkey)
+    }
+}
+
+/// Whether the node answered with an error status, as opposed to not answering at all.
+fn is_rejected(error: &eyre::Report) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .map_or(false, |e| e.status().is_some())
+}
@@ -0,0 +1,181 @@
+use std::path::Path;
+
+use ethers::types::{Address, Bytes};
+use serde::{Deserialize, Serialize};
+
+/// Builder known by its block `extra_data` and the addresses it pays proposers from.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct KnownBuilder {
+    pub name: String,
+    /// Names matched (case-insensitively) against the start of the decoded `extra_data` or a whole
+    /// word in it.
+    #[serde(default)]
+    pub extra_data: Vec<String>,
+    #[serde(default)]
+    pub payout_addresses: Vec<Address>,
+    /// BLS pubkeys the builder submits blocks to relays with, matched against the input's
+    /// `builder_pubkey`.
+    #[serde(default)]
+    pub pubkeys: Vec<String>,
+}
+
+/// Builder list as bundled, downloaded by `update-builders` or given with `--builder-overrides`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuilderRegistry {
+    /// Chain the builders are on, mainnet if unset like the bundled list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
+    #[serde(default)]
+    pub builders: Vec<KnownBuilder>,
+}
+
+impl BuilderRegistry {
+    pub fn parse(registry: &str) -> eyre::Result<Self> {
+        Ok(toml::from_str(registry)?)
+    }
+
+    pub fn chain(&self) -> &str {
+        self.chain.as_deref().unwrap_or("mainnet")
+    }
+
+    pub fn read(path: &Path) -> eyre::Result<Self> {
+        let registry = std::fs::read_to_string(path)
+            .map_err(|e| eyre::eyre!("can't read builder registry {}: {e}", path.display()))?;
+        Self::parse(&registry)
+    }
+}
+
+pub fn mainnet_builders() -> Vec<KnownBuilder> {
+    BuilderRegistry::parse(include_str!("builders.toml"))
+        .expect("bundled builder registry is valid")
+        .builders
+}
+
+/// `builders` replaced by the `registry` file written by `update-builders` if it exists and is
+/// for `chain`, with the builders of `overrides` replacing those of the same name or added.
+pub fn load(
+    mut builders: Vec<KnownBuilder>,
+    chain: &str,
+    registry: Option<&Path>,
+    overrides: Option<&Path>,
+) -> eyre::Result<Vec<KnownBuilder>> {
+    if let Some(path) = registry.filter(|path| path.exists()) {
+        let registry = BuilderRegistry::read(path)?;
+        if registry.chain() == chain {
+            builders = registry.builders;
+        } else {
+            tracing::warn!(
+                "ignoring builder registry {} of chain {}",
+                path.display(),
+                registry.chain()
+            );
+        }
+    }
+    if let Some(overrides) = overrides {
+        for builder in BuilderRegistry::read(overrides)?.builders {
+            match builders.iter_mut().find(|b| b.name == builder.name) {
+                Some(existing) => *existing = builder,
+                None => builders.push(builder),
+            }
+        }
+    }
+    Ok(builders)
+}
+
+/// Downloads the registry of `chain` at `url` into `path`, replacing it only once it parsed. A
+/// registry that doesn't name its chain is recorded as one of `chain`.
+pub async fn update(url: &str, chain: &str, path: &Path) -> eyre::Result<usize> {
+    let registry = crate::rpc::http_client()
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let mut registry = BuilderRegistry::parse(&registry)
+        .map_err(|e| eyre::eyre!("{url} is not a builder registry: {e}"))?;
+    if registry.chain.is_some() && registry.chain() != chain {
+        eyre::bail!(
+            "{url} is a builder registry of {}, not {chain}",
+            registry.chain()
+        );
+    }
+    registry.chain = Some(chain.to_string());
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, toml::to_string(&registry)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(registry.builders.len())
+}
+
+/// Decodes `extra_data` as text, dropping non-printable bytes.
+pub fn decode_extra_data(extra_data: &Bytes) -> String {
+    String::from_utf8_lossy(extra_data)
+        .chars()
+        .filter(|c| !c.is_control() && *c != char::REPLACEMENT_CHARACTER)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Whether `address` is a known payout address of any builder.
+pub fn is_builder_address(builders: &[KnownBuilder], address: Address) -> bool {
+    builders
+        .iter()
+        .any(|b| b.payout_addresses.contains(&address))
+}
+
+/// Whether `pattern` starts `extra_data` or is a whole word in it, so a short name like `titan`
+/// doesn't match wherever it appears inside another word.
+fn matches_extra_data(extra_data: &str, pattern: &str) -> bool {
+    if pattern.is_empty() {
+        return false;
+    }
+    if extra_data.starts_with(pattern) {
+        return true;
+    }
+    let is_boundary = |c: Option<char>| c.map_or(true, |c| !c.is_alphanumeric());
+    extra_data.match_indices(pattern).any(|(start, _)| {
+        is_boundary(extra_data[..start].chars().next_back())
+            && is_boundary(extra_data[start + pattern.len()..].chars().next())
+    })
+}
+
+/// Identifies the builder, preferring the payment sender over `extra_data` since the latter is
+/// free-form. A sender that isn't a known payout address, e.g. a builder's new one, still leaves
+/// `extra_data` to identify the block.
+pub fn identify_builder(
+    builders: &[KnownBuilder],
+    extra_data: &Bytes,
+    payment_from: Option<Address>,
+) -> Option<String> {
+    if let Some(from) = payment_from {
+        if let Some(builder) = builders.iter().find(|b| b.payout_addresses.contains(&from)) {
+            return Some(builder.name.clone());
+        }
+    }
+
+    let extra_data = decode_extra_data(extra_data).to_lowercase();
+    if extra_data.is_empty() {
+        return None;
+    }
+    builders
+        .iter()
+        .find(|b| {
+            b.extra_data
+                .iter()
+                .any(|p| matches_extra_data(&extra_data, &p.to_lowercase()))
+        })
+        .map(|b| b.name.clone())
+}
+
+/// Builder submitting with `pubkey`, for blocks neither the payment nor `extra_data` identify.
+pub fn builder_by_pubkey(builders: &[KnownBuilder], pubkey: Option<&str>) -> Option<String> {
+    let pubkey = pubkey?;
+    builders
+        .iter()
+        .find(|b| b.pubkeys.iter().any(|p| p.eq_ignore_ascii_case(pubkey)))
+        .map(|b| b.name.clone())
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use ethers::providers::Middleware;
+use ethers::types::U256;
+use serde::Deserialize;
+
+use crate::builders::{self, KnownBuilder};
+use crate::rpc::EthProvider;
+
+/// Chain specific parameters, selected with `--chain`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainProfile {
+    pub name: String,
+    /// Chain id the RPC endpoint has to report, not checked if unset.
+    #[serde(default)]
+    pub chain_id: Option<u64>,
+    pub genesis_time: u64,
+    pub seconds_per_slot: u64,
+    #[serde(default = "default_slots_per_epoch")]
+    pub slots_per_epoch: u64,
+    /// Whether withdrawals credit the native balance. On Gnosis they are paid out in GNO by a
+    /// contract, so they don't show up in the balance diff.
+    #[serde(default = "default_native_withdrawals")]
+    pub native_withdrawals: bool,
+    #[serde(default)]
+    pub builders: Vec<KnownBuilder>,
+}
+
+fn default_slots_per_epoch() -> u64 {
+    32
+}
+
+fn default_native_withdrawals() -> bool {
+    true
+}
+
+impl Default for ChainProfile {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
+
+impl ChainProfile {
+    pub fn mainnet() -> Self {
+        Self {
+            name: "mainnet".to_string(),
+            chain_id: Some(1),
+            genesis_time: 1606824023,
+            seconds_per_slot: 12,
+            slots_per_epoch: 32,
+            native_withdrawals: true,
+            builders: builders::mainnet_builders(),
+        }
+    }
+
+    pub fn holesky() -> Self {
+        Self {
+            name: "holesky".to_string(),
+            chain_id: Some(17000),
+            genesis_time: 1695902400,
+            seconds_per_slot: 12,
+            slots_per_epoch: 32,
+            native_withdrawals: true,
+            builders: Vec::new(),
+        }
+    }
+
+    pub fn gnosis() -> Self {
+        Self {
+            name: "gnosis".to_string(),
+            chain_id: Some(100),
+            genesis_time: 1638993340,
+            seconds_per_slot: 5,
+            slots_per_epoch: 16,
+            native_withdrawals: false,
+            builders: Vec::new(),
+        }
+    }
+
+    /// Resolves a bundled profile by name, otherwise reads `chain` as a path to a TOML profile.
+    pub fn resolve(chain: &str) -> eyre::Result<Self> {
+        match chain {
+            "mainnet" => Ok(Self::mainnet()),
+            "holesky" => Ok(Self::holesky()),
+            "gnosis" => Ok(Self::gnosis()),
+            path => {
+                let profile = std::fs::read_to_string(Path::new(path)).map_err(|e| {
+                    eyre::eyre!("{path} is neither a known chain nor a readable profile: {e}")
+                })?;
+                Ok(toml::from_str(&profile)?)
+            }
+        }
+    }
+
+    /// Fails if the endpoint behind `provider` serves another chain than this profile.
+    pub async fn check_chain_id(&self, provider: &EthProvider) -> eyre::Result<()> {
+        let Some(expected) = self.chain_id else {
+            return Ok(());
+        };
+        let chain_id = provider.get_chainid().await?;
+        if chain_id != U256::from(expected) {
+            eyre::bail!(
+                "the RPC endpoint serves chain id {chain_id}, but --chain {} is chain id {expected}",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Timestamp of the block proposed in `slot`.
+    pub fn slot_timestamp(&self, slot: u64) -> u64 {
+        self.genesis_time + slot * self.seconds_per_slot
+    }
+
+    /// Slot of a block with `timestamp`, `None` before genesis.
+    pub fn slot_at(&self, timestamp: u64) -> Option<u64> {
+        Some(timestamp.checked_sub(self.genesis_time)? / self.seconds_per_slot)
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::file::InputFilter;
+
+/// Progress of a `file` run, written next to the output after every flush so a resumed run can
+/// skip the rows it already handled without scanning the whole output.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub input: PathBuf,
+    /// Filter of the run; rows it excludes count as handled.
+    #[serde(default)]
+    pub filter: InputFilter,
+    /// Leading input rows that are either written to the output or listed in `failed`.
+    pub input_rows: u64,
+    /// Output size in bytes at the time of the checkpoint, used to detect a modified output.
+    pub output_len: u64,
+    /// Slots within `input_rows` that failed and have to be retried.
+    pub failed: BTreeSet<u64>,
+    /// Slots by input row of rows after `input_rows` that are already in the output. Entries
+    /// complete out of order, so some of them are written before all earlier rows are done.
+    #[serde(default)]
+    pub ahead: BTreeMap<u64, u64>,
+}
+
+impl Checkpoint {
+    pub fn path(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".checkpoint");
+        path.into()
+    }
+
+    /// Loads the checkpoint for `output` if it still describes `input`, `filter` and the current
+    /// output.
+    pub fn load(input: &Path, output: &Path, filter: &InputFilter) -> eyre::Result<Option<Self>> {
+        let path = Self::path(output);
+        if !path.exists() || !output.exists() {
+            return Ok(None);
+        }
+        let checkpoint: Checkpoint = serde_json::from_slice(&std::fs::read(&path)?)?;
+        if checkpoint.input != input {
+            tracing::info!(checkpoint = %path.display(), "checkpoint is for a different input, ignoring it");
+            return Ok(None);
+        }
+        if &checkpoint.filter != filter {
+            tracing::info!(checkpoint = %path.display(), "checkpoint is for a different filter, ignoring it");
+            return Ok(None);
+        }
+        if checkpoint.output_len != std::fs::metadata(output)?.len() {
+            tracing::warn!(checkpoint = %path.display(), "output changed since the checkpoint, ignoring it");
+            return Ok(None);
+        }
+        Ok(Some(checkpoint))
+    }
+
+    pub fn save(&self, output: &Path) -> eyre::Result<()> {
+        let path = Self::path(output);
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use crate::OutputFileEntry;
+
+/// Inserts output rows into a ClickHouse table over the HTTP interface.
+///
+/// Rows are sent as `CSVWithNames`, so the table needs columns named like the output file; wei
+/// values fit `UInt256`/`Int256`. A crash between an insert and the next checkpoint inserts those
+/// rows again on resume, a `ReplacingMergeTree` ordered by `slot` absorbs that.
+#[derive(Debug, Clone)]
+pub struct ClickHouseSink {
+    client: reqwest::Client,
+    url: String,
+    table: String,
+    user: Option<String>,
+    password: Option<String>,
+}
+
+impl ClickHouseSink {
+    pub fn new(url: &str, table: String, user: Option<String>, password: Option<String>) -> Self {
+        Self {
+            client: crate::rpc::http_client(),
+            url: url.trim_end_matches('/').to_string(),
+            table,
+            user,
+            password,
+        }
+    }
+
+    pub async fn insert(&self, rows: &[OutputFileEntry]) -> eyre::Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for row in rows {
+            writer.serialize(row)?;
+        }
+        let body = writer.into_inner()?;
+
+        let mut request = self
+            .client
+            .post(format!("{}/", self.url))
+            .query(&[(
+                "query",
+                format!("INSERT INTO {} FORMAT CSVWithNames", self.table),
+            )])
+            .body(body);
+        if let Some(user) = &self.user {
+            request = request.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = &self.password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let error = response.text().await.unwrap_or_default();
+            eyre::bail!("clickhouse insert failed with {status}: {}", error.trim());
+        }
+        tracing::debug!(rows = rows.len(), table = %self.table, "inserted into clickhouse");
+        Ok(())
+    }
+}
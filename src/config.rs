@@ -0,0 +1,199 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::Deserialize;
+
+use crate::rpc::{parse_header, BalanceSource};
+use crate::rules::Rule;
+use crate::trace::{TraceBackend, TransferAction};
+use crate::units::ValueUnit;
+use crate::{parse_u256_decimal, Cli, Command};
+
+/// Settings read from `--config`. Anything given on the command line or through the environment
+/// takes precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub chain: Option<String>,
+    pub eth_rpc_url: Option<String>,
+    pub beacon_url: Option<String>,
+    pub usd_prices: Option<String>,
+    pub rpc_parallel: Option<usize>,
+    pub proxy: Option<String>,
+    pub rpc_headers: Option<Vec<String>>,
+    pub rpc_timeout: Option<u64>,
+    pub rpc_retries: Option<u32>,
+    pub trace_timeout: Option<u64>,
+    pub trace_backend: Option<TraceBackend>,
+    pub balance_source: Option<BalanceSource>,
+    pub light_blocks: Option<bool>,
+    pub transfer_actions: Option<Vec<TransferAction>>,
+    pub cache_dir: Option<PathBuf>,
+    pub results_cache_dir: Option<PathBuf>,
+    pub archive_dir: Option<PathBuf>,
+    pub value_unit: Option<ValueUnit>,
+    pub recipient_registry: Option<PathBuf>,
+    pub labels: Option<PathBuf>,
+    pub entities: Option<PathBuf>,
+    pub builder_registry: Option<PathBuf>,
+    pub builder_overrides: Option<PathBuf>,
+    pub metrics_addr: Option<SocketAddr>,
+    pub alert_webhook: Option<String>,
+    pub clickhouse_url: Option<String>,
+    pub clickhouse_table: Option<String>,
+    /// Decimal string like the tolerance.
+    pub alert_min_shortfall_wei: Option<String>,
+    pub log_json: Option<bool>,
+    #[serde(default)]
+    pub tolerance: ToleranceConfig,
+    /// Classification rules, tried in order before the built-in heuristics.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToleranceConfig {
+    /// Decimal string, wei values don't fit into TOML integers.
+    pub wei: Option<String>,
+    pub pct: Option<f64>,
+}
+
+fn is_unset(matches: &ArgMatches, id: &str) -> bool {
+    !matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+impl Config {
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let config = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&config)?)
+    }
+
+    /// Fills in every option of `cli` that wasn't set explicitly.
+    pub fn apply(self, cli: &mut Cli, matches: &ArgMatches) -> eyre::Result<()> {
+        if let (Some(chain), true) = (self.chain, is_unset(matches, "chain")) {
+            cli.chain = chain;
+        }
+        if let (Some(url), true) = (self.eth_rpc_url, is_unset(matches, "eth_rpc_url")) {
+            cli.eth_rpc_url = Some(url);
+        }
+        if let (Some(url), true) = (self.beacon_url, is_unset(matches, "beacon_url")) {
+            cli.beacon_url = Some(url);
+        }
+        if let (Some(source), true) = (self.usd_prices, is_unset(matches, "usd_prices")) {
+            cli.usd_prices = Some(source);
+        }
+        if let (Some(parallel), true) = (self.rpc_parallel, is_unset(matches, "rpc_parallel")) {
+            cli.rpc_parallel = parallel;
+        }
+        if let (Some(proxy), true) = (self.proxy, is_unset(matches, "proxy")) {
+            cli.proxy = Some(proxy);
+        }
+        if let (Some(headers), true) = (self.rpc_headers, is_unset(matches, "rpc_headers")) {
+            cli.rpc_headers = headers
+                .iter()
+                .map(|header| parse_header(header))
+                .collect::<Result<_, _>>()
+                .map_err(|e| eyre::eyre!(e))?;
+        }
+        if let (Some(timeout), true) = (self.rpc_timeout, is_unset(matches, "rpc_timeout")) {
+            cli.rpc_timeout = timeout;
+        }
+        if let (Some(retries), true) = (self.rpc_retries, is_unset(matches, "rpc_retries")) {
+            cli.rpc_retries = retries;
+        }
+        if let (Some(timeout), true) = (self.trace_timeout, is_unset(matches, "trace_timeout")) {
+            cli.trace_timeout = timeout;
+        }
+        if let (Some(backend), true) = (self.trace_backend, is_unset(matches, "trace_backend")) {
+            cli.trace_backend = backend;
+        }
+        if let (Some(source), true) = (self.balance_source, is_unset(matches, "balance_source")) {
+            cli.balance_source = source;
+        }
+        if let (Some(light), true) = (self.light_blocks, is_unset(matches, "light_blocks")) {
+            cli.light_blocks = light;
+        }
+        if let (Some(actions), true) =
+            (self.transfer_actions, is_unset(matches, "transfer_actions"))
+        {
+            cli.transfer_actions = actions;
+        }
+        if let (Some(dir), true) = (self.cache_dir, is_unset(matches, "cache_dir")) {
+            cli.cache_dir = Some(dir);
+        }
+        if let (Some(dir), true) = (
+            self.results_cache_dir,
+            is_unset(matches, "results_cache_dir"),
+        ) {
+            cli.results_cache_dir = Some(dir);
+        }
+        if let (Some(dir), true) = (self.archive_dir, is_unset(matches, "archive_dir")) {
+            cli.archive_dir = Some(dir);
+        }
+        if let (Some(unit), true) = (self.value_unit, is_unset(matches, "value_unit")) {
+            cli.value_unit = unit;
+        }
+        if let (Some(path), true) = (
+            self.recipient_registry,
+            is_unset(matches, "recipient_registry"),
+        ) {
+            cli.recipient_registry = Some(path);
+        }
+        if let (Some(path), true) = (self.labels, is_unset(matches, "labels")) {
+            cli.labels = Some(path);
+        }
+        if let (Some(path), true) = (self.entities, is_unset(matches, "entities")) {
+            cli.entities = Some(path);
+        }
+        if let (Some(path), true) = (self.builder_registry, is_unset(matches, "builder_registry")) {
+            cli.builder_registry = Some(path);
+        }
+        if let (Some(path), true) = (
+            self.builder_overrides,
+            is_unset(matches, "builder_overrides"),
+        ) {
+            cli.builder_overrides = Some(path);
+        }
+        if let (Some(url), true) = (self.alert_webhook, is_unset(matches, "alert_webhook")) {
+            cli.alert_webhook = Some(url);
+        }
+        if let (Some(wei), true) = (
+            self.alert_min_shortfall_wei,
+            is_unset(matches, "alert_min_shortfall_wei"),
+        ) {
+            cli.alert_min_shortfall_wei = parse_u256_decimal(&wei).map_err(|e| eyre::eyre!(e))?;
+        }
+        if let (Some(url), true) = (self.clickhouse_url, is_unset(matches, "clickhouse_url")) {
+            cli.clickhouse_url = Some(url);
+        }
+        if let (Some(table), true) = (self.clickhouse_table, is_unset(matches, "clickhouse_table"))
+        {
+            cli.clickhouse_table = table;
+        }
+        if let (Some(addr), true) = (self.metrics_addr, is_unset(matches, "metrics_addr")) {
+            cli.metrics_addr = Some(addr);
+        }
+        if let (Some(json), true) = (self.log_json, is_unset(matches, "log_json")) {
+            cli.log_json = json;
+        }
+        cli.rules = self.rules;
+
+        if let (Some(Command::File { tolerance, .. }), Some(matches)) =
+            (&mut cli.command, matches.subcommand_matches("file"))
+        {
+            if let (Some(wei), true) = (self.tolerance.wei, is_unset(matches, "wei")) {
+                tolerance.wei = parse_u256_decimal(&wei).map_err(|e| eyre::eyre!(e))?;
+            }
+            if let (Some(pct), true) = (self.tolerance.pct, is_unset(matches, "pct")) {
+                tolerance.pct = pct;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use ethers::abi::{self, ParamType, Token};
+use ethers::prelude::*;
+
+/// Code of an EOA that delegated with an EIP-7702 authorization, followed by the address whose
+/// code it runs.
+const DELEGATION_PREFIX: [u8; 3] = [0xef, 0x01, 0x00];
+
+/// Address `code` delegates to when it is an EIP-7702 delegation designator.
+pub fn delegation_target(code: &[u8]) -> Option<Address> {
+    match code.strip_prefix(&DELEGATION_PREFIX) {
+        Some(target) if target.len() == 20 => Some(Address::from_slice(target)),
+        _ => None,
+    }
+}
+
+/// Whether `code` belongs to a contract, a delegated EOA still being an EOA: it signs its own txs
+/// and can pay from its balance like any other.
+pub fn is_contract_code(code: &[u8]) -> bool {
+    !code.is_empty() && delegation_target(code).is_none()
+}
+
+/// Value sent to `recipient` by the calls in the calldata of a tx an EOA sends to itself, for the
+/// batch interfaces of the common delegation targets. Without delegated code such a tx runs
+/// nothing, so it only pays anyone when the sender executes its EIP-7702 delegation. Only used
+/// when the block can't be traced, the traces are authoritative otherwise.
+pub fn self_call_value_to(tx: &Transaction, recipient: Address) -> Option<U256> {
+    if tx.to != Some(tx.from) {
+        return None;
+    }
+    let calls = decode_calls(&tx.input)?;
+    let value = calls
+        .iter()
+        .filter(|(to, _)| *to == recipient)
+        .fold(U256::zero(), |acc, (_, value)| acc + *value);
+    (!value.is_zero()).then_some(value)
+}
+
+/// `(to, value)` of the calls of an `execute` or `executeBatch` calldata.
+fn decode_calls(input: &[u8]) -> Option<Vec<(Address, U256)>> {
+    let call = ParamType::Tuple(vec![
+        ParamType::Address,
+        ParamType::Uint(256),
+        ParamType::Bytes,
+    ]);
+    let calls = ParamType::Array(Box::new(call));
+    let (selector, args) = (input.get(..4)?, input.get(4..)?);
+    let tokens = if selector == ethers::utils::id("execute(bytes32,bytes)") {
+        // ERC-7821, the execution data is the calls optionally followed by op data
+        let tokens = abi::decode(&[ParamType::FixedBytes(32), ParamType::Bytes], args).ok()?;
+        let execution_data = tokens.into_iter().nth(1)?.into_bytes()?;
+        abi::decode(&[calls], &execution_data).ok()?
+    } else if selector == ethers::utils::id("execute((address,uint256,bytes)[])")
+        || selector == ethers::utils::id("executeBatch((address,uint256,bytes)[])")
+    {
+        abi::decode(&[calls], args).ok()?
+    } else if selector == ethers::utils::id("execute(address,uint256,bytes)") {
+        vec![Token::Array(vec![Token::Tuple(
+            abi::decode(
+                &[ParamType::Address, ParamType::Uint(256), ParamType::Bytes],
+                args,
+            )
+            .ok()?,
+        )])]
+    } else if selector == ethers::utils::id("executeBatch(address[],uint256[],bytes[])") {
+        // SimpleAccount, an empty value array for calls without value
+        let mut tokens = abi::decode(
+            &[
+                ParamType::Array(Box::new(ParamType::Address)),
+                ParamType::Array(Box::new(ParamType::Uint(256))),
+                ParamType::Array(Box::new(ParamType::Bytes)),
+            ],
+            args,
+        )
+        .ok()?
+        .into_iter();
+        let targets = tokens.next()?.into_array()?;
+        let values = tokens.next()?.into_array()?;
+        return targets
+            .into_iter()
+            .enumerate()
+            .map(|(i, to)| {
+                let value = match values.get(i) {
+                    Some(value) => value.clone().into_uint()?,
+                    None => U256::zero(),
+                };
+                Some((to.into_address()?, value))
+            })
+            .collect();
+    } else {
+        return None;
+    };
+    tokens
+        .into_iter()
+        .next()?
+        .into_array()?
+        .into_iter()
+        .map(|call| {
+            let mut fields = call.into_tuple()?.into_iter();
+            Some((fields.next()?.into_address()?, fields.next()?.into_uint()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_delegation_designator() {
+        let target = Address::repeat_byte(0x11);
+        let mut code = DELEGATION_PREFIX.to_vec();
+        code.extend_from_slice(target.as_bytes());
+        assert_eq!(delegation_target(&code), Some(target));
+        assert!(!is_contract_code(&code));
+        assert!(is_contract_code(&[0x60, 0x80]));
+        assert!(!is_contract_code(&[]));
+    }
+
+    #[test]
+    fn sums_batched_calls_to_recipient() {
+        let sender = Address::repeat_byte(0x22);
+        let recipient = Address::repeat_byte(0x33);
+        let call = |to: Address, value: u64| {
+            Token::Tuple(vec![
+                Token::Address(to),
+                Token::Uint(U256::from(value)),
+                Token::Bytes(Vec::new()),
+            ])
+        };
+        let mut input = ethers::utils::id("executeBatch((address,uint256,bytes)[])").to_vec();
+        input.extend(abi::encode(&[Token::Array(vec![
+            call(recipient, 3),
+            call(Address::repeat_byte(0x44), 5),
+            call(recipient, 4),
+        ])]));
+        let tx = Transaction {
+            from: sender,
+            to: Some(sender),
+            input: input.into(),
+            ..Default::default()
+        };
+        assert_eq!(self_call_value_to(&tx, recipient), Some(U256::from(7)));
+        assert_eq!(self_call_value_to(&tx, Address::repeat_byte(0x55)), None);
+
+        let to_other = Transaction {
+            to: Some(recipient),
+            ..tx
+        };
+        assert_eq!(self_call_value_to(&to_other, recipient), None);
+    }
+}
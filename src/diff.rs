@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::schema;
+use crate::streams::{open_input, Dialect};
+
+/// Rows of an output file by slot, the last row of a slot winning as `finalize` keeps it.
+fn read_rows(path: &Path) -> eyre::Result<(csv::StringRecord, BTreeMap<u64, csv::StringRecord>)> {
+    let mut reader = Dialect::for_path(path).reader(open_input(path)?);
+    let header = reader.headers()?.clone();
+    let slot_index = header
+        .iter()
+        .position(|column| column == "slot")
+        .ok_or_else(|| eyre::eyre!("{} has no slot column", path.display()))?;
+    let mut rows = BTreeMap::new();
+    for record in reader.into_records() {
+        let record = record?;
+        rows.insert(record[slot_index].parse()?, record);
+    }
+    Ok((header, rows))
+}
+
+/// Compares two output files slot by slot and prints every column that differs, e.g. between
+/// runs of different versions or RPC providers. Only columns both files have are compared,
+/// restricted to `columns` when given. Fails when any slot differs.
+pub fn diff(left: &Path, right: &Path, columns: &[String]) -> eyre::Result<()> {
+    let (left_unit, right_unit) = (schema::read_unit(left)?, schema::read_unit(right)?);
+    if left_unit != right_unit {
+        eyre::bail!("values of the files are in {left_unit} and {right_unit}, migrate one first");
+    }
+    let (left_header, left_rows) = read_rows(left)?;
+    let (right_header, right_rows) = read_rows(right)?;
+    for column in columns {
+        if !left_header.iter().any(|c| c == column) || !right_header.iter().any(|c| c == column) {
+            eyre::bail!("column {column} is not in both files");
+        }
+    }
+    // (column, index in left, index in right)
+    let compared: Vec<(&str, usize, usize)> = left_header
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| columns.is_empty() || columns.iter().any(|c| c == column))
+        .filter_map(|(i, column)| {
+            let j = right_header.iter().position(|c| c == column)?;
+            Some((column, i, j))
+        })
+        .collect();
+    for column in left_header.iter().chain(right_header.iter()) {
+        if columns.is_empty() && !compared.iter().any(|(c, ..)| *c == column) {
+            tracing::warn!(column, "column is only in one of the files, not compared");
+        }
+    }
+
+    let mut differing = 0;
+    for (slot, left_row) in &left_rows {
+        let Some(right_row) = right_rows.get(slot) else {
+            continue;
+        };
+        let diffs: Vec<_> = compared
+            .iter()
+            .filter(|(_, i, j)| left_row.get(*i) != right_row.get(*j))
+            .collect();
+        if diffs.is_empty() {
+            continue;
+        }
+        differing += 1;
+        println!("slot {slot}:");
+        for (column, i, j) in diffs {
+            println!(
+                "  {column}: {} -> {}",
+                left_row.get(*i).unwrap_or_default(),
+                right_row.get(*j).unwrap_or_default()
+            );
+        }
+    }
+    let only_left = left_rows
+        .keys()
+        .filter(|slot| !right_rows.contains_key(slot))
+        .count();
+    let only_right = right_rows
+        .keys()
+        .filter(|slot| !left_rows.contains_key(slot))
+        .count();
+
+    println!("compared:   {}", left_rows.len() - only_left);
+    println!("differing:  {differing}");
+    println!("only left:  {only_left}");
+    println!("only right: {only_right}");
+    if differing + only_left + only_right > 0 {
+        eyre::bail!("{differing} slots differ, {only_left} are only in the left file and {only_right} only in the right");
+    }
+    Ok(())
+}
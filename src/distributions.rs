@@ -0,0 +1,172 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use ethers::prelude::*;
+use futures::stream::{StreamExt, TryStreamExt};
+use serde::Serialize;
+
+use crate::file::read_entries;
+use crate::recipients::RecipientRegistry;
+use crate::rpc::EthProvider;
+use crate::streams::{create_output, DialectArgs};
+use crate::trace::{self, TransferActions};
+use crate::{format_eth, serialize_u256_to_decimal};
+
+/// Blocks per `trace_filter` request, nodes refuse or time out on much larger ranges.
+const FILTER_CHUNK: u64 = 10_000;
+
+/// Row of the `distributions` output, one per transfer out of a distribution contract.
+#[derive(Debug, Serialize)]
+struct PayoutEntry {
+    pool: Address,
+    /// Contract the payout was sent from, the pool itself or one of the followed contracts.
+    from: Address,
+    block_number: u64,
+    tx_hash: H256,
+    operator: Address,
+    #[serde(serialize_with = "serialize_u256_to_decimal")]
+    value: U256,
+    /// Blocks paying the pool within the window before the payout.
+    pool_blocks: usize,
+    /// Sum of the bids of those blocks.
+    #[serde(serialize_with = "serialize_u256_to_decimal")]
+    pool_income: U256,
+}
+
+/// Traces payouts of fee distribution contracts (smoothing pools, club pools) to operators.
+///
+/// Rows of `input` whose fee recipient has one of `kinds` in the registry are pool income. Every
+/// transfer out of the pool, or out of a `follow` contract it forwards to (e.g. a merkle
+/// distributor operators claim from), up to `window` blocks after the last pool block is a payout.
+/// Transfers between the pool and followed contracts are not payouts.
+#[allow(clippy::too_many_arguments)]
+pub async fn distributions(
+    provider: &EthProvider,
+    registry: &RecipientRegistry,
+    kinds: &[String],
+    follow: &[Address],
+    window: u64,
+    input: &Path,
+    output: &Path,
+    dialect: &DialectArgs,
+    rpc_parallel: usize,
+) -> eyre::Result<()> {
+    let mut pools: BTreeMap<Address, Vec<(u64, U256)>> = BTreeMap::new();
+    for row in read_entries(input, &dialect.input(input)?)? {
+        let row = row?;
+        let kind = registry.kind(row.proposer_fee_recipient);
+        if kinds.iter().any(|k| k == kind) {
+            pools
+                .entry(row.proposer_fee_recipient)
+                .or_default()
+                .push((row.block_number, row.value));
+        }
+    }
+    if pools.is_empty() {
+        eyre::bail!(
+            "no block of {} paid a fee recipient of kind {}",
+            input.display(),
+            kinds.join(",")
+        );
+    }
+
+    let latest = provider.get_block_number().await?.as_u64();
+    // traces of blocks that can still be reorged are fetched again by the next run
+    let finalized = provider
+        .get_block(BlockNumber::Finalized)
+        .await?
+        .and_then(|block| block.number)
+        .map_or(0, |number| number.as_u64());
+    let uncached = Provider::new(provider.as_ref().uncached());
+    let mut payouts = Vec::new();
+    for (pool, blocks) in &mut pools {
+        blocks.sort();
+        let from_block = blocks.first().map_or(0, |(number, _)| *number);
+        let to_block = blocks
+            .last()
+            .map_or(0, |(number, _)| *number)
+            .saturating_add(window)
+            .min(latest);
+        let mut senders = vec![*pool];
+        senders.extend(follow.iter().filter(|address| *address != pool));
+        tracing::info!(%pool, from_block, to_block, "tracing payouts");
+
+        let chunks = (from_block..=to_block)
+            .step_by(FILTER_CHUNK as usize)
+            .map(|start| (start, (start + FILTER_CHUNK - 1).min(to_block)));
+        let transfers: Vec<Vec<trace::TransferData>> = futures::stream::iter(chunks)
+            .map(|(start, end)| {
+                let filter = TraceFilter::default()
+                    .from_block(start)
+                    .to_block(end)
+                    .from_address(senders.clone());
+                let provider = if end <= finalized {
+                    provider
+                } else {
+                    &uncached
+                };
+                async move {
+                    let traces = provider.trace_filter(filter).await?;
+                    Ok::<_, eyre::Report>(trace::extract_transfers(
+                        &traces,
+                        &TransferActions::default(),
+                    ))
+                }
+            })
+            .buffered(rpc_parallel.max(1))
+            .try_collect()
+            .await?;
+
+        for transfer in transfers.into_iter().flatten() {
+            if senders.contains(&transfer.to) {
+                continue;
+            }
+            let window_start = transfer.block_number.saturating_sub(window);
+            let income: Vec<U256> = blocks
+                .iter()
+                .filter(|(number, _)| (window_start..transfer.block_number).contains(number))
+                .map(|(_, value)| *value)
+                .collect();
+            payouts.push(PayoutEntry {
+                pool: *pool,
+                from: transfer.from,
+                block_number: transfer.block_number,
+                tx_hash: transfer.tx_hash,
+                operator: transfer.to,
+                value: transfer.value,
+                pool_blocks: income.len(),
+                pool_income: income.iter().fold(U256::zero(), |acc, value| acc + *value),
+            });
+        }
+    }
+
+    let dialect = dialect.output(output);
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(dialect.delimiter)
+        .has_headers(dialect.has_headers)
+        .from_writer(create_output(output)?);
+    let mut operators: HashMap<Address, U256> = HashMap::new();
+    for payout in &payouts {
+        writer.serialize(payout)?;
+        *operators.entry(payout.operator).or_default() += payout.value;
+    }
+    drop(writer.into_inner().map_err(|e| e.into_error())?);
+
+    let income = pools
+        .values()
+        .flatten()
+        .fold(U256::zero(), |acc, (_, value)| acc + *value);
+    let paid = operators
+        .values()
+        .fold(U256::zero(), |acc, value| acc + *value);
+    println!("pools:              {}", pools.len());
+    println!(
+        "pool blocks:        {}",
+        pools.values().map(Vec::len).sum::<usize>()
+    );
+    println!("pool income:        {} ETH", format_eth(income));
+    println!("payouts:            {}", payouts.len());
+    println!("operators:          {}", operators.len());
+    println!("paid out:           {} ETH", format_eth(paid));
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use ethers::prelude::*;
+
+use crate::rpc::EthProvider;
+
+/// Primary ENS names of addresses, looked up once per address for human readable output.
+#[derive(Debug)]
+pub struct EnsNames {
+    provider: EthProvider,
+    names: Mutex<HashMap<Address, Option<String>>>,
+}
+
+impl EnsNames {
+    /// ENS names are only resolved on mainnet, where the registry lives.
+    pub async fn new(provider: EthProvider) -> eyre::Result<Self> {
+        if provider.get_chainid().await? != U256::one() {
+            eyre::bail!("ENS names can only be resolved against a mainnet endpoint");
+        }
+        Ok(Self {
+            provider,
+            names: Default::default(),
+        })
+    }
+
+    /// Reverse record of `address` if it also resolves back to `address`.
+    pub async fn name(&self, address: Address) -> Option<String> {
+        if let Some(name) = self.names.lock().unwrap().get(&address) {
+            return name.clone();
+        }
+        let name = match self.provider.lookup_address(address).await {
+            Ok(name) => Some(name),
+            Err(e) => {
+                // addresses without a reverse record end up here as well
+                tracing::debug!(%address, error = %e, "no ENS name");
+                None
+            }
+        };
+        self.names.lock().unwrap().insert(address, name.clone());
+        name
+    }
+}
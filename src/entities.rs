@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use ethers::types::Address;
+use serde::Deserialize;
+
+/// Staking entities whose validators all pay the same fee recipient contract, so their blocks are
+/// attributed without a validator dataset.
+const KNOWN_ENTITIES: &[(&str, &str)] = &[
+    // Lido execution layer rewards vault
+    ("0x388C818CA8B9251b393131C08a736A67ccB19297", "lido"),
+    // Rocket Pool smoothing pool, minipools outside of it use their own fee recipients
+    ("0xd4E96eF8eee8678dBFf4d535E033Ed1a4F7605b7", "rocket_pool"),
+];
+
+#[derive(Debug, Deserialize)]
+struct EntityRow {
+    #[serde(default)]
+    validator_index: Option<u64>,
+    #[serde(default)]
+    pubkey: Option<String>,
+    #[serde(default)]
+    fee_recipient: Option<Address>,
+    entity: String,
+}
+
+/// Staking entity (Lido, Coinbase, Kiln, solo stakers, ...) running each proposer, bundled for
+/// entities with a dedicated fee recipient and optionally extended from a csv file.
+#[derive(Debug, Clone)]
+pub struct Entities {
+    by_index: HashMap<u64, String>,
+    by_pubkey: HashMap<String, String>,
+    by_fee_recipient: HashMap<Address, String>,
+}
+
+impl Default for Entities {
+    fn default() -> Self {
+        Self {
+            by_index: HashMap::new(),
+            by_pubkey: HashMap::new(),
+            by_fee_recipient: KNOWN_ENTITIES
+                .iter()
+                .map(|(address, entity)| (address.parse().unwrap(), entity.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl Entities {
+    /// Bundled entities with rows of `path` (columns `validator_index,pubkey,fee_recipient,entity`,
+    /// any but one of the first three may be empty) added on top.
+    pub fn load(path: Option<&Path>) -> eyre::Result<Self> {
+        let mut entities = Self::default();
+        let Some(path) = path else {
+            return Ok(entities);
+        };
+        for (row, entry) in csv::Reader::from_path(path)?
+            .into_deserialize::<EntityRow>()
+            .enumerate()
+        {
+            let entry = entry?;
+            if entry.validator_index.is_none()
+                && entry.pubkey.is_none()
+                && entry.fee_recipient.is_none()
+            {
+                eyre::bail!(
+                    "{} row {}: needs a validator_index, pubkey or fee_recipient",
+                    path.display(),
+                    row + 1
+                );
+            }
+            if let Some(index) = entry.validator_index {
+                entities.by_index.insert(index, entry.entity.clone());
+            }
+            if let Some(pubkey) = entry.pubkey {
+                entities
+                    .by_pubkey
+                    .insert(pubkey.to_lowercase(), entry.entity.clone());
+            }
+            if let Some(fee_recipient) = entry.fee_recipient {
+                entities
+                    .by_fee_recipient
+                    .insert(fee_recipient, entry.entity);
+            }
+        }
+        Ok(entities)
+    }
+
+    /// Entity of the proposer, looked up by validator index, then pubkey, then fee recipient.
+    pub fn get(
+        &self,
+        index: Option<u64>,
+        pubkey: Option<&str>,
+        fee_recipient: Option<Address>,
+    ) -> Option<&str> {
+        index
+            .and_then(|index| self.by_index.get(&index))
+            .or_else(|| pubkey.and_then(|pubkey| self.by_pubkey.get(&pubkey.to_lowercase())))
+            .or_else(|| fee_recipient.and_then(|address| self.by_fee_recipient.get(&address)))
+            .map(String::as_str)
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use ethers::types::Address;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct ExpectedRow {
+    #[serde(default)]
+    validator_index: Option<u64>,
+    #[serde(default)]
+    pubkey: Option<String>,
+    fee_recipient: Address,
+}
+
+/// Fee recipient each validator is expected to be paid at, e.g. by a staking operator.
+#[derive(Debug, Clone, Default)]
+pub struct ExpectedRecipients {
+    by_index: HashMap<u64, Address>,
+    by_pubkey: HashMap<String, Address>,
+}
+
+impl ExpectedRecipients {
+    /// Reads a csv file with `validator_index,pubkey,fee_recipient` columns, either of the first
+    /// two may be empty.
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let mut expected = Self::default();
+        for (row, entry) in csv::Reader::from_path(path)?
+            .into_deserialize::<ExpectedRow>()
+            .enumerate()
+        {
+            let entry = entry?;
+            match (entry.validator_index, entry.pubkey) {
+                (None, None) => eyre::bail!(
+                    "{} row {}: needs a validator_index or a pubkey",
+                    path.display(),
+                    row + 1
+                ),
+                (index, pubkey) => {
+                    if let Some(index) = index {
+                        expected.by_index.insert(index, entry.fee_recipient);
+                    }
+                    if let Some(pubkey) = pubkey {
+                        expected
+                            .by_pubkey
+                            .insert(pubkey.to_lowercase(), entry.fee_recipient);
+                    }
+                }
+            }
+        }
+        Ok(expected)
+    }
+
+    /// Expected fee recipient of the validator, looked up by index first.
+    pub fn get(&self, index: Option<u64>, pubkey: Option<&str>) -> Option<Address> {
+        index
+            .and_then(|index| self.by_index.get(&index))
+            .or_else(|| pubkey.and_then(|pubkey| self.by_pubkey.get(&pubkey.to_lowercase())))
+            .copied()
+    }
+}
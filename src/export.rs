@@ -0,0 +1,418 @@
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow::array::{
+    ArrayRef, BooleanBuilder, Decimal256Builder, Float64Builder, StringBuilder, UInt64Builder,
+};
+use arrow::datatypes::{i256, DataType, Field, Schema, SchemaRef, DECIMAL256_MAX_PRECISION};
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+
+use crate::schema;
+use crate::streams::{open_input, Dialect};
+use crate::units;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Arrow IPC file (Feather v2), readable by Polars and pyarrow.
+    Arrow,
+    /// Newline-delimited JSON for BigQuery, with the table schema written next to it as
+    /// `<output>.schema.json`.
+    Bigquery,
+}
+
+/// Wei amounts, exported as 256 bit decimals instead of strings.
+const WEI_COLUMNS: &[&str] = &[
+    "bid_value",
+    "balance_diff",
+    "balance_diff_execution",
+    "observed_payment",
+    "delta_wei",
+    "overpaid_by",
+    "payment_value",
+    "payment_tx_fee",
+    "payment_tx_priority_fee",
+    "payment_net_value",
+    "withdrawals_value",
+    "transfers_in_value",
+    "transfers_out_value",
+    "unexplained_diff",
+    "base_fee_burned",
+    "blob_fee_burned",
+    "total_priority_fees",
+    "builder_block_value",
+    "builder_margin",
+    "relay_value",
+    "best_bid_value",
+    "bid_margin",
+];
+
+const INTEGER_COLUMNS: &[&str] = &[
+    "slot",
+    "block_number",
+    "withdrawals",
+    "transfers",
+    "transfers_in",
+    "transfers_out",
+    "gas_used",
+    "gas_limit",
+    "tx_count",
+    "tx_count_legacy",
+    "tx_count_eip2930",
+    "tx_count_eip1559",
+    "tx_count_eip4844",
+    "tx_count_eip7702",
+    "payment_tx_index",
+    "blob_gas_used",
+    "timestamp",
+    "proposer_index",
+];
+
+/// Also the signed integers, the integer columns are unsigned.
+const FLOAT_COLUMNS: &[&str] = &[
+    "payment_ratio",
+    "block_fullness_pct",
+    "bid_value_usd",
+    "payment_usd",
+    "winning_bid_ms_into_slot",
+];
+
+const BOOL_COLUMNS: &[&str] = &[
+    "payment_reverted",
+    "underpaid",
+    "payment_tx_zero_priority_fee",
+    "relay_value_mismatch",
+    "registration_mismatch",
+    "wrong_recipient",
+    "is_contract",
+    "vertically_integrated",
+    "block_hash_match",
+    "trace_data",
+];
+
+/// Rows per record batch.
+const BATCH_ROWS: usize = 65536;
+
+fn data_type(column: &str) -> DataType {
+    if WEI_COLUMNS.contains(&column) {
+        DataType::Decimal256(DECIMAL256_MAX_PRECISION, 0)
+    } else if INTEGER_COLUMNS.contains(&column) {
+        DataType::UInt64
+    } else if FLOAT_COLUMNS.contains(&column) {
+        DataType::Float64
+    } else if BOOL_COLUMNS.contains(&column) {
+        DataType::Boolean
+    } else {
+        DataType::Utf8
+    }
+}
+
+enum ColumnBuilder {
+    Wei(Decimal256Builder),
+    Integer(UInt64Builder),
+    Float(Float64Builder),
+    Bool(BooleanBuilder),
+    Text(StringBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(data_type: &DataType) -> eyre::Result<Self> {
+        Ok(match data_type {
+            DataType::Decimal256(precision, scale) => ColumnBuilder::Wei(
+                Decimal256Builder::new().with_precision_and_scale(*precision, *scale)?,
+            ),
+            DataType::UInt64 => ColumnBuilder::Integer(UInt64Builder::new()),
+            DataType::Float64 => ColumnBuilder::Float(Float64Builder::new()),
+            DataType::Boolean => ColumnBuilder::Bool(BooleanBuilder::new()),
+            _ => ColumnBuilder::Text(StringBuilder::new()),
+        })
+    }
+
+    /// Appends a csv field, empty fields are nulls.
+    fn append(&mut self, column: &str, value: &str) -> eyre::Result<()> {
+        let invalid = || eyre::eyre!("invalid {column} value {value:?}");
+        match self {
+            ColumnBuilder::Wei(builder) if value.is_empty() => builder.append_null(),
+            ColumnBuilder::Wei(builder) => {
+                let wei = units::get().parse_i256(value).map_err(|_| invalid())?;
+                builder.append_value(i256::from_string(&wei.to_string()).ok_or_else(invalid)?)
+            }
+            ColumnBuilder::Integer(builder) if value.is_empty() => builder.append_null(),
+            ColumnBuilder::Integer(builder) => {
+                builder.append_value(value.parse().map_err(|_| invalid())?)
+            }
+            ColumnBuilder::Float(builder) if value.is_empty() => builder.append_null(),
+            ColumnBuilder::Float(builder) => {
+                builder.append_value(value.parse().map_err(|_| invalid())?)
+            }
+            ColumnBuilder::Bool(builder) if value.is_empty() => builder.append_null(),
+            ColumnBuilder::Bool(builder) => {
+                builder.append_value(value.parse().map_err(|_| invalid())?)
+            }
+            ColumnBuilder::Text(builder) if value.is_empty() => builder.append_null(),
+            ColumnBuilder::Text(builder) => builder.append_value(value),
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Wei(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Integer(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Float(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Bool(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Text(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+/// Converts an output file to `format` with typed columns. Value columns are exported in wei
+/// whatever unit the output uses.
+pub fn export(input: &Path, output: &Path, format: ExportFormat) -> eyre::Result<()> {
+    schema::check_unit(input)?;
+    match format {
+        ExportFormat::Arrow => export_arrow(input, output),
+        ExportFormat::Bigquery => export_bigquery(input, output),
+    }
+}
+
+/// Reads an output file as record batches of typed columns.
+pub struct BatchReader {
+    reader: csv::Reader<Box<dyn std::io::Read + Send>>,
+    columns: Vec<String>,
+    schema: SchemaRef,
+    builders: Vec<ColumnBuilder>,
+    record: csv::StringRecord,
+}
+
+impl BatchReader {
+    pub fn open(input: &Path) -> eyre::Result<Self> {
+        schema::check_unit(input)?;
+        let mut reader = Dialect::for_path(input).reader(open_input(input)?);
+        let columns: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+        let schema = Arc::new(Schema::new(
+            columns
+                .iter()
+                .map(|column| Field::new(column, data_type(column), true))
+                .collect::<Vec<_>>(),
+        ));
+        let builders = schema
+            .fields()
+            .iter()
+            .map(|field| ColumnBuilder::new(field.data_type()))
+            .collect::<eyre::Result<Vec<_>>>()?;
+        Ok(Self {
+            reader,
+            columns,
+            schema,
+            builders,
+            record: csv::StringRecord::new(),
+        })
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Next batch of up to [BATCH_ROWS] rows, `None` at the end of the file.
+    pub fn next_batch(&mut self) -> eyre::Result<Option<RecordBatch>> {
+        let mut rows = 0;
+        while rows < BATCH_ROWS && self.reader.read_record(&mut self.record)? {
+            for ((builder, column), value) in self
+                .builders
+                .iter_mut()
+                .zip(&self.columns)
+                .zip(&self.record)
+            {
+                builder.append(column, value)?;
+            }
+            rows += 1;
+        }
+        if rows == 0 {
+            return Ok(None);
+        }
+        let arrays = self
+            .builders
+            .iter_mut()
+            .map(ColumnBuilder::finish)
+            .collect();
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), arrays)?))
+    }
+}
+
+fn export_arrow(input: &Path, output: &Path) -> eyre::Result<()> {
+    let mut reader = BatchReader::open(input)?;
+    let mut writer = FileWriter::try_new(std::fs::File::create(output)?, &reader.schema())?;
+    let mut rows = 0;
+    while let Some(batch) = reader.next_batch()? {
+        rows += batch.num_rows();
+        writer.write(&batch)?;
+    }
+    writer.finish()?;
+    tracing::info!(rows, "exported {}", output.display());
+    Ok(())
+}
+
+/// BigQuery type of an output column. Wei amounts are `BIGNUMERIC`, whose 38 integer digits are
+/// far more than any amount of ether in existence.
+fn bigquery_type(column: &str) -> &'static str {
+    match data_type(column) {
+        DataType::Decimal256(..) => "BIGNUMERIC",
+        DataType::UInt64 => "INT64",
+        DataType::Float64 => "FLOAT64",
+        DataType::Boolean => "BOOL",
+        _ => "STRING",
+    }
+}
+
+fn bigquery_schema(columns: &[String]) -> serde_json::Value {
+    columns
+        .iter()
+        .map(|column| {
+            serde_json::json!({
+                "name": column,
+                "type": bigquery_type(column),
+                "mode": "NULLABLE",
+            })
+        })
+        .collect()
+}
+
+pub fn bigquery_schema_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".schema.json");
+    path.into()
+}
+
+/// Converts a csv field to its JSON value, empty fields are nulls. Wei amounts stay strings,
+/// which BigQuery parses into `BIGNUMERIC` without losing precision.
+fn bigquery_value(column: &str, value: &str) -> eyre::Result<serde_json::Value> {
+    let invalid = || eyre::eyre!("invalid {column} value {value:?}");
+    if value.is_empty() {
+        return Ok(serde_json::Value::Null);
+    }
+    Ok(match data_type(column) {
+        DataType::UInt64 => value.parse::<u64>().map_err(|_| invalid())?.into(),
+        DataType::Float64 => value.parse::<f64>().map_err(|_| invalid())?.into(),
+        DataType::Boolean => value.parse::<bool>().map_err(|_| invalid())?.into(),
+        DataType::Decimal256(..) => units::get()
+            .parse_i256(value)
+            .map_err(|_| invalid())?
+            .to_string()
+            .into(),
+        _ => value.into(),
+    })
+}
+
+fn export_bigquery(input: &Path, output: &Path) -> eyre::Result<()> {
+    let mut reader = Dialect::for_path(input).reader(open_input(input)?);
+    let columns: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+    std::fs::write(
+        bigquery_schema_path(output),
+        serde_json::to_vec_pretty(&bigquery_schema(&columns))?,
+    )?;
+
+    let mut writer = BufWriter::new(std::fs::File::create(output)?);
+    let mut rows = 0;
+    for record in reader.records() {
+        let record = record?;
+        let mut row = serde_json::Map::with_capacity(columns.len());
+        for (column, value) in columns.iter().zip(&record) {
+            row.insert(column.clone(), bigquery_value(column, value)?);
+        }
+        serde_json::to_writer(&mut writer, &row)?;
+        writer.write_all(b"\n")?;
+        rows += 1;
+    }
+    writer.flush()?;
+    tracing::info!(rows, "exported {}", output.display());
+    Ok(())
+}
+
+/// Appends a BigQuery export to `table` (`project.dataset.table`) with a load job.
+///
+/// `token` is an OAuth access token with BigQuery scope, e.g. from
+/// `gcloud auth print-access-token`. The table is created from the exported schema if missing.
+pub async fn load_bigquery(output: &Path, table: &str, token: &str) -> eyre::Result<()> {
+    let [project, dataset, table] = table.split('.').collect::<Vec<_>>()[..] else {
+        eyre::bail!("bigquery table must be project.dataset.table, got {table}");
+    };
+    let schema: serde_json::Value =
+        serde_json::from_slice(&tokio::fs::read(bigquery_schema_path(output)).await?)?;
+    let job = serde_json::json!({
+        "configuration": {
+            "load": {
+                "destinationTable": {
+                    "projectId": project,
+                    "datasetId": dataset,
+                    "tableId": table,
+                },
+                "sourceFormat": "NEWLINE_DELIMITED_JSON",
+                "schema": { "fields": schema },
+                "writeDisposition": "WRITE_APPEND",
+                "createDisposition": "CREATE_IF_NEEDED",
+            }
+        }
+    });
+
+    let client = crate::rpc::http_client();
+    // resumable upload, the simple one is limited to small files
+    let session = client
+        .post(format!(
+            "https://bigquery.googleapis.com/upload/bigquery/v2/projects/{project}/jobs?uploadType=resumable"
+        ))
+        .bearer_auth(token)
+        .json(&job)
+        .send()
+        .await?
+        .error_for_status()?;
+    let upload_url = session
+        .headers()
+        .get(reqwest::header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .ok_or_else(|| eyre::eyre!("bigquery upload session without location"))?
+        .to_string();
+    let job: serde_json::Value = client
+        .put(upload_url)
+        .bearer_auth(token)
+        .body(tokio::fs::read(output).await?)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let job_id = job["jobReference"]["jobId"]
+        .as_str()
+        .ok_or_else(|| eyre::eyre!("bigquery load job without id: {job}"))?
+        .to_string();
+    let location = job["jobReference"]["location"].as_str().unwrap_or_default();
+    tracing::info!(%job_id, "started bigquery load job");
+    loop {
+        let job: serde_json::Value = client
+            .get(format!(
+                "https://bigquery.googleapis.com/bigquery/v2/projects/{project}/jobs/{job_id}"
+            ))
+            .query(&[("location", location)])
+            .bearer_auth(token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if job["status"]["state"] == "DONE" {
+            if let Some(error) = job["status"].get("errorResult") {
+                eyre::bail!("bigquery load job {job_id} failed: {error}");
+            }
+            tracing::info!(
+                rows = job["statistics"]["load"]["outputRows"]
+                    .as_str()
+                    .unwrap_or("?"),
+                "loaded into {project}.{dataset}.{table}"
+            );
+            return Ok(());
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+    }
+}
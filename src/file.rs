@@ -0,0 +1,1852 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ethers::providers::ProviderError;
+use ethers::types::{Address, H256, U256};
+use futures::stream::{FuturesUnordered, StreamExt, TryStreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+use crate::adapters::InputSchema;
+use crate::beacon::BeaconClient;
+use crate::chain::ChainProfile;
+use crate::checkpoint::Checkpoint;
+use crate::clickhouse::ClickHouseSink;
+use crate::entities::Entities;
+use crate::metrics;
+use crate::rpc::{self, EthProvider};
+use crate::schema;
+use crate::streams::{
+    append_output, create_output, create_output_as, is_stdio, open_input, Compression, Dialect,
+    DialectArgs, InputFormat, InputSource,
+};
+use crate::units::ValueUnit;
+use crate::{
+    date_utc, format_eth, process_input_entry, resolve_execution_block, BoostRelayDataEntry,
+    OutputFileEntry, ProcessingOptions, INPUT_COLUMNS,
+};
+
+/// Finished rows held back with `--ordered` at which no more rows are started until the slow row
+/// before them is written, so the buffer stays bounded however long that row takes.
+const MAX_HELD_BACK: usize = 10_000;
+
+/// Outcome of a `file` run, written with `--report` for orchestration.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunReport {
+    pub processed: u64,
+    /// Input rows already in the output or excluded by the filter.
+    pub skipped: u64,
+    pub failed: u64,
+    pub unknown: u64,
+    /// Slots that failed, retried by the next run.
+    pub failed_slots: Vec<u64>,
+    /// Entries whose block wasn't finalized yet with `--only-finalized`, retried by the next run.
+    pub deferred: u64,
+    /// Stopped by SIGINT or SIGTERM before the end of the input.
+    pub interrupted: bool,
+    pub duration_secs: f64,
+}
+
+impl RunReport {
+    pub fn write(&self, path: &Path) -> eyre::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// How a run reports its progress on stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Progress {
+    #[default]
+    Bar,
+    Hidden,
+    /// A JSON line with the processed count, rate and ETA at this interval.
+    Json(std::time::Duration),
+}
+
+impl Progress {
+    pub fn from_flags(no_progress: bool, json_interval_secs: Option<u64>) -> Self {
+        match (no_progress, json_interval_secs) {
+            (_, Some(secs)) => Progress::Json(std::time::Duration::from_secs(secs.max(1))),
+            (true, None) => Progress::Hidden,
+            (false, None) => Progress::Bar,
+        }
+    }
+}
+
+/// Prints a progress event of `progress` as a JSON line to stderr.
+fn print_progress_event(progress: &ProgressBar, event: &str) {
+    let event = serde_json::json!({
+        "event": event,
+        "processed": progress.position(),
+        "total": progress.length(),
+        "rate_per_sec": progress.per_sec(),
+        "elapsed_secs": progress.elapsed().as_secs(),
+        "eta_secs": progress.length().map(|_| progress.eta().as_secs()),
+    });
+    eprintln!("{event}");
+}
+
+/// Selects the input rows to process.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, clap::Args)]
+pub struct InputFilter {
+    /// Skip input rows before this slot.
+    #[clap(long)]
+    pub from_slot: Option<u64>,
+    /// Skip input rows after this slot.
+    #[clap(long)]
+    pub to_slot: Option<u64>,
+    /// Only process rows paying one of these fee recipients.
+    #[clap(long = "fee-recipient", value_delimiter = ',')]
+    #[serde(default)]
+    pub fee_recipients: Vec<Address>,
+    /// Only process rows built by one of these builder pubkeys, needs a `builder_pubkey` column.
+    #[clap(long = "builder", value_delimiter = ',')]
+    #[serde(default)]
+    pub builders: Vec<String>,
+    /// Which of several rows for the same slot to process.
+    #[clap(long, value_enum, default_value = "all")]
+    #[serde(default)]
+    pub dedup_policy: DedupPolicy,
+    /// Process rows of the same slot and block hash once, with their relay names joined by commas
+    /// in the `relay` column, as `sync` stores them.
+    #[clap(long)]
+    #[serde(default)]
+    pub collapse_relays: bool,
+    /// Process a random sample of this many of the selected rows, e.g. to try out options before
+    /// a long run.
+    #[clap(long)]
+    #[serde(default)]
+    pub sample: Option<usize>,
+    /// Seed of `--sample`, the same seed picks the same rows.
+    #[clap(long, default_value = "0")]
+    #[serde(default)]
+    pub seed: u64,
+    /// Stop after sending this many rows for processing in this run.
+    #[clap(long)]
+    #[serde(default)]
+    pub limit: Option<u64>,
+    /// Only process the slots of shard `i` of `N`, as `i/N`, so N runs on different machines
+    /// process disjoint parts of the input. Combine their outputs with `merge`.
+    #[clap(long, value_parser = parse_shard)]
+    #[serde(default)]
+    pub shard: Option<Shard>,
+    /// Resume after the highest slot in the output instead of skipping each slot already in it,
+    /// for append-only pipelines whose input only grows at the end. Missing and failed slots
+    /// before it are not retried.
+    #[clap(long)]
+    #[serde(default)]
+    pub resume_after_last: bool,
+    /// Defer rows whose block isn't finalized yet instead of processing them, so no row ever has
+    /// to be revisited because of a reorg. Deferred rows are retried by the next run.
+    #[clap(long)]
+    #[serde(default)]
+    pub only_finalized: bool,
+}
+
+/// Part of the input a sharded run processes, slots are assigned round robin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    fn contains(&self, slot: u64) -> bool {
+        slot % self.count == self.index
+    }
+}
+
+fn parse_shard(s: &str) -> Result<Shard, String> {
+    let (index, count) = s
+        .split_once('/')
+        .ok_or_else(|| format!("expected i/N, got {s}"))?;
+    let index: u64 = index
+        .parse()
+        .map_err(|e| format!("invalid shard {index}: {e}"))?;
+    let count: u64 = count
+        .parse()
+        .map_err(|e| format!("invalid shard count {count}: {e}"))?;
+    if index >= count {
+        return Err(format!(
+            "shard {index} is not below the shard count {count}"
+        ));
+    }
+    Ok(Shard { index, count })
+}
+
+/// How `--partition-by` splits the output into files, named after the output with the partition
+/// appended to the file name before its extensions, e.g. `payments-2024-05-01.csv`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partition {
+    /// UTC day of the slot.
+    Day,
+    /// Epoch of the slot, as `epoch-<epoch>`.
+    Epoch,
+    /// Ranges of this many slots, named after their first slot.
+    Slots(u64),
+}
+
+pub fn parse_partition(s: &str) -> Result<Partition, String> {
+    match s.split_once(':') {
+        None if s == "day" => Ok(Partition::Day),
+        None if s == "epoch" => Ok(Partition::Epoch),
+        Some(("slots", slots)) => match slots.parse() {
+            Ok(0) => Err("slots:N needs at least one slot per partition".to_string()),
+            Ok(slots) => Ok(Partition::Slots(slots)),
+            Err(e) => Err(format!("invalid slot count {slots}: {e}")),
+        },
+        _ => Err(format!("expected day, epoch or slots:N, got {s}")),
+    }
+}
+
+impl Partition {
+    /// Name of the partition of `slot`.
+    fn key(&self, slot: u64, chain: &ChainProfile) -> String {
+        match self {
+            Partition::Day => date_utc(chain.slot_timestamp(slot)),
+            Partition::Epoch => format!("epoch-{}", slot / chain.slots_per_epoch),
+            Partition::Slots(slots) => (slot / slots * slots).to_string(),
+        }
+    }
+
+    /// File name of `output` split before its first extension.
+    fn split_name(output: &Path) -> eyre::Result<(&str, &str)> {
+        let name = output
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| eyre::eyre!("{} is not a file name", output.display()))?;
+        Ok(name.split_at(name.find('.').unwrap_or(name.len())))
+    }
+
+    /// File of the partition `key` of `output`.
+    fn path(output: &Path, key: &str) -> eyre::Result<PathBuf> {
+        let (stem, extensions) = Self::split_name(output)?;
+        Ok(output.with_file_name(format!("{stem}-{key}{extensions}")))
+    }
+
+    /// Partition files of `output` written so far.
+    pub fn existing(output: &Path) -> eyre::Result<Vec<PathBuf>> {
+        let (stem, extensions) = Self::split_name(output)?;
+        let dir = match output.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut paths = Vec::new();
+        for dir_entry in std::fs::read_dir(dir)? {
+            let path = dir_entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            // sidecars like `.journal` files have other extensions
+            let is_partition = name
+                .strip_prefix(stem)
+                .and_then(|name| name.strip_prefix('-'))
+                .and_then(|name| name.strip_suffix(extensions))
+                .map_or(false, |key| {
+                    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                });
+            if is_partition {
+                paths.push(output.with_file_name(name));
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+/// xorshift64*, good enough to pick a sample without another dependency.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % n as u64) as usize
+    }
+}
+
+/// Resolution of input rows sharing a slot, e.g. from overlapping relay exports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DedupPolicy {
+    /// The row with the highest bid value, the first of them on a tie.
+    MaxBid,
+    /// The first row in the input.
+    First,
+    /// Every row.
+    #[default]
+    All,
+}
+
+/// Input rows delivering the same block, collapsed into the first of them.
+struct RowGroup {
+    row: u64,
+    value: U256,
+    relays: BTreeSet<String>,
+    rows: usize,
+}
+
+/// Rows of `input` left after collapsing relays, applying the dedup policy and sampling, with the
+/// merged relay names of collapsed rows. `None` when every row is processed as is.
+fn select_rows(
+    input: &Path,
+    source: &InputSource,
+    filter: &InputFilter,
+) -> eyre::Result<Option<HashMap<u64, Option<String>>>> {
+    if filter.dedup_policy == DedupPolicy::All && !filter.collapse_relays && filter.sample.is_none()
+    {
+        return Ok(None);
+    }
+    if is_stdio(input) {
+        eyre::bail!(
+            "--dedup-policy, --collapse-relays and --sample need an input file, stdin can't be read twice"
+        );
+    }
+    let mut by_slot: BTreeMap<u64, Vec<(Option<H256>, RowGroup)>> = BTreeMap::new();
+    for (row, entry) in read_entries(input, source)?.enumerate() {
+        let entry = entry?;
+        if !filter.matches(&entry) {
+            continue;
+        }
+        let relays = entry
+            .relay
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(str::to_string);
+        let groups = by_slot.entry(entry.slot).or_default();
+        match groups
+            .iter_mut()
+            .find(|(hash, _)| filter.collapse_relays && hash.is_some() && *hash == entry.block_hash)
+        {
+            Some((_, group)) => {
+                group.relays.extend(relays);
+                group.rows += 1;
+            }
+            None => groups.push((
+                entry.block_hash,
+                RowGroup {
+                    row: row as u64,
+                    value: entry.value,
+                    relays: relays.collect(),
+                    rows: 1,
+                },
+            )),
+        }
+    }
+
+    let mut selected = HashMap::new();
+    for (_, groups) in by_slot {
+        let groups = groups.into_iter().map(|(_, group)| group);
+        let kept: Vec<RowGroup> = match filter.dedup_policy {
+            DedupPolicy::All => groups.collect(),
+            DedupPolicy::First => groups.min_by_key(|group| group.row).into_iter().collect(),
+            DedupPolicy::MaxBid => groups
+                .max_by(|a, b| a.value.cmp(&b.value).then(b.row.cmp(&a.row)))
+                .into_iter()
+                .collect(),
+        };
+        for group in kept {
+            let relays =
+                (group.rows > 1).then(|| group.relays.into_iter().collect::<Vec<_>>().join(","));
+            selected.insert(group.row, relays);
+        }
+    }
+
+    if let Some(sample) = filter.sample {
+        let mut rows: Vec<u64> = selected.keys().copied().collect();
+        rows.sort_unstable();
+        let sample = sample.min(rows.len());
+        // partial Fisher-Yates, the sample ends up at the front
+        let mut rng = Rng::new(filter.seed);
+        for i in 0..sample {
+            let j = i + rng.below(rows.len() - i);
+            rows.swap(i, j);
+        }
+        for row in &rows[sample..] {
+            selected.remove(row);
+        }
+    }
+    Ok(Some(selected))
+}
+
+impl InputFilter {
+    pub fn matches(&self, entry: &BoostRelayDataEntry) -> bool {
+        self.from_slot.map_or(true, |from| entry.slot >= from)
+            && self.to_slot.map_or(true, |to| entry.slot <= to)
+            && self.shard.map_or(true, |shard| shard.contains(entry.slot))
+            && (self.fee_recipients.is_empty()
+                // resolved from the beacon block of the slot and matched again once it is
+                || entry.proposer_fee_recipient.is_zero()
+                || self.fee_recipients.contains(&entry.proposer_fee_recipient))
+            && (self.builders.is_empty()
+                || entry.builder_pubkey.as_deref().map_or(false, |pubkey| {
+                    self.builders
+                        .iter()
+                        .any(|builder| builder.eq_ignore_ascii_case(pubkey))
+                }))
+    }
+}
+
+/// What a resumed run can skip, either from a checkpoint or from the slots in the output.
+#[derive(Debug, Default)]
+struct ResumeState {
+    filter: InputFilter,
+    processed: SlotSet,
+    /// Leading input rows covered by the checkpoint.
+    skip_rows: u64,
+    retry: BTreeSet<u64>,
+    /// Rows after `skip_rows` written before the checkpoint, by row.
+    ahead: BTreeMap<u64, u64>,
+    /// Rows chosen by relay collapsing and the dedup policy, with merged relay names, `None` for
+    /// all of them.
+    selected: Option<HashMap<u64, Option<String>>>,
+    /// Highest slot in the output with `--resume-after-last`, everything up to it is skipped.
+    last_slot: Option<u64>,
+}
+
+impl ResumeState {
+    fn load(
+        input: &Path,
+        output: &Path,
+        filter: InputFilter,
+        source: &InputSource,
+        output_dialect: Dialect,
+        partition: Option<Partition>,
+        resume_after_last: bool,
+    ) -> eyre::Result<Self> {
+        let selected = select_rows(input, source, &filter)?;
+        if is_stdio(output) {
+            return Ok(Self {
+                filter,
+                selected,
+                ..Default::default()
+            });
+        }
+        if resume_after_last {
+            let outputs = match partition {
+                Some(_) => Partition::existing(output)?,
+                None if output.exists() => vec![output.to_path_buf()],
+                None => Vec::new(),
+            };
+            let mut last_slot = None;
+            for path in outputs {
+                for_each_slot(&path, output_dialect, |slot| {
+                    last_slot = last_slot.max(Some(slot));
+                })?;
+            }
+            tracing::info!(?last_slot, "resuming after the last slot of the output");
+            return Ok(Self {
+                filter,
+                selected,
+                last_slot,
+                ..Default::default()
+            });
+        }
+        if partition.is_some() {
+            // partitions aren't checkpointed, each resumes from its own slots
+            let mut processed = SlotSet::default();
+            for path in Partition::existing(output)? {
+                read_slots_into(&path, output_dialect, &mut processed)?;
+            }
+            return Ok(Self {
+                filter,
+                selected,
+                processed,
+                ..Default::default()
+            });
+        }
+        if is_stdio(input) {
+            return Ok(Self {
+                filter,
+                selected,
+                processed: if output.exists() {
+                    read_slots(output, output_dialect)?
+                } else {
+                    SlotSet::default()
+                },
+                ..Default::default()
+            });
+        }
+        if let Some(checkpoint) = Checkpoint::load(input, output, &filter)? {
+            tracing::info!(
+                rows = checkpoint.input_rows,
+                failed = checkpoint.failed.len(),
+                "resuming from checkpoint"
+            );
+            return Ok(Self {
+                filter,
+                processed: SlotSet::default(),
+                skip_rows: checkpoint.input_rows,
+                retry: checkpoint.failed,
+                ahead: checkpoint.ahead,
+                selected,
+                last_slot: None,
+            });
+        }
+        Ok(Self {
+            filter,
+            processed: if output.exists() {
+                read_slots(output, output_dialect)?
+            } else {
+                SlotSet::default()
+            },
+            selected,
+            ..Default::default()
+        })
+    }
+
+    /// Relay names of the rows collapsed into `row`.
+    fn merged_relays(&self, row: u64) -> Option<&str> {
+        self.selected.as_ref()?.get(&row)?.as_deref()
+    }
+
+    /// Whether the row needs no processing, either because it was handled already or because the
+    /// filter excludes it.
+    fn is_done(&self, row: u64, entry: &BoostRelayDataEntry) -> bool {
+        if !self.filter.matches(entry) {
+            return true;
+        }
+        if let Some(selected) = &self.selected {
+            if !selected.contains_key(&row) {
+                return true;
+            }
+        }
+        if self.last_slot.map_or(false, |last| entry.slot <= last) {
+            return true;
+        }
+        if row < self.skip_rows {
+            return !self.retry.contains(&entry.slot);
+        }
+        self.ahead.contains_key(&row) || self.processed.contains(entry.slot)
+    }
+}
+
+/// Processes `input` into `output`, skipping slots already present in `output`.
+///
+/// Input rows are streamed through a bounded channel and up to `rpc_parallel` entries are in flight
+/// at any time, each result is appended to the output as soon as it completes. A slow block only
+/// holds up its own slot, and memory use doesn't grow with the size of either file. Rows are
+/// written in completion order, `finalize` sorts the output. Either path can be `-`
+/// for stdin/stdout, in which case there is nothing to resume from and no checkpoint is kept.
+/// `.gz` and `.zst` files are decompressed and compressed on the fly. A compressed output only
+/// gets its final size once the encoder is dropped, so it is resumed from its slots instead of a
+/// checkpoint.
+///
+/// With `ordered` finished rows are held back until every row before them is done, so an input
+/// sorted by slot gives an output sorted by slot. Failed slots retried on resume are still
+/// appended after the rows of the previous run.
+///
+/// With a `partition` rows go to a file per partition next to `output` instead, which is never
+/// written itself. Partition files are resumed from their slots rather than a checkpoint.
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    provider: EthProvider,
+    options: Arc<ProcessingOptions>,
+    input: &Path,
+    output: &Path,
+    mut filter: InputFilter,
+    dialect: &DialectArgs,
+    rpc_parallel: usize,
+    ordered: bool,
+    partition: Option<Partition>,
+) -> eyre::Result<RunReport> {
+    let started = std::time::Instant::now();
+    let mut report = RunReport::default();
+    let source = dialect.input(input)?;
+    let output_dialect = dialect.output(output);
+    if partition.is_some() && is_stdio(output) {
+        eyre::bail!("--partition-by needs an output file to name the partitions after");
+    }
+    let outputs = match partition {
+        Some(_) => Partition::existing(output)?,
+        None => vec![output.to_path_buf()],
+    };
+    let columns = Columns::select(&dialect.columns)?;
+    if let Some(columns) = &columns {
+        if !is_stdio(output) && !columns.header.iter().any(|column| column == "slot") {
+            eyre::bail!("--columns needs the slot column to resume an output file");
+        }
+    }
+    for path in &outputs {
+        repair_output(path)?;
+        replay_journal(
+            path,
+            output_dialect,
+            columns.as_ref(),
+            options.clickhouse.as_ref(),
+        )
+        .await?;
+    }
+    // only bounds this run, a checkpoint written with a limit is still good without one
+    let limit = filter.limit.take();
+    let resume_after_last = std::mem::take(&mut filter.resume_after_last);
+    let mut finalized = if std::mem::take(&mut filter.only_finalized) {
+        Some(FinalizedHead::fetch(&provider).await?)
+    } else {
+        None
+    };
+    let needs_execution_block = finalized.is_some() || !filter.fee_recipients.is_empty();
+    let resume = Arc::new(ResumeState::load(
+        input,
+        output,
+        filter,
+        &source,
+        output_dialect,
+        partition,
+        resume_after_last,
+    )?);
+    let mut checkpoint = Checkpoint {
+        input: input.to_path_buf(),
+        filter: resume.filter.clone(),
+        input_rows: resume.skip_rows,
+        output_len: 0,
+        failed: resume.retry.clone(),
+        ahead: BTreeMap::new(),
+    };
+    let keep_checkpoint = partition.is_none() && can_resume(input, output);
+    let mut writer = match partition {
+        Some(partition) => Outputs::Partitioned(PartitionedOutput {
+            output: output.to_path_buf(),
+            partition,
+            chain: options.chain.clone(),
+            dialect: output_dialect,
+            columns,
+            sink: options.clickhouse.clone(),
+            open: BTreeMap::new(),
+            written: BTreeSet::new(),
+        }),
+        None => Outputs::Single(open_output(
+            output,
+            output_dialect,
+            columns,
+            options.clickhouse.clone(),
+        )?),
+    };
+
+    let progress = if options.progress != Progress::Bar {
+        let progress = ProgressBar::hidden();
+        if !is_stdio(input) {
+            let pending = count_pending(input, &source, &resume)?;
+            progress.set_length(limit.map_or(pending, |limit| pending.min(limit)));
+        }
+        progress
+    } else if is_stdio(input) {
+        let progress = ProgressBar::new_spinner();
+        progress.set_style(
+            ProgressStyle::default_spinner()
+                .template("[{elapsed_precise}] {spinner} {pos:>7} {msg} ({per_sec})")
+                .unwrap(),
+        );
+        progress
+    } else {
+        let pending = count_pending(input, &source, &resume)?;
+        let progress = ProgressBar::new(limit.map_or(pending, |limit| pending.min(limit)));
+        progress.set_style(
+            ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg} ({eta})")
+                .unwrap()
+                .progress_chars("##-"),
+        );
+        progress
+    };
+
+    let progress_events = match options.progress {
+        Progress::Json(interval) => {
+            let progress = progress.clone();
+            Some(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(interval);
+                // the first tick completes immediately, nothing is processed yet
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    print_progress_event(&progress, "progress");
+                }
+            }))
+        }
+        _ => None,
+    };
+
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(rpc_parallel * 4);
+    let reader = {
+        let input = input.to_path_buf();
+        let resume = resume.clone();
+        tokio::task::spawn_blocking(move || -> eyre::Result<u64> {
+            let entries = read_entries(&input, &source)?;
+            let mut skipped = 0;
+            let mut sent = 0;
+            let mut last_slot = 0;
+            for (row, entry) in entries.enumerate() {
+                let mut entry = entry?;
+                if resume.is_done(row as u64, &entry) {
+                    skipped += 1;
+                    continue;
+                }
+                if limit.map_or(false, |limit| sent >= limit) {
+                    break;
+                }
+                if ordered && entry.slot < last_slot {
+                    eyre::bail!(
+                        "--ordered needs an input sorted by slot, slot {} follows {last_slot}",
+                        entry.slot
+                    );
+                }
+                last_slot = entry.slot;
+                sent += 1;
+                if let Some(relays) = resume.merged_relays(row as u64) {
+                    entry.relay = Some(relays.to_string());
+                }
+                if sender.blocking_send((row as u64, entry)).is_err() {
+                    break;
+                }
+            }
+            Ok(skipped)
+        })
+    };
+
+    let mut concurrency = AdaptiveConcurrency::new(rpc_parallel);
+    let mut in_flight = FuturesUnordered::new();
+    // the lowest row still in flight bounds the rows a checkpoint can cover
+    let mut in_flight_rows = BTreeSet::new();
+    let mut next_row = resume.skip_rows;
+    let mut ahead = resume.ahead.clone();
+    // finished rows waiting for earlier ones with `ordered`
+    let mut held_back: BTreeMap<u64, OutputFileEntry> = BTreeMap::new();
+    let mut input_done = false;
+    let mut completed = 0;
+    let mut throttled = 0;
+    let mut unflushed = 0;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let mut strict_failure = None;
+        tokio::select! {
+            _ = &mut shutdown, if !report.interrupted => {
+                tracing::warn!(
+                    in_flight = in_flight.len(),
+                    "interrupted, waiting for entries in flight"
+                );
+                report.interrupted = true;
+                input_done = true;
+                receiver.close();
+                continue;
+            }
+            entry = receiver.recv(), if !input_done
+                && in_flight.len() < concurrency.current()
+                && held_back.len() < MAX_HELD_BACK => {
+                match entry {
+                    Some((row, entry)) => {
+                        next_row = row + 1;
+                        // slot-only rows have no block or fee recipient to check before this
+                        let entry = if needs_execution_block {
+                            let slot = entry.slot;
+                            match resolve_execution_block(&options, entry).await {
+                                Ok(entry) => Some(entry),
+                                Err(e) => {
+                                    report.failed += 1;
+                                    report.failed_slots.push(slot);
+                                    checkpoint.failed.insert(slot);
+                                    metrics::ENTRIES_FAILED.inc();
+                                    progress.inc(1);
+                                    if options.strict {
+                                        strict_failure = Some((slot, e));
+                                    } else {
+                                        tracing::warn!(
+                                            slot,
+                                            "failed to resolve the execution block: {e:#}"
+                                        );
+                                    }
+                                    None
+                                }
+                            }
+                        } else {
+                            Some(entry)
+                        };
+                        match entry {
+                            Some(entry) if !resume.filter.matches(&entry) => {
+                                report.skipped += 1;
+                                progress.inc(1);
+                            }
+                            Some(entry) => {
+                                if let Some(finalized) = &mut finalized {
+                                    let seconds_per_slot = options.chain.seconds_per_slot;
+                                    if !finalized.is_final(&provider, entry.block_number, seconds_per_slot).await? {
+                                        // retried like a failure, from the checkpoint or the missing slot
+                                        report.deferred += 1;
+                                        checkpoint.failed.insert(entry.slot);
+                                        progress.inc(1);
+                                        continue;
+                                    }
+                                }
+                                in_flight_rows.insert(row);
+                                in_flight.push(spawn_entry(
+                                    &provider, &options, &progress, row, entry,
+                                ));
+                            }
+                            None => {}
+                        }
+                    }
+                    None => input_done = true,
+                }
+                if strict_failure.is_none() {
+                    continue;
+                }
+            }
+            Some(res) = in_flight.next() => {
+                let (row, res) = res?;
+                in_flight_rows.remove(&row);
+                match res {
+                    Ok(res) => {
+                        report.processed += 1;
+                        if res.payment_type == "unknown" {
+                            report.unknown += 1;
+                        }
+                        checkpoint.failed.remove(&res.slot);
+                        metrics::ENTRIES_PROCESSED.inc();
+                        metrics::PAYMENT_TYPES
+                            .with_label_values(&[res.payment_type.as_str()])
+                            .inc();
+                        if ordered {
+                            held_back.insert(row, res);
+                        } else {
+                            ahead.insert(row, res.slot);
+                            writer.serialize(res)?;
+                        }
+                    }
+                    Err((slot, e)) => {
+                        report.failed += 1;
+                        report.failed_slots.push(slot);
+                        checkpoint.failed.insert(slot);
+                        metrics::ENTRIES_FAILED.inc();
+                        if is_throttling_error(&e) {
+                            throttled += 1;
+                        }
+                        if options.strict {
+                            strict_failure = Some((slot, e));
+                        }
+                    }
+                }
+                if ordered {
+                    // rows still to come all come after the ones received so far
+                    let first_unfinished = in_flight_rows.first().copied().unwrap_or(u64::MAX);
+                    while let Some(held) = held_back
+                        .first_entry()
+                        .filter(|held| *held.key() < first_unfinished)
+                    {
+                        let (row, res) = held.remove_entry();
+                        ahead.insert(row, res.slot);
+                        writer.serialize(res)?;
+                    }
+                }
+                completed += 1;
+                unflushed += 1;
+                if completed >= concurrency.current() {
+                    concurrency.record(throttled);
+                    completed = 0;
+                    throttled = 0;
+                }
+            }
+            else => break,
+        }
+
+        if unflushed >= rpc_parallel || strict_failure.is_some() {
+            writer.flush().await?;
+            unflushed = 0;
+            if keep_checkpoint {
+                let covered = in_flight_rows.first().copied().unwrap_or(next_row);
+                save_checkpoint(&mut checkpoint, &mut ahead, covered, output)?;
+            }
+        }
+        // finished entries are written first so a rerun can resume after the failure
+        if let Some((slot, e)) = strict_failure {
+            progress.abandon();
+            if let Some(events) = &progress_events {
+                events.abort();
+            }
+            return Err(e.wrap_err(format!("failed to process slot {slot}")));
+        }
+    }
+    writer.flush().await?;
+    writer.close(output)?;
+    if keep_checkpoint {
+        save_checkpoint(&mut checkpoint, &mut ahead, next_row, output)?;
+    }
+    progress.finish();
+    if let Some(events) = progress_events {
+        events.abort();
+        print_progress_event(&progress, "finished");
+    }
+    report.skipped += reader.await??;
+    report.failed_slots.sort_unstable();
+    report.duration_secs = started.elapsed().as_secs_f64();
+    Ok(report)
+}
+
+/// Appends a `missed` row for every slot of `from_slot..=to_slot` that is neither in `output` nor
+/// has a block on the beacon chain, so per-validator statistics see every slot they were due.
+/// The rows go to `sink` too, like the processed ones.
+#[allow(clippy::too_many_arguments)]
+pub async fn write_missed_slots(
+    beacon: &BeaconClient,
+    chain: &ChainProfile,
+    entities: &Entities,
+    output: &Path,
+    dialect: &DialectArgs,
+    sink: Option<ClickHouseSink>,
+    from_slot: u64,
+    to_slot: u64,
+    rpc_parallel: usize,
+) -> eyre::Result<u64> {
+    let columns = Columns::select(&dialect.columns)?;
+    let dialect = dialect.output(output);
+    let written = read_slots(output, dialect)?;
+    let missed: Vec<OutputFileEntry> = futures::stream::iter(from_slot..=to_slot)
+        .filter(|slot| futures::future::ready(!written.contains(*slot)))
+        .map(|slot| async move {
+            // slots without an input row are usually locally built blocks
+            if beacon.proposer_index(slot).await?.is_some() {
+                return Ok::<_, eyre::Report>(None);
+            }
+            let duty = beacon.proposer_duty(slot, chain.slots_per_epoch).await?;
+            let timestamp = chain.slot_timestamp(slot);
+            let (proposer_index, proposer_pubkey) = duty.unzip();
+            Ok(Some(OutputFileEntry {
+                slot,
+                bid_value_eth: format_eth(U256::zero()),
+                balance_diff_eth: format_eth(U256::zero()),
+                payment_type: "missed".to_string(),
+                underpaid: Some(false),
+                timestamp,
+                date_utc: date_utc(timestamp),
+                entity: entities
+                    .get(proposer_index, proposer_pubkey.as_deref(), None)
+                    .map(str::to_string),
+                proposer_index,
+                proposer_pubkey,
+                ..Default::default()
+            }))
+        })
+        .buffered(rpc_parallel.max(1))
+        .try_filter_map(|entry| futures::future::ready(Ok(entry)))
+        .try_collect()
+        .await?;
+
+    let mut writer = open_output(output, dialect, columns, sink)?;
+    let count = missed.len() as u64;
+    for entry in missed {
+        tracing::debug!(slot = entry.slot, "missed slot");
+        writer.serialize(entry)?;
+    }
+    writer.flush().await?;
+    close_output(writer, output)?;
+    Ok(count)
+}
+
+/// Whether a run is checkpointed, so that an interrupted one resumes where it stopped.
+pub fn can_resume(input: &Path, output: &Path) -> bool {
+    !is_stdio(input) && !is_stdio(output) && Compression::of(output) == Compression::None
+}
+
+/// Resolves on Ctrl-C or, on unix, SIGTERM.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to listen for SIGTERM");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Last finalized block of the execution chain, fetched again at most once a slot when a row is
+/// past it.
+struct FinalizedHead {
+    number: u64,
+    fetched: std::time::Instant,
+}
+
+impl FinalizedHead {
+    async fn fetch(provider: &EthProvider) -> eyre::Result<Self> {
+        let block = provider
+            .get_block(ethers::types::BlockNumber::Finalized)
+            .await?
+            .ok_or_else(|| eyre::eyre!("the node has no finalized block"))?;
+        Ok(Self {
+            number: block.number.unwrap_or_default().as_u64(),
+            fetched: std::time::Instant::now(),
+        })
+    }
+
+    async fn is_final(
+        &mut self,
+        provider: &EthProvider,
+        block_number: u64,
+        seconds_per_slot: u64,
+    ) -> eyre::Result<bool> {
+        if block_number > self.number
+            && self.fetched.elapsed() >= std::time::Duration::from_secs(seconds_per_slot)
+        {
+            *self = Self::fetch(provider).await?;
+        }
+        Ok(block_number <= self.number)
+    }
+}
+
+type EntryResult = Result<OutputFileEntry, (u64, eyre::Report)>;
+
+fn spawn_entry(
+    provider: &EthProvider,
+    options: &Arc<ProcessingOptions>,
+    progress: &ProgressBar,
+    row: u64,
+    entry: BoostRelayDataEntry,
+) -> tokio::task::JoinHandle<(u64, EntryResult)> {
+    let provider = provider.clone();
+    let options = options.clone();
+    let progress = progress.clone();
+
+    let slot = entry.slot;
+    let span = tracing::info_span!(
+        "entry",
+        slot = entry.slot,
+        block_number = entry.block_number
+    );
+    tokio::spawn(
+        async move {
+            let timer = metrics::ENTRY_DURATION.start_timer();
+            let res = process_input_entry(&provider, &options, entry).await;
+            timer.observe_duration();
+            progress.inc(1);
+            match &res {
+                Ok(entry) => {
+                    tracing::debug!(payment_type = %entry.payment_type, "processed");
+                    if let Some(alerter) = &options.alerter {
+                        alerter.check(entry).await;
+                    }
+                }
+                Err(e) => {
+                    tracing::error!(error = %format!("{e:#}"), "failed to process entry")
+                }
+            }
+            (row, res.map_err(|e| (slot, e)))
+        }
+        .instrument(span),
+    )
+}
+
+/// Records that the first `covered` input rows are handled, together with the rows after them
+/// that are already written, so a resumed run neither skips nor duplicates anything.
+fn save_checkpoint(
+    checkpoint: &mut Checkpoint,
+    ahead: &mut BTreeMap<u64, u64>,
+    covered: u64,
+    output: &Path,
+) -> eyre::Result<()> {
+    *ahead = ahead.split_off(&covered);
+    checkpoint.input_rows = checkpoint.input_rows.max(covered);
+    checkpoint.ahead = ahead.clone();
+    checkpoint.output_len = std::fs::metadata(output)?.len();
+    checkpoint.save(output)
+}
+
+/// Number of entries processed at once, halved when the provider throttles and grown back one
+/// step at a time while it is healthy.
+struct AdaptiveConcurrency {
+    current: usize,
+    max: usize,
+    healthy_windows: usize,
+}
+
+impl AdaptiveConcurrency {
+    /// Healthy windows of `current` completed entries in a row required before concurrency
+    /// grows again.
+    const RAMP_UP_AFTER: usize = 5;
+
+    fn new(max: usize) -> Self {
+        Self {
+            current: max.max(1),
+            max: max.max(1),
+            healthy_windows: 0,
+        }
+    }
+
+    fn current(&self) -> usize {
+        self.current
+    }
+
+    fn record(&mut self, throttled: usize) {
+        if throttled > 0 {
+            self.current = (self.current / 2).max(1);
+            self.healthy_windows = 0;
+            tracing::warn!(
+                concurrency = self.current,
+                "provider is throttling, reducing concurrency"
+            );
+        } else if self.current < self.max {
+            self.healthy_windows += 1;
+            if self.healthy_windows >= Self::RAMP_UP_AFTER {
+                self.current += 1;
+                self.healthy_windows = 0;
+            }
+        }
+    }
+}
+
+/// Whether an entry failed because the endpoint is overloaded, judged by the HTTP status and
+/// JSON-RPC error of the failure rather than its text, which holds slots, blocks and hashes.
+fn is_throttling_error(error: &eyre::Report) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<ProviderError>() {
+            rpc::is_throttled(e)
+        } else if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            rpc::is_throttled_http(e)
+        } else {
+            false
+        }
+    })
+}
+
+/// Header of the current output schema.
+pub fn output_header() -> eyre::Result<csv::StringRecord> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.serialize(OutputFileEntry::default())?;
+    let data = writer.into_inner()?;
+    Ok(csv::Reader::from_reader(data.as_slice()).headers()?.clone())
+}
+
+/// Fields of `entry` in the order of [output_header].
+pub fn output_record(entry: &OutputFileEntry) -> eyre::Result<csv::StringRecord> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer.serialize(entry)?;
+    let data = writer.into_inner().map_err(|e| e.into_error())?;
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(data.as_slice())
+        .records()
+        .next()
+        .ok_or_else(|| eyre::eyre!("empty record"))?
+        .map_err(Into::into)
+}
+
+/// Output columns picked with `--columns`, in the order given.
+#[derive(Debug, Clone)]
+pub struct Columns {
+    header: csv::StringRecord,
+    /// Position of each picked column in [output_header].
+    indices: Vec<usize>,
+}
+
+impl Columns {
+    /// `None` when no columns are picked and rows are written whole.
+    pub fn select(names: &[String]) -> eyre::Result<Option<Self>> {
+        if names.is_empty() {
+            return Ok(None);
+        }
+        let all = output_header()?;
+        let indices = names
+            .iter()
+            .map(|name| {
+                all.iter()
+                    .position(|column| column == name)
+                    .ok_or_else(|| eyre::eyre!("unknown output column {name}"))
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Some(Self {
+            header: names.iter().collect(),
+            indices,
+        }))
+    }
+}
+
+/// Header of an output written with `columns`.
+fn header(columns: Option<&Columns>) -> eyre::Result<csv::StringRecord> {
+    match columns {
+        Some(columns) => Ok(columns.header.clone()),
+        None => output_header(),
+    }
+}
+
+/// Writes `entry` with only the picked columns, or whole without any.
+fn write_row<W: Write>(
+    writer: &mut csv::Writer<W>,
+    columns: Option<&Columns>,
+    entry: &OutputFileEntry,
+) -> eyre::Result<()> {
+    match columns {
+        Some(columns) => {
+            let record = output_record(entry)?;
+            let picked: csv::StringRecord = columns.indices.iter().map(|&i| &record[i]).collect();
+            writer.write_record(&picked)?;
+        }
+        None => writer.serialize(entry)?,
+    }
+    Ok(())
+}
+
+/// Slots as ranges of consecutive slots, by first slot, where a hash set takes tens of bytes per
+/// entry. The slots of an output are dense, so even one with tens of millions of rows is a handful
+/// of ranges, and slots inserted in any order only touch their neighbouring ranges.
+#[derive(Debug, Clone, Default)]
+pub struct SlotSet {
+    /// Last slot of the range starting at each key.
+    ranges: BTreeMap<u64, u64>,
+}
+
+impl SlotSet {
+    pub fn insert(&mut self, slot: u64) {
+        if self.contains(slot) {
+            return;
+        }
+        let mut start = slot;
+        let mut end = slot;
+        // a range ending right before the slot is extended
+        if let Some((&before, &before_end)) = self.ranges.range(..slot).next_back() {
+            if before_end + 1 == slot {
+                start = before;
+            }
+        }
+        // and one starting right after it merged in
+        if let Some(after_end) = slot
+            .checked_add(1)
+            .and_then(|after| self.ranges.remove(&after))
+        {
+            end = after_end;
+        }
+        self.ranges.insert(start, end);
+    }
+
+    pub fn contains(&self, slot: u64) -> bool {
+        self.ranges
+            .range(..=slot)
+            .next_back()
+            .map_or(false, |(_, &end)| slot <= end)
+    }
+}
+
+/// Reads only the `slot` column of a csv file. Without a header it has to be the first column.
+pub fn read_slots(path: &Path, dialect: Dialect) -> eyre::Result<SlotSet> {
+    let mut slots = SlotSet::default();
+    read_slots_into(path, dialect, &mut slots)?;
+    Ok(slots)
+}
+
+/// Adds the slots of a csv file to `slots`.
+fn read_slots_into(path: &Path, dialect: Dialect, slots: &mut SlotSet) -> eyre::Result<()> {
+    for_each_slot(path, dialect, |slot| slots.insert(slot))
+}
+
+/// Calls `f` with the slot of every row of a csv file, streaming the rows without decoding other
+/// columns.
+fn for_each_slot(path: &Path, dialect: Dialect, mut f: impl FnMut(u64)) -> eyre::Result<()> {
+    let mut reader = dialect.reader(open_input(path)?);
+    let slot_index = if dialect.has_headers {
+        reader
+            .headers()?
+            .iter()
+            .position(|h| h == "slot")
+            .ok_or_else(|| eyre::eyre!("{} has no slot column", path.display()))?
+    } else {
+        0
+    };
+    let mut record = csv::ByteRecord::new();
+    while reader.read_byte_record(&mut record)? {
+        let slot = record
+            .get(slot_index)
+            .ok_or_else(|| eyre::eyre!("{} has a row without a slot", path.display()))?;
+        f(parse_slot(slot).ok_or_else(|| {
+            eyre::eyre!(
+                "{} has an invalid slot {:?}",
+                path.display(),
+                String::from_utf8_lossy(slot)
+            )
+        })?);
+    }
+    Ok(())
+}
+
+/// Parses a slot field straight from its bytes, without validating the row as UTF-8 first.
+fn parse_slot(field: &[u8]) -> Option<u64> {
+    if field.is_empty() {
+        return None;
+    }
+    field.iter().try_fold(0u64, |slot, &b| {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        slot.checked_mul(10)?.checked_add(u64::from(b - b'0'))
+    })
+}
+
+/// Rows of `input` in either format.
+pub fn read_entries(
+    input: &Path,
+    source: &InputSource,
+) -> eyre::Result<Box<dyn Iterator<Item = eyre::Result<BoostRelayDataEntry>> + Send>> {
+    Ok(match source.format {
+        InputFormat::Csv => Box::new(read_input(
+            source.dialect.reader(open_input(input)?),
+            source.schema.clone(),
+        )?),
+        InputFormat::Json => Box::new(
+            crate::relays::read_payloads_json(open_input(input)?)?
+                .into_iter()
+                .map(Ok),
+        ),
+        InputFormat::Parquet => {
+            let (header, records) = read_parquet_records(input)?;
+            Box::new(input_entries(Some(header), records, source.schema.clone()))
+        }
+    })
+}
+
+/// Input rows with the columns not in [INPUT_COLUMNS] kept in `extra`. Inputs without a header
+/// have no extra columns.
+fn read_input<R: Read>(
+    mut reader: csv::Reader<R>,
+    schema: InputSchema,
+) -> eyre::Result<impl Iterator<Item = eyre::Result<BoostRelayDataEntry>>> {
+    let header = if reader.has_headers() {
+        Some(reader.headers()?.clone())
+    } else {
+        None
+    };
+    let records = reader.into_records().map(|record| Ok(record?));
+    Ok(input_entries(header, records, schema))
+}
+
+/// Rows of a Parquet input as csv records under its column names, formatted the way a csv export
+/// would write them, so they go through the same column mapping. Nulls become empty fields.
+fn read_parquet_records(
+    path: &Path,
+) -> eyre::Result<(
+    csv::StringRecord,
+    impl Iterator<Item = eyre::Result<csv::StringRecord>>,
+)> {
+    use arrow::util::display::{ArrayFormatter, FormatOptions};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    if is_stdio(path) {
+        return Err(eyre::eyre!("Parquet inputs can't be read from stdin"));
+    }
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()?;
+    let header = reader
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().as_str())
+        .collect();
+    let records = reader.flat_map(|batch| {
+        let rows = batch.map_err(eyre::Report::from).and_then(|batch| {
+            let options = FormatOptions::default();
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((0..batch.num_rows())
+                .map(|row| {
+                    columns
+                        .iter()
+                        .map(|column| column.value(row).to_string())
+                        .collect::<csv::StringRecord>()
+                })
+                .collect::<Vec<_>>())
+        });
+        match rows {
+            Ok(rows) => rows.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        }
+    });
+    Ok((header, records))
+}
+
+/// Entries of input `records` under the export's `header`, renamed to the standard columns.
+fn input_entries(
+    header: Option<csv::StringRecord>,
+    records: impl Iterator<Item = eyre::Result<csv::StringRecord>>,
+    schema: InputSchema,
+) -> impl Iterator<Item = eyre::Result<BoostRelayDataEntry>> {
+    let headers = header.map(|header| schema.standard_header(&header));
+    let value_index = match &headers {
+        Some(headers) => headers.iter().position(|column| column == "value"),
+        // see DialectArgs::no_input_header
+        None => Some(2),
+    };
+    records.map(move |record| {
+        let mut record = record?;
+        if let (Some(index), true) = (value_index, schema.value_unit != ValueUnit::Wei) {
+            let value = record
+                .get(index)
+                .ok_or_else(|| eyre::eyre!("row has no value column"))?;
+            let value = schema.value_in_wei(value)?;
+            record = record
+                .iter()
+                .enumerate()
+                .map(|(i, field)| if i == index { value.as_str() } else { field })
+                .collect();
+        }
+        let mut entry: BoostRelayDataEntry = record.deserialize(headers.as_ref())?;
+        if let Some(headers) = &headers {
+            entry.extra = headers
+                .iter()
+                .zip(record.iter())
+                .filter(|(column, _)| !INPUT_COLUMNS.contains(column))
+                .map(|(column, value)| (column.to_string(), value.to_string()))
+                .collect();
+        }
+        Ok(entry)
+    })
+}
+
+fn count_pending(input: &Path, source: &InputSource, resume: &ResumeState) -> eyre::Result<u64> {
+    let mut pending = 0;
+    for (row, entry) in read_entries(input, source)?.enumerate() {
+        if !resume.is_done(row as u64, &entry?) {
+            pending += 1;
+        }
+    }
+    Ok(pending)
+}
+
+/// Cuts off a row left incomplete by a crash, a complete output always ends with a newline. An
+/// output without even a complete header is removed.
+fn repair_output(path: &Path) -> eyre::Result<()> {
+    if is_stdio(path) || !path.exists() || Compression::of(path) != Compression::None {
+        return Ok(());
+    }
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut end = len;
+    let mut buf = [0u8; 8192];
+    while end > 0 {
+        let start = end.saturating_sub(buf.len() as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(i) = chunk.iter().rposition(|b| *b == b'\n') {
+            let complete = start + i as u64 + 1;
+            if complete < len {
+                tracing::warn!(
+                    bytes = len - complete,
+                    "dropping incomplete last row of {}",
+                    path.display()
+                );
+                file.set_len(complete)?;
+                file.sync_all()?;
+            }
+            return Ok(());
+        }
+        end = start;
+    }
+    tracing::warn!("{} has no complete header, starting over", path.display());
+    drop(file);
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Writes `entries` under the current header, or only the picked `columns`, to a temporary file
+/// and renames it over `path`, so `path` never holds a partial header.
+pub fn replace_output(
+    path: &Path,
+    dialect: Dialect,
+    columns: Option<&Columns>,
+    entries: impl Iterator<Item = eyre::Result<OutputFileEntry>>,
+) -> eyre::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut writer = dialect.writer(create_output_as(tmp.as_ref(), Compression::of(path))?);
+    dialect.write_header(&mut writer, &header(columns)?)?;
+    for entry in entries {
+        write_row(&mut writer, columns, &entry?)?;
+    }
+    drop(writer.into_inner().map_err(|e| e.into_error())?);
+    File::open(&tmp)?.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    schema::write_version(path, columns.map(|columns| &columns.header))
+}
+
+/// Output being appended to, with a handle to sync flushed rows of plain files to disk and an
+/// optional ClickHouse table that receives every flushed batch.
+struct Output {
+    writer: csv::Writer<Box<dyn Write + Send>>,
+    file: Option<File>,
+    /// Rows written since the last flush as JSON lines, only for plain files whose flushed rows
+    /// are on disk once synced.
+    journal: Option<File>,
+    /// Columns written to the file, the sink always gets whole rows.
+    columns: Option<Columns>,
+    sink: Option<ClickHouseSink>,
+    /// Rows written since the last flush, kept for the sink.
+    batch: Vec<OutputFileEntry>,
+}
+
+fn journal_path(output: &Path) -> std::path::PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".journal");
+    path.into()
+}
+
+/// Appends rows of the journal that didn't make it into `output` before a crash, and empties it.
+/// Rows of the journal are matched to the output by slot. The journal is only emptied once its
+/// rows are in the `sink` too, so all of them are inserted again: they may be in the file but
+/// not in the table.
+async fn replay_journal(
+    output: &Path,
+    dialect: Dialect,
+    columns: Option<&Columns>,
+    sink: Option<&ClickHouseSink>,
+) -> eyre::Result<()> {
+    let journal = journal_path(output);
+    if is_stdio(output) || !journal.exists() {
+        return Ok(());
+    }
+    if !output.exists() {
+        std::fs::remove_file(&journal)?;
+        return Ok(());
+    }
+    let written = read_slots(output, dialect)?;
+    let mut journaled = Vec::new();
+    for line in std::io::BufRead::lines(std::io::BufReader::new(File::open(&journal)?)) {
+        // the last line is cut off if the crash happened while writing it
+        let Ok(entry) = serde_json::from_str::<OutputFileEntry>(&line?) else {
+            continue;
+        };
+        journaled.push(entry);
+    }
+    let recovered: Vec<&OutputFileEntry> = journaled
+        .iter()
+        .filter(|entry| !written.contains(entry.slot))
+        .collect();
+    if !recovered.is_empty() {
+        tracing::warn!(
+            rows = recovered.len(),
+            "recovering rows lost before the last flush of {}",
+            output.display()
+        );
+        let mut reader = dialect.reader(open_input(output)?);
+        if !dialect.has_headers || reader.headers()? == &header(columns)? {
+            let mut writer = dialect.writer(append_output(output)?);
+            for entry in &recovered {
+                write_row(&mut writer, columns, entry)?;
+            }
+            drop(writer.into_inner().map_err(|e| e.into_error())?);
+            File::open(output)?.sync_all()?;
+        } else if columns.is_some() {
+            eyre::bail!(
+                "{} has other columns than --columns, can't recover its journal",
+                output.display()
+            );
+        } else {
+            let existing: Vec<OutputFileEntry> =
+                reader.into_deserialize().collect::<Result<_, _>>()?;
+            replace_output(
+                output,
+                dialect,
+                None,
+                existing
+                    .into_iter()
+                    .chain(recovered.into_iter().cloned())
+                    .map(Ok),
+            )?;
+        }
+    }
+    if let Some(sink) = sink {
+        sink.insert(&journaled).await?;
+    }
+    std::fs::remove_file(&journal)?;
+    Ok(())
+}
+
+impl Output {
+    /// Journals the row before it goes into the buffered writer, so a crash before the next flush
+    /// doesn't lose it.
+    fn serialize(&mut self, entry: OutputFileEntry) -> eyre::Result<()> {
+        if let Some(journal) = &mut self.journal {
+            let mut line = serde_json::to_vec(&entry)?;
+            line.push(b'\n');
+            journal.write_all(&line)?;
+        }
+        write_row(&mut self.writer, self.columns.as_ref(), &entry)?;
+        if self.sink.is_some() {
+            self.batch.push(entry);
+        }
+        Ok(())
+    }
+
+    /// Flushes, syncs and inserts into the sink, so everything a checkpoint counts is stored. The
+    /// journal is emptied last: after a failed insert the next run inserts its rows again.
+    async fn flush(&mut self) -> eyre::Result<()> {
+        self.writer.flush()?;
+        if let Some(file) = &self.file {
+            file.sync_data()?;
+        }
+        if let Some(sink) = &self.sink {
+            sink.insert(&self.batch).await?;
+            self.batch.clear();
+        }
+        if let Some(journal) = &self.journal {
+            journal.set_len(0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Output of a run, a single file or one per partition.
+enum Outputs {
+    Single(Output),
+    Partitioned(PartitionedOutput),
+}
+
+/// Partition files of an output, opened when the first row for them comes in and closed again
+/// once a flush finds they got no rows since the previous one, so a long run over slot ordered
+/// input doesn't keep every partition open.
+struct PartitionedOutput {
+    output: PathBuf,
+    partition: Partition,
+    chain: ChainProfile,
+    dialect: Dialect,
+    columns: Option<Columns>,
+    sink: Option<ClickHouseSink>,
+    open: BTreeMap<PathBuf, Output>,
+    /// Partitions written since the last flush.
+    written: BTreeSet<PathBuf>,
+}
+
+impl Outputs {
+    fn serialize(&mut self, entry: OutputFileEntry) -> eyre::Result<()> {
+        match self {
+            Outputs::Single(output) => output.serialize(entry),
+            Outputs::Partitioned(partitioned) => {
+                let key = partitioned.partition.key(entry.slot, &partitioned.chain);
+                let path = Partition::path(&partitioned.output, &key)?;
+                if !partitioned.open.contains_key(&path) {
+                    let output = open_output(
+                        &path,
+                        partitioned.dialect,
+                        partitioned.columns.clone(),
+                        partitioned.sink.clone(),
+                    )?;
+                    partitioned.open.insert(path.clone(), output);
+                }
+                partitioned
+                    .open
+                    .get_mut(&path)
+                    .expect("opened above")
+                    .serialize(entry)?;
+                partitioned.written.insert(path);
+                Ok(())
+            }
+        }
+    }
+
+    async fn flush(&mut self) -> eyre::Result<()> {
+        match self {
+            Outputs::Single(output) => output.flush().await,
+            Outputs::Partitioned(partitioned) => {
+                for output in partitioned.open.values_mut() {
+                    output.flush().await?;
+                }
+                let written = std::mem::take(&mut partitioned.written);
+                let idle: Vec<PathBuf> = partitioned
+                    .open
+                    .keys()
+                    .filter(|path| !written.contains(*path))
+                    .cloned()
+                    .collect();
+                for path in idle {
+                    let output = partitioned.open.remove(&path).expect("listed above");
+                    close_output(output, &path)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes the journals of the flushed outputs, everything journaled is in the files now.
+    fn close(self, output: &Path) -> eyre::Result<()> {
+        match self {
+            Outputs::Single(single) => close_output(single, output),
+            Outputs::Partitioned(partitioned) => {
+                for (path, output) in partitioned.open {
+                    close_output(output, &path)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn close_output(mut output: Output, path: &Path) -> eyre::Result<()> {
+    if output.journal.take().is_some() {
+        std::fs::remove_file(journal_path(path))?;
+    }
+    Ok(())
+}
+
+/// Opens the output for appending, first creating it with a header or rewriting existing rows if
+/// they use an older schema. Outputs with picked `columns` can't be rewritten.
+fn open_output(
+    path: &Path,
+    dialect: Dialect,
+    columns: Option<Columns>,
+    sink: Option<ClickHouseSink>,
+) -> eyre::Result<Output> {
+    if is_stdio(path) {
+        let mut writer = dialect.writer(create_output(path)?);
+        dialect.write_header(&mut writer, &header(columns.as_ref())?)?;
+        return Ok(Output {
+            writer,
+            file: None,
+            journal: None,
+            columns,
+            sink,
+            batch: Vec::new(),
+        });
+    }
+
+    if !path.exists() {
+        replace_output(path, dialect, columns.as_ref(), std::iter::empty())?;
+    } else if dialect.has_headers {
+        schema::check_unit(path)?;
+        let mut reader = dialect.reader(open_input(path)?);
+        if reader.headers()? != &header(columns.as_ref())? {
+            if columns.is_some() {
+                eyre::bail!(
+                    "{} was written with other columns than --columns",
+                    path.display()
+                );
+            }
+            if let Some(picked) = schema::read_columns(path)? {
+                eyre::bail!(
+                    "{} was written with --columns {}, pass the same to append to it",
+                    path.display(),
+                    picked.join(",")
+                );
+            }
+            replace_output(
+                path,
+                dialect,
+                None,
+                reader
+                    .into_deserialize::<OutputFileEntry>()
+                    .map(|entry| Ok(entry?)),
+            )?;
+        }
+    }
+
+    Ok(Output {
+        writer: dialect.writer(append_output(path)?),
+        file: match Compression::of(path) {
+            Compression::None => Some(OpenOptions::new().append(true).open(path)?),
+            // compressed streams are only complete once the encoder is finished
+            _ => None,
+        },
+        journal: match Compression::of(path) {
+            Compression::None => Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(journal_path(path))?,
+            ),
+            _ => None,
+        },
+        columns,
+        sink,
+        batch: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slot_set_inserts_below_first() {
+        let mut slots = SlotSet::default();
+        slots.insert(1000);
+        slots.insert(10);
+        slots.insert(999);
+        assert!(slots.contains(1000));
+        assert!(slots.contains(10));
+        assert!(slots.contains(999));
+        assert!(!slots.contains(11));
+        assert!(!slots.contains(1001));
+    }
+
+    #[test]
+    fn slot_set_contains_slot_zero() {
+        let mut slots = SlotSet::default();
+        assert!(!slots.contains(0));
+        slots.insert(5);
+        assert!(!slots.contains(0));
+        slots.insert(0);
+        assert!(slots.contains(0));
+        assert!(slots.contains(5));
+    }
+
+    #[test]
+    fn slot_set_word_boundaries() {
+        let mut slots = SlotSet::default();
+        for slot in [63, 64, 127, 128] {
+            slots.insert(slot);
+        }
+        for slot in [63, 64, 127, 128] {
+            assert!(slots.contains(slot));
+        }
+        for slot in [0, 62, 65, 126, 129, 192] {
+            assert!(!slots.contains(slot));
+        }
+    }
+
+    #[test]
+    fn slot_set_merges_ranges_in_any_order() {
+        let mut slots = SlotSet::default();
+        for slot in (0..1000).rev().filter(|slot| slot % 2 == 0) {
+            slots.insert(slot);
+        }
+        assert_eq!(slots.ranges.len(), 500);
+        for slot in (0..1000).filter(|slot| slot % 2 == 1) {
+            slots.insert(slot);
+        }
+        assert_eq!(slots.ranges.len(), 1);
+        assert!(slots.contains(0));
+        assert!(slots.contains(999));
+        assert!(!slots.contains(1000));
+    }
+
+    #[test]
+    fn parses_slot_bytes() {
+        assert_eq!(parse_slot(b"0"), Some(0));
+        assert_eq!(parse_slot(b"8000123"), Some(8000123));
+        assert_eq!(parse_slot(b""), None);
+        assert_eq!(parse_slot(b"12a"), None);
+        assert_eq!(parse_slot(b"99999999999999999999"), None);
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::schema;
+use crate::streams::{create_output_as, Compression, Dialect};
+use crate::{read_output_file, OutputFileEntry};
+
+/// Sorts `entries` by slot, keeping only the last entry seen for every slot.
+fn dedup_by_slot(entries: impl IntoIterator<Item = OutputFileEntry>) -> Vec<OutputFileEntry> {
+    let mut by_slot = BTreeMap::new();
+    for entry in entries {
+        by_slot.insert(entry.slot, entry);
+    }
+    by_slot.into_values().collect()
+}
+
+/// Writes `entries` to a temporary file next to `path` and renames it over `path`.
+fn write_atomically(path: &Path, entries: &[OutputFileEntry]) -> eyre::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(Dialect::for_path(path).delimiter)
+        .from_writer(create_output_as(tmp.as_ref(), Compression::of(path))?);
+    for entry in entries {
+        writer.serialize(entry)?;
+    }
+    // dropping the inner writer finishes a compressed stream before it is synced
+    drop(writer.into_inner().map_err(|e| e.into_error())?);
+    std::fs::File::open(&tmp)?.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    schema::write_version(path, None)
+}
+
+/// Rewrites an output file sorted by slot without duplicate slots, newer rows win.
+pub fn finalize(path: &Path) -> eyre::Result<()> {
+    schema::check_full(path)?;
+    let entries = read_output_file(path)?;
+    let rows = entries.len();
+    let entries = dedup_by_slot(entries);
+    write_atomically(path, &entries)?;
+    tracing::info!(
+        rows = entries.len(),
+        duplicates = rows - entries.len(),
+        "finalized {}",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Combines partial outputs into one sorted, deduplicated file. Rows from later inputs win.
+pub fn merge(inputs: &[PathBuf], output: &Path) -> eyre::Result<()> {
+    let mut entries = Vec::new();
+    for input in inputs {
+        schema::check_full(input)?;
+        entries.extend(read_output_file(input)?);
+    }
+    let rows = entries.len();
+    let entries = dedup_by_slot(entries);
+    write_atomically(output, &entries)?;
+    tracing::info!(
+        inputs = inputs.len(),
+        rows = entries.len(),
+        duplicates = rows - entries.len(),
+        "merged into {}",
+        output.display()
+    );
+    Ok(())
+}
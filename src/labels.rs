@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use ethers::types::Address;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct LabelRow {
+    address: Address,
+    #[serde(alias = "name")]
+    label: String,
+}
+
+/// User supplied names for addresses such as builders, exchanges or pools.
+#[derive(Debug, Clone, Default)]
+pub struct Labels {
+    labels: HashMap<Address, String>,
+}
+
+impl Labels {
+    /// Reads a csv file with `address,label` columns.
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let mut labels = HashMap::new();
+        for row in csv::Reader::from_path(path)?.into_deserialize::<LabelRow>() {
+            let row = row?;
+            labels.insert(row.address, row.label);
+        }
+        Ok(Self { labels })
+    }
+
+    pub fn get(&self, address: Address) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+}
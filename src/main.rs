@@ -1,114 +1,736 @@
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
+use std::process::ExitCode;
+
+mod adapters;
+mod alerts;
+mod archive;
+mod audit;
+mod beacon;
+mod builders;
+mod chain;
+mod checkpoint;
+mod clickhouse;
+mod config;
+mod delegation;
+mod diff;
+mod distributions;
+mod ens;
+mod entities;
+mod expected;
+mod export;
+mod file;
+mod finalize;
+mod labels;
+mod meta;
+mod metrics;
+mod migrate;
+mod prices;
+mod quality;
+mod query;
+mod recipients;
+mod relays;
+mod render;
+mod report;
+mod results;
+mod rpc;
+mod rules;
+mod s3;
+mod scan;
+mod schema;
+mod serve;
+mod stats;
+mod streams;
+mod summarize;
+mod trace;
+mod units;
+mod validate;
+mod verify;
+mod watch;
 
 use ethers::prelude::*;
-use ethers::types::Call;
+use rpc::EthProvider;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use trace::TransferData;
 
-use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
+use clap::{CommandFactory, FromArgMatches};
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 struct BoostRelayDataEntry {
     slot: u64,
+    /// Taken from the beacon block of `slot` when the input has no such column.
+    #[serde(default)]
     proposer_fee_recipient: Address,
-    #[serde(deserialize_with = "deserialize_u256_from_decimal")]
+    #[serde(
+        serialize_with = "serialize_wei_to_decimal",
+        deserialize_with = "deserialize_wei_from_decimal"
+    )]
     value: U256,
-    block_hash: H256,
+    /// Compared with the canonical block when given.
+    #[serde(default)]
+    block_hash: Option<H256>,
+    /// Taken from the beacon block of `slot` like `proposer_fee_recipient`.
+    #[serde(default)]
     block_number: u64,
+    #[serde(default)]
+    relay: Option<String>,
+    #[serde(default)]
+    builder_pubkey: Option<String>,
+    /// Input columns the tool doesn't use, passed through to the output.
+    #[serde(skip)]
+    extra: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Input columns read into [BoostRelayDataEntry], everything else ends up in its `extra`.
+const INPUT_COLUMNS: &[&str] = &[
+    "slot",
+    "proposer_fee_recipient",
+    "value",
+    "block_hash",
+    "block_number",
+    "relay",
+    "builder_pubkey",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 struct OutputFileEntry {
     slot: u64,
     block_number: u64,
+    /// Whether the input block hash is the canonical block at `block_number`, empty without one.
+    /// The rest of the row describes the canonical block.
+    #[serde(default)]
+    block_hash_match: Option<bool>,
     #[serde(
         serialize_with = "serialize_u256_to_decimal",
         deserialize_with = "deserialize_u256_from_decimal"
     )]
     bid_value: U256,
+    /// `bid_value` in ETH with all 18 decimals, for spreadsheets.
+    #[serde(default)]
+    bid_value_eth: String,
+    /// `bid_value` in USD at the ETH price of the block, see `--usd-prices`.
+    #[serde(default)]
+    bid_value_usd: Option<f64>,
     #[serde(
-        serialize_with = "serialize_u256_to_decimal",
-        deserialize_with = "deserialize_u256_from_decimal"
+        serialize_with = "serialize_i256_to_decimal",
+        deserialize_with = "deserialize_i256_from_decimal"
+    )]
+    balance_diff: I256,
+    #[serde(default)]
+    balance_diff_eth: String,
+    #[serde(
+        default,
+        serialize_with = "serialize_i256_to_decimal",
+        deserialize_with = "deserialize_i256_from_decimal"
     )]
-    balance_diff: U256,
+    balance_diff_execution: I256,
     payment_type: String,
+    #[serde(default)]
+    unknown_reason: Option<String>,
+    /// Name of the config rule that classified a `rule` payment.
+    #[serde(default)]
+    payment_rule: Option<String>,
+    #[serde(default)]
+    payment_reverted: bool,
+    #[serde(
+        default,
+        serialize_with = "serialize_i256_to_decimal",
+        deserialize_with = "deserialize_i256_from_decimal"
+    )]
+    observed_payment: I256,
+    /// `observed_payment` in USD at the ETH price of the block, see `--usd-prices`.
+    #[serde(default)]
+    payment_usd: Option<f64>,
+    /// `observed_payment - bid_value`, negative when the proposer got less than the bid.
+    #[serde(
+        default,
+        serialize_with = "serialize_i256_to_decimal",
+        deserialize_with = "deserialize_i256_from_decimal"
+    )]
+    delta_wei: I256,
+    #[serde(default)]
+    payment_ratio: Option<f64>,
+    /// `None` in outputs written before the column existed, see [read_output_totals].
+    #[serde(default)]
+    underpaid: Option<bool>,
+    /// How far the observed payment exceeded the bid when by more than the overpayment
+    /// threshold, e.g. a builder subsidizing the block.
+    #[serde(
+        default,
+        serialize_with = "serialize_opt_u256_to_decimal",
+        deserialize_with = "deserialize_opt_u256_from_decimal"
+    )]
+    overpaid_by: Option<U256>,
+    #[serde(default)]
+    payment_tx_hash: Option<H256>,
+    /// Position of the payment tx in the block, see `tx_count` for how close to the end it is.
+    #[serde(default)]
+    payment_tx_index: Option<usize>,
+    #[serde(default)]
+    payment_from: Option<Address>,
+    #[serde(default)]
+    payment_from_label: Option<String>,
+    /// `eoa` or `contract`, whether the payment sender had code at the block.
+    #[serde(default)]
+    payment_from_kind: Option<String>,
+    #[serde(
+        default,
+        serialize_with = "serialize_opt_u256_to_decimal",
+        deserialize_with = "deserialize_opt_u256_from_decimal"
+    )]
+    payment_value: Option<U256>,
+    #[serde(
+        default,
+        serialize_with = "serialize_opt_u256_to_decimal",
+        deserialize_with = "deserialize_opt_u256_from_decimal"
+    )]
+    payment_tx_fee: Option<U256>,
+    /// Part of `payment_tx_fee` above the base fee, paid to the coinbase.
+    #[serde(
+        default,
+        serialize_with = "serialize_opt_u256_to_decimal",
+        deserialize_with = "deserialize_opt_u256_from_decimal"
+    )]
+    payment_tx_priority_fee: Option<U256>,
+    /// The payment tx paid no priority fee, as builders paying from their own coinbase usually do.
+    #[serde(default)]
+    payment_tx_zero_priority_fee: bool,
+    /// Other addresses the payment tx paid from the same sender, as a JSON object of values by
+    /// address. Only the fee recipient's part counts against the bid.
+    #[serde(default)]
+    payment_side_recipients: Option<String>,
+    #[serde(
+        default,
+        serialize_with = "serialize_opt_i256_to_decimal",
+        deserialize_with = "deserialize_opt_i256_from_decimal"
+    )]
+    payment_net_value: Option<I256>,
     withdrawals: usize,
+    /// Total withdrawn to the fee recipient in the block, which is also the withdrawal part of `balance_diff`.
+    #[serde(
+        default,
+        alias = "balance_diff_withdrawals",
+        serialize_with = "serialize_u256_to_decimal",
+        deserialize_with = "deserialize_u256_from_decimal"
+    )]
+    withdrawals_value: U256,
     transfers: usize,
     transfers_in: usize,
     transfers_out: usize,
+    #[serde(
+        default,
+        serialize_with = "serialize_u256_to_decimal",
+        deserialize_with = "deserialize_u256_from_decimal"
+    )]
+    transfers_in_value: U256,
+    #[serde(
+        default,
+        serialize_with = "serialize_u256_to_decimal",
+        deserialize_with = "deserialize_u256_from_decimal"
+    )]
+    transfers_out_value: U256,
+    /// Balance change not accounted for by traced transfers, withdrawals and, for a coinbase fee
+    /// recipient, priority fees. Nonzero means a payment path was missed. Empty without traces.
+    #[serde(
+        default,
+        serialize_with = "serialize_opt_i256_to_decimal",
+        deserialize_with = "deserialize_opt_i256_from_decimal"
+    )]
+    unexplained_diff: Option<I256>,
+    /// False when the node couldn't trace the block, so the payment was found from the receipts
+    /// and the last tx alone and payments through contracts are missing.
+    #[serde(default)]
+    trace_data: bool,
+    #[serde(default)]
+    gas_used: u64,
+    #[serde(default)]
+    gas_limit: u64,
+    /// Number of transactions in the block, zero for an empty delivered payload.
+    #[serde(default)]
+    tx_count: usize,
+    /// Of `tx_count`, the legacy txs (type 0).
+    #[serde(default)]
+    tx_count_legacy: usize,
+    /// Of `tx_count`, the access list txs (type 1).
+    #[serde(default)]
+    tx_count_eip2930: usize,
+    /// Of `tx_count`, the dynamic fee txs (type 2).
+    #[serde(default)]
+    tx_count_eip1559: usize,
+    /// Of `tx_count`, the blob txs (type 3).
+    #[serde(default)]
+    tx_count_eip4844: usize,
+    /// Of `tx_count`, the set code txs (type 4).
+    #[serde(default)]
+    tx_count_eip7702: usize,
+    /// Gas used as a percentage of the gas limit.
+    #[serde(default)]
+    block_fullness_pct: f64,
+    #[serde(
+        default,
+        serialize_with = "serialize_u256_to_decimal",
+        deserialize_with = "deserialize_u256_from_decimal"
+    )]
+    base_fee_burned: U256,
+    #[serde(default)]
+    blob_gas_used: u64,
+    #[serde(
+        default,
+        serialize_with = "serialize_u256_to_decimal",
+        deserialize_with = "deserialize_u256_from_decimal"
+    )]
+    blob_fee_burned: U256,
+    #[serde(
+        default,
+        serialize_with = "serialize_u256_to_decimal",
+        deserialize_with = "deserialize_u256_from_decimal"
+    )]
+    total_priority_fees: U256,
+    #[serde(
+        default,
+        serialize_with = "serialize_u256_to_decimal",
+        deserialize_with = "deserialize_u256_from_decimal"
+    )]
+    builder_block_value: U256,
+    #[serde(
+        default,
+        serialize_with = "serialize_i256_to_decimal",
+        deserialize_with = "deserialize_i256_from_decimal"
+    )]
+    builder_margin: I256,
+    #[serde(default)]
+    timestamp: u64,
+    #[serde(default)]
+    date_utc: String,
+    /// Fork the block was produced under, see [Era].
+    #[serde(default)]
+    era: String,
+    #[serde(default)]
+    builder: Option<String>,
+    #[serde(default)]
+    builder_pubkey: Option<String>,
+    #[serde(default)]
+    relay: Option<String>,
+    /// Fee recipient of the input row, whose balance the payment is checked against.
+    #[serde(default)]
+    fee_recipient: Option<Address>,
+    #[serde(default)]
+    recipient_kind: String,
+    #[serde(default)]
+    fee_recipient_label: Option<String>,
+    /// `eoa` or `contract`, whether the fee recipient had code at the block.
+    #[serde(default)]
+    fee_recipient_account_kind: String,
+    /// Same as `fee_recipient_account_kind == contract`, for filtering.
+    #[serde(default)]
+    is_contract: bool,
+    /// The proposer built the block itself or its fee recipient belongs to a builder, so the
+    /// payment doesn't reflect a competitive bid.
+    #[serde(default)]
+    vertically_integrated: bool,
+    #[serde(default)]
+    proposer_index: Option<u64>,
+    #[serde(default)]
+    proposer_pubkey: Option<String>,
+    /// Staking entity running the proposer, by validator or fee recipient, see `--entities`.
+    #[serde(default)]
+    entity: Option<String>,
+    /// Value the relay API currently reports as delivered for the slot, when cross-checked.
+    #[serde(
+        default,
+        serialize_with = "serialize_opt_u256_to_decimal",
+        deserialize_with = "deserialize_opt_u256_from_decimal"
+    )]
+    relay_value: Option<U256>,
+    #[serde(default)]
+    relay_value_mismatch: bool,
+    /// Highest bid relays received for a block other than the delivered one.
+    #[serde(
+        default,
+        serialize_with = "serialize_opt_u256_to_decimal",
+        deserialize_with = "deserialize_opt_u256_from_decimal"
+    )]
+    best_bid_value: Option<U256>,
+    /// `bid_value - best_bid_value`, how far the delivered bid beat the best competing one.
+    #[serde(
+        default,
+        serialize_with = "serialize_opt_i256_to_decimal",
+        deserialize_with = "deserialize_opt_i256_from_decimal"
+    )]
+    bid_margin: Option<I256>,
+    /// Milliseconds from the start of the slot until the relays first received the delivered
+    /// block, negative if it arrived before. Empty if the relays don't report when.
+    #[serde(default)]
+    winning_bid_ms_into_slot: Option<i64>,
+    /// Fee recipient the proposer registered with the relays, when cross-checked.
+    #[serde(default)]
+    registered_fee_recipient: Option<Address>,
+    /// The block paid a fee recipient other than the registered one.
+    #[serde(default)]
+    registration_mismatch: bool,
+    /// Fee recipient the proposer is expected to use according to `--expected-recipients`.
+    #[serde(default)]
+    expected_fee_recipient: Option<Address>,
+    /// The block paid a fee recipient other than the expected one.
+    #[serde(default)]
+    wrong_recipient: bool,
+    /// Extra input columns as a JSON object, e.g. `gas_used` or `num_tx` of relay exports.
+    #[serde(default)]
+    input_extra: Option<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct TransferData {
-    block_number: u64,
-    tx_hash: H256,
-    from: Address,
-    to: Address,
-    value: U256,
+/// Relay data is in wei whatever `--value-unit` says.
+fn deserialize_wei_from_decimal<'de, D>(deserializer: D) -> Result<U256, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    U256::from_dec_str(&s).map_err(serde::de::Error::custom)
+}
+
+fn serialize_wei_to_decimal<S>(value: &U256, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&value.to_string())
 }
 
+/// Value columns of the output are in the unit set with `--value-unit`, see [units].
 fn deserialize_u256_from_decimal<'de, D>(deserializer: D) -> Result<U256, D::Error>
 where
     D: Deserializer<'de>,
 {
     let s = String::deserialize(deserializer)?;
-    U256::from_dec_str(&s).map_err(serde::de::Error::custom)
+    units::get()
+        .parse_u256(&s)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_opt_u256_from_decimal<'de, D>(deserializer: D) -> Result<Option<U256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) if !s.is_empty() => units::get()
+            .parse_u256(&s)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+fn serialize_opt_u256_to_decimal<S>(value: &Option<U256>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match value {
+        Some(value) => serializer.serialize_str(&units::get().format_u256(*value)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_i256_from_decimal<'de, D>(deserializer: D) -> Result<I256, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    units::get()
+        .parse_i256(&s)
+        .map_err(serde::de::Error::custom)
+}
+
+fn serialize_i256_to_decimal<S>(value: &I256, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&units::get().format_i256(*value))
+}
+
+fn deserialize_opt_i256_from_decimal<'de, D>(deserializer: D) -> Result<Option<I256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) if !s.is_empty() => units::get()
+            .parse_i256(&s)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+fn serialize_opt_i256_to_decimal<S>(value: &Option<I256>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match value {
+        Some(value) => serializer.serialize_str(&units::get().format_i256(*value)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn parse_u256_decimal(s: &str) -> Result<U256, String> {
+    U256::from_dec_str(s).map_err(|e| e.to_string())
 }
 
 fn serialize_u256_to_decimal<S>(value: &U256, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    serializer.serialize_str(&value.to_string())
+    serializer.serialize_str(&units::get().format_u256(*value))
 }
 
-fn extract_transfers(traces: &[Trace]) -> Vec<TransferData> {
-    let mut transfers = Vec::new();
-    for trace in traces {
-        if let Trace {
-            action:
-                Action::Call(Call {
-                    from,
-                    to,
-                    value,
-                    call_type: CallType::Call,
-                    ..
-                }),
-            error: None,
-            block_number,
-            transaction_hash: Some(tx_hash),
-            ..
-        } = trace
-        {
-            if value.is_zero() {
-                continue;
+fn u256_to_f64(value: U256) -> f64 {
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, limb| acc * 2f64.powi(64) + *limb as f64)
+}
+
+fn i256_to_f64(value: I256) -> f64 {
+    if value.is_negative() {
+        -u256_to_f64(value.unsigned_abs())
+    } else {
+        u256_to_f64(value.into_raw())
+    }
+}
+
+fn gwei_to_wei(gwei: U256) -> U256 {
+    gwei * U256::exp10(9)
+}
+
+/// Wei as ETH with a fixed 18 decimals, so the value stays exact.
+fn format_eth(wei: impl Into<ethers::utils::ParseUnits>) -> String {
+    ethers::utils::format_units(wei, "ether").expect("ether is a valid unit")
+}
+
+fn date_utc(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// Fork a block was produced under, as far as it changes how the payment is found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum Era {
+    /// Proof-of-work, the coinbase is the miner and its balance includes the block reward.
+    PreMerge,
+    /// Proof-of-stake without withdrawals.
+    Paris,
+    /// Withdrawals credit the fee recipient balance.
+    Shanghai,
+    /// Blob transactions.
+    Cancun,
+}
+
+impl Era {
+    fn of<TX>(block: &Block<TX>) -> Self {
+        if !block.difficulty.is_zero() {
+            Era::PreMerge
+        } else if block.withdrawals_root.is_none() {
+            Era::Paris
+        } else if block.blob_gas_used.is_none() {
+            Era::Shanghai
+        } else {
+            Era::Cancun
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Era::PreMerge => "pre_merge",
+            Era::Paris => "paris",
+            Era::Shanghai => "shanghai",
+            Era::Cancun => "cancun",
+        }
+    }
+}
+
+/// Gas used as a percentage of the gas limit.
+fn fullness_pct(gas_used: u64, gas_limit: u64) -> f64 {
+    if gas_limit == 0 {
+        return 0.0;
+    }
+    gas_used as f64 * 100.0 / gas_limit as f64
+}
+
+/// Transactions of a block by EIP-2718 type. Unknown types are only counted in the total.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct TxTypeCounts {
+    legacy: usize,
+    eip2930: usize,
+    eip1559: usize,
+    eip4844: usize,
+    eip7702: usize,
+}
+
+impl TxTypeCounts {
+    fn of(transactions: &[Transaction]) -> Self {
+        let mut counts = Self::default();
+        for tx in transactions {
+            match tx.transaction_type.map(|typ| typ.as_u64()) {
+                None | Some(0) => counts.legacy += 1,
+                Some(1) => counts.eip2930 += 1,
+                Some(2) => counts.eip1559 += 1,
+                Some(3) => counts.eip4844 += 1,
+                Some(4) => counts.eip7702 += 1,
+                Some(_) => {}
             }
-            transfers.push(TransferData {
-                block_number: *block_number,
-                tx_hash: *tx_hash,
-                from: *from,
-                to: *to,
-                value: *value,
-            });
+        }
+        counts
+    }
+}
+
+/// Blob base fee for a block with `excess_blob_gas`, as specified by EIP-4844.
+fn blob_base_fee(excess_blob_gas: U256) -> U256 {
+    const MIN_BLOB_BASE_FEE: u64 = 1;
+    const BLOB_BASE_FEE_UPDATE_FRACTION: u64 = 3338477;
+
+    // fake_exponential(factor, numerator, denominator)
+    let factor = U256::from(MIN_BLOB_BASE_FEE);
+    let denominator = U256::from(BLOB_BASE_FEE_UPDATE_FRACTION);
+    let mut i = U256::one();
+    let mut output = U256::zero();
+    let mut accum = factor * denominator;
+    while !accum.is_zero() {
+        output += accum;
+        accum = accum * excess_blob_gas / (denominator * i);
+        i += U256::one();
+    }
+    output / denominator
+}
+
+impl OutputFileEntry {
+    /// Row written by `--missed-slots` for a slot without a block, which has no block data.
+    fn is_missed(&self) -> bool {
+        self.payment_type == "missed"
+    }
+
+    /// Whether `balance_diff` falls short of the bid by more than `tolerance` allows. Rows written
+    /// before the `underpaid` column existed have no `delta_wei` or `observed_payment` either, so
+    /// the balance change is what they are judged by.
+    fn derive_underpaid(&self, tolerance: &Tolerance) -> bool {
+        self.era != Era::PreMerge.as_str()
+            && tolerance.is_underpaid(self.bid_value, self.balance_diff)
+    }
+}
+
+fn read_output_file(path: &std::path::Path) -> eyre::Result<Vec<OutputFileEntry>> {
+    read_output_file_as(path, streams::Dialect::for_path(path))
+}
+
+/// [read_output_file] of a file written with `--delimiter` or `--no-output-header`.
+fn read_output_file_as(
+    path: &std::path::Path,
+    dialect: streams::Dialect,
+) -> eyre::Result<Vec<OutputFileEntry>> {
+    if !streams::is_stdio(path) {
+        schema::check_unit(path)?;
+    }
+    let mut reader = dialect.reader(streams::open_input(path)?);
+    let mut entries = Vec::new();
+    for entry in reader.deserialize() {
+        let entry: OutputFileEntry = entry?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// [read_output_file] for the commands counting underpayments. Rows written before the
+/// `underpaid` column existed get it derived with `tolerance` instead of counting as paid.
+fn read_output_totals(
+    path: &std::path::Path,
+    tolerance: &Tolerance,
+) -> eyre::Result<Vec<OutputFileEntry>> {
+    let mut entries = read_output_file(path)?;
+    for entry in &mut entries {
+        if entry.underpaid.is_none() {
+            entry.underpaid = Some(entry.derive_underpaid(tolerance));
         }
     }
-    transfers
+    Ok(entries)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 enum ProposerPayment {
     LastTxDirect {
+        tx_hash: H256,
         from: Address,
         to: Address,
         value: U256,
     },
+    /// `contract` is the sender itself when it is an EOA running its EIP-7702 delegated code.
     LastTxContract {
+        tx_hash: H256,
         from: Address,
         contract: Address,
         value: U256,
     },
-    Coinbase(Address),
-    Unknown,
+    /// Last tx would have paid the fee recipient but its receipt status is failed.
+    LastTxReverted {
+        tx_hash: H256,
+        from: Address,
+        to: Address,
+        value: U256,
+    },
+    /// The fee recipient built the block itself, so it is paid through priority fees and direct
+    /// transfers to the coinbase. `value` is the sum of both.
+    Coinbase {
+        coinbase: Address,
+        value: U256,
+    },
+    /// The builder didn't pay, but txs sent by others (searchers paying for inclusion) transferred
+    /// to the fee recipient. `value` is the sum over all of them, `tx_hash` and `from` are those of
+    /// the largest.
+    Searcher {
+        tx_hash: H256,
+        from: Address,
+        value: U256,
+    },
+    /// Paid through internal calls from an ERC-4337 EntryPoint, i.e. the fee recipient is the
+    /// beneficiary of bundles. `from` is the bundler, otherwise like `Searcher`.
+    EntryPoint {
+        tx_hash: H256,
+        from: Address,
+        value: U256,
+    },
+    /// Matched the classification rule `rule` from the config. `to` is the receiver of the tx,
+    /// which isn't necessarily the last one of the block.
+    Rule {
+        rule: String,
+        tx_hash: H256,
+        from: Address,
+        to: Address,
+        value: U256,
+    },
+    Unknown(UnknownReason),
+}
+
+/// Why no payment could be identified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum UnknownReason {
+    EmptyBlock,
+    /// The recipient received transfers in the block, but not from the last tx.
+    LastTxNotToRecipient,
+    /// Nothing in the block transferred value to the recipient.
+    NoMatchingTransfer,
+    /// The node could not trace the block, so internal payments can't be seen.
+    TraceUnavailable,
+    /// Proof-of-work block, proposers weren't paid by builders before the merge.
+    PreMerge,
+}
+
+impl UnknownReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UnknownReason::EmptyBlock => "empty_block",
+            UnknownReason::LastTxNotToRecipient => "last_tx_not_to_recipient",
+            UnknownReason::NoMatchingTransfer => "no_matching_transfer",
+            UnknownReason::TraceUnavailable => "trace_unavailable",
+            UnknownReason::PreMerge => "pre_merge",
+        }
+    }
 }
 
 impl ProposerPayment {
@@ -118,9 +740,58 @@ impl ProposerPayment {
             ProposerPayment::LastTxDirect { .. } | ProposerPayment::LastTxContract { .. }
         )
     }
+
+    fn is_reverted(&self) -> bool {
+        matches!(self, ProposerPayment::LastTxReverted { .. })
+    }
+
+    fn sender(&self) -> Option<Address> {
+        match self {
+            ProposerPayment::LastTxDirect { from, .. }
+            | ProposerPayment::LastTxContract { from, .. }
+            | ProposerPayment::LastTxReverted { from, .. }
+            | ProposerPayment::Searcher { from, .. }
+            | ProposerPayment::EntryPoint { from, .. }
+            | ProposerPayment::Rule { from, .. } => Some(*from),
+            ProposerPayment::Coinbase { .. } | ProposerPayment::Unknown(..) => None,
+        }
+    }
+
+    /// Sender of a payment made by the builder, which identifies it.
+    fn builder_sender(&self) -> Option<Address> {
+        match self {
+            ProposerPayment::Searcher { .. } | ProposerPayment::EntryPoint { .. } => None,
+            payment => payment.sender(),
+        }
+    }
+
+    fn tx_hash(&self) -> Option<H256> {
+        match self {
+            ProposerPayment::LastTxDirect { tx_hash, .. }
+            | ProposerPayment::LastTxContract { tx_hash, .. }
+            | ProposerPayment::LastTxReverted { tx_hash, .. }
+            | ProposerPayment::Searcher { tx_hash, .. }
+            | ProposerPayment::EntryPoint { tx_hash, .. }
+            | ProposerPayment::Rule { tx_hash, .. } => Some(*tx_hash),
+            ProposerPayment::Coinbase { .. } | ProposerPayment::Unknown(..) => None,
+        }
+    }
+
+    fn value(&self) -> Option<U256> {
+        match self {
+            ProposerPayment::LastTxDirect { value, .. }
+            | ProposerPayment::LastTxContract { value, .. }
+            | ProposerPayment::LastTxReverted { value, .. }
+            | ProposerPayment::Coinbase { value, .. }
+            | ProposerPayment::Searcher { value, .. }
+            | ProposerPayment::EntryPoint { value, .. }
+            | ProposerPayment::Rule { value, .. } => Some(*value),
+            ProposerPayment::Unknown(..) => None,
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 struct BlockProposerPaymentData {
     block_number: u64,
     fee_recipient: Address,
@@ -128,273 +799,2353 @@ struct BlockProposerPaymentData {
     fee_recipient_transfers: Vec<TransferData>,
     fee_recipient_withdrawals: Vec<Withdrawal>,
     payment: ProposerPayment,
-    balance_diff: U256,
+    /// Transfers of the payment tx to addresses other than the fee recipient, made by the same
+    /// sender as the payment.
+    payment_side_transfers: Vec<TransferData>,
+    /// Not known when the balance change is taken from a state diff.
+    balance_before: Option<U256>,
+    balance_after: Option<U256>,
+    /// Negative when the fee recipient spent more than it received in the block.
+    balance_diff: I256,
+    /// Gas fee paid by the sender of the payment tx.
+    payment_tx_fee: Option<U256>,
+    /// Priority fee paid by the payment tx, for any payment made by a tx.
+    payment_tx_priority_fee: Option<U256>,
+    /// Position of the payment tx in the block.
+    payment_tx_index: Option<usize>,
+    timestamp: u64,
+    era: Era,
+    gas_used: u64,
+    gas_limit: u64,
+    tx_count: usize,
+    tx_type_counts: TxTypeCounts,
+    base_fee_burned: U256,
+    /// Zero before Dencun.
+    blob_gas_used: u64,
+    /// Blob fees are burned in full, so unlike the priority fees they are not part of the block
+    /// value.
+    blob_fee_burned: U256,
+    /// Sum of priority fees paid to the block coinbase, excluding blob fees.
+    priority_fees: U256,
+    /// Priority fees plus value transferred to the coinbase by others in the block.
+    builder_block_value: U256,
+    /// Hash of the canonical block at the height.
+    block_hash: Option<H256>,
+    extra_data: Bytes,
+    builder: Option<String>,
+    fee_recipient_is_contract: bool,
+    /// `None` when there is no payment sender.
+    payment_from_is_contract: Option<bool>,
+    /// False when the node couldn't trace the block and internal transfers are missing.
+    trace_available: bool,
 }
 
-async fn get_block_proposer_payment_data(
-    provider: &Provider<Http>,
-    block_numer: u64,
-    fee_recipient: Address,
-    bid_value: U256,
-    block_hash: Option<H256>,
-) -> eyre::Result<BlockProposerPaymentData> {
-    let transfers = {
-        let trace = provider
-            .trace_block(BlockNumber::Number(block_numer.into()))
-            .await?;
-        let mut transfers = extract_transfers(&trace);
-        transfers.retain(|t| t.to == fee_recipient || t.from == fee_recipient);
-        transfers
-    };
+impl BlockProposerPaymentData {
+    /// Indices of the transfers in `fee_recipient_transfers` that make up the identified payment.
+    fn payment_transfer_indices(&self) -> Vec<usize> {
+        let transfers = self.fee_recipient_transfers.iter().enumerate();
+        match &self.payment {
+            ProposerPayment::LastTxDirect { tx_hash, from, .. } => transfers
+                .filter(|(_, t)| {
+                    t.tx_hash == *tx_hash && t.from == *from && t.to == self.fee_recipient
+                })
+                .map(|(i, _)| i)
+                .take(1)
+                .collect(),
+            ProposerPayment::LastTxContract { tx_hash, .. }
+            | ProposerPayment::Rule { tx_hash, .. } => transfers
+                .filter(|(_, t)| t.tx_hash == *tx_hash && t.to == self.fee_recipient)
+                .map(|(i, _)| i)
+                .collect(),
+            // only classified as such when every incoming transfer is part of the payment
+            ProposerPayment::Searcher { .. } | ProposerPayment::EntryPoint { .. } => transfers
+                .filter(|(_, t)| t.to == self.fee_recipient)
+                .map(|(i, _)| i)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 
-    let (withdrawals, payment) = {
-        let block = provider
-            .get_block_with_txs(block_numer)
-            .await?
-            .ok_or_else(|| eyre::eyre!("block not found"))?;
+    /// Other recipients of the payment tx and the total each received, as a JSON object.
+    fn payment_side_recipients(&self) -> Option<String> {
+        if self.payment_side_transfers.is_empty() {
+            return None;
+        }
+        let mut recipients: BTreeMap<Address, U256> = BTreeMap::new();
+        for transfer in &self.payment_side_transfers {
+            *recipients.entry(transfer.to).or_default() += transfer.value;
+        }
+        let recipients: BTreeMap<String, String> = recipients
+            .into_iter()
+            .map(|(to, value)| (format!("{to:?}"), units::get().format_u256(value)))
+            .collect();
+        Some(serde_json::to_string(&recipients).expect("string map serializes"))
+    }
 
-        if block_hash.is_some() {
-            if block_hash != block.hash {
-                return Err(eyre::eyre!("block hash mismatch, possible reorg"));
-            }
+    /// Transfers to or from the fee recipient other than the payment itself.
+    fn other_transfers(&self) -> Vec<&TransferData> {
+        if let ProposerPayment::Coinbase { .. } = self.payment {
+            // all of them are part of what the coinbase received or kept
+            return Vec::new();
         }
+        let payment_indices = self.payment_transfer_indices();
+        self.fee_recipient_transfers
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !payment_indices.contains(i))
+            .map(|(_, t)| t)
+            .collect()
+    }
 
-        let withdrawals = {
-            let mut withdrawals = block.withdrawals.unwrap_or_default();
-            withdrawals.retain(|w| w.address == fee_recipient);
-            withdrawals
+    /// Part of the balance change that comes from consensus layer withdrawals.
+    fn withdrawals_value(&self) -> U256 {
+        self.fee_recipient_withdrawals
+            .iter()
+            .fold(U256::zero(), |acc, w| acc + gwei_to_wei(w.amount))
+    }
+
+    /// Part of the balance change that comes from execution layer activity, i.e. everything but withdrawals.
+    fn execution_balance_diff(&self) -> I256 {
+        self.balance_diff - I256::from_raw(self.withdrawals_value())
+    }
+
+    /// `balance_diff - transfers in + transfers out - withdrawals - priority fees if coinbase`,
+    /// `None` when the block couldn't be traced.
+    fn unexplained_diff(&self) -> Option<I256> {
+        if !self.trace_available {
+            return None;
+        }
+        let explained = self.fee_recipient_transfers.iter().fold(
+            I256::from_raw(self.withdrawals_value()),
+            |acc, t| match (t.from == self.fee_recipient, t.to == self.fee_recipient) {
+                (false, true) => acc + I256::from_raw(t.value),
+                (true, false) => acc - I256::from_raw(t.value),
+                _ => acc,
+            },
+        );
+        let fees = match self.payment {
+            ProposerPayment::Coinbase { .. } => I256::from_raw(self.priority_fees),
+            _ => I256::zero(),
         };
+        Some(self.balance_diff - explained - fees)
+    }
 
-        let coinbase = block.author.unwrap_or_default();
-        let payment = if coinbase == fee_recipient {
-            ProposerPayment::Coinbase(coinbase)
-        } else {
-            if let Some(last_tx) = block.transactions.last() {
-                if last_tx.to == Some(fee_recipient) {
-                    ProposerPayment::LastTxDirect {
-                        from: last_tx.from,
-                        to: last_tx.to.unwrap(),
-                        value: last_tx.value,
-                    }
-                } else {
-                    if let Some(last_transfer) = transfers.last().cloned() {
-                        if last_transfer.tx_hash == last_tx.hash
-                            && last_transfer.to == fee_recipient
-                        {
-                            ProposerPayment::LastTxContract {
-                                from: last_tx.from,
-                                contract: last_tx.to.unwrap_or_default(),
-                                value: last_transfer.value,
-                            }
-                        } else {
-                            ProposerPayment::Unknown
-                        }
-                    } else {
-                        ProposerPayment::Unknown
-                    }
-                }
-            } else {
-                ProposerPayment::Unknown
+    /// Payment value net of what the fee recipient spent on it, e.g. when it sent the payment tx itself.
+    fn payment_net_value(&self) -> Option<I256> {
+        let value = I256::from_raw(self.payment.value()?);
+        match &self.payment {
+            ProposerPayment::LastTxDirect { from, .. } if *from == self.fee_recipient => {
+                // value goes out and comes back, only the fee is lost
+                Some(-I256::from_raw(self.payment_tx_fee.unwrap_or_default()))
             }
-        };
-        (withdrawals, payment)
-    };
+            ProposerPayment::LastTxReverted { .. } => Some(I256::zero()),
+            ProposerPayment::Coinbase { .. } => {
+                // whatever the coinbase sent on within the block was kept by the builder
+                let sent = self
+                    .fee_recipient_transfers
+                    .iter()
+                    .filter(|t| t.from == self.fee_recipient)
+                    .fold(U256::zero(), |acc, t| acc + t.value);
+                Some(value - I256::from_raw(sent))
+            }
+            _ => Some(value),
+        }
+    }
 
-    let balance_diff = {
-        let balance_before = provider
-            .get_balance(fee_recipient, Some((block_numer - 1u64).into()))
-            .await?;
-        let balance_after = provider
-            .get_balance(fee_recipient, Some(block_numer.into()))
-            .await?;
+    /// Best estimate of what the proposer was paid: the identified payment if there is one,
+    /// otherwise the execution layer balance change.
+    fn observed_payment(&self) -> I256 {
+        self.payment_net_value()
+            .unwrap_or_else(|| self.execution_balance_diff())
+    }
 
-        balance_after
-            .checked_sub(balance_before)
-            .unwrap_or_default()
-    };
+    fn delta(&self) -> I256 {
+        self.observed_payment() - I256::from_raw(self.bid_value)
+    }
 
-    Ok(BlockProposerPaymentData {
-        block_number: block_numer,
-        fee_recipient,
+    /// What the builder kept of the block value after paying the proposer.
+    fn builder_margin(&self) -> I256 {
+        I256::from_raw(self.builder_block_value) - self.observed_payment()
+    }
+
+    /// Whether the fee recipient is the builder: it is the coinbase, pays itself or is a known
+    /// builder payout address.
+    fn vertically_integrated(&self, builders: &[builders::KnownBuilder]) -> bool {
+        matches!(self.payment, ProposerPayment::Coinbase { .. })
+            || self.payment.builder_sender() == Some(self.fee_recipient)
+            || builders::is_builder_address(builders, self.fee_recipient)
+    }
+
+    fn payment_ratio(&self) -> Option<f64> {
+        if self.bid_value.is_zero() {
+            return None;
+        }
+        Some(i256_to_f64(self.observed_payment()) / u256_to_f64(self.bid_value))
+    }
+}
+
+async fn get_block_proposer_payment_data(
+    provider: &EthProvider,
+    options: &ProcessingOptions,
+    block_numer: u64,
+    fee_recipient: Address,
+    bid_value: U256,
+    block_hash: Option<H256>,
+) -> eyre::Result<BlockProposerPaymentData> {
+    let Some(cache) = &options.results_cache else {
+        return compute_block_proposer_payment_data(
+            provider,
+            options,
+            block_numer,
+            fee_recipient,
+            bid_value,
+        )
+        .await;
+    };
+    if let Some(mut data) = cache
+        .get(options, block_numer, fee_recipient, block_hash)
+        .await
+    {
+        data.bid_value = bid_value;
+        data.builder = builders::identify_builder(
+            &options.chain.builders,
+            &data.extra_data,
+            data.payment.builder_sender(),
+        );
+        return Ok(data);
+    }
+    let data = compute_block_proposer_payment_data(
+        provider,
+        options,
+        block_numer,
+        fee_recipient,
+        bid_value,
+    )
+    .await?;
+    // a later run may be able to trace the block
+    if data.trace_available {
+        cache.put(options, &data).await?;
+    }
+    Ok(data)
+}
+
+async fn compute_block_proposer_payment_data(
+    provider: &EthProvider,
+    options: &ProcessingOptions,
+    block_numer: u64,
+    fee_recipient: Address,
+    bid_value: U256,
+) -> eyre::Result<BlockProposerPaymentData> {
+    // the round trips don't depend on each other, except that the filter backend needs the
+    // block to know which addresses to trace
+    let trace_with_block = options.trace_backend != trace::TraceBackend::Filter;
+    let (state, traces, state_diff_change) = tokio::join!(
+        rpc::fetch_block_state(
+            provider,
+            block_numer,
+            fee_recipient,
+            options.balance_source,
+            if options.light_blocks {
+                // the last tx and every tx a rule looks at
+                let last_txs = options.rules.iter().map(|rule| rule.last_txs).max();
+                rpc::TxBodies::Last(last_txs.unwrap_or(1).max(1))
+            } else {
+                rpc::TxBodies::All
+            },
+        ),
+        async {
+            if trace_with_block {
+                Some(trace::fetch_traces(provider, options.trace_backend, block_numer, &[]).await)
+            } else {
+                None
+            }
+        },
+        async {
+            match options.balance_source {
+                rpc::BalanceSource::StateDiff => {
+                    Some(rpc::state_diff_balance_change(provider, block_numer, fee_recipient).await)
+                }
+                rpc::BalanceSource::GetBalance => None,
+            }
+        },
+    );
+    let rpc::BlockState {
+        block,
+        receipts,
+        balance_before,
+        balance_after,
+        code: fee_recipient_code,
+        bodies_from,
+    } = state?;
+    let block = block.ok_or_else(|| eyre::eyre!("block not found"))?;
+
+    let traces = match traces {
+        Some(traces) => traces,
+        None => {
+            // the payment, the builder value and split payments only involve transfers from or
+            // to these
+            let mut traced_addresses = vec![fee_recipient];
+            traced_addresses.extend(block.author);
+            traced_addresses.extend(block.transactions.last().and_then(|tx| tx.to));
+            trace::fetch_traces(
+                provider,
+                options.trace_backend,
+                block_numer,
+                &traced_addresses,
+            )
+            .await
+        }
+    };
+    // a node that can't trace the block still lets us check direct payments and balances
+    let traces = match traces {
+        Ok(traces) => Some(traces),
+        Err(e) if trace::is_trace_unavailable(&e) => {
+            tracing::warn!(error = %format!("{e:#}"), "block trace unavailable");
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    let trace_available = traces.is_some();
+    let block_transfers = match &traces {
+        Some(traces) => traces.transfers(&options.transfer_actions, block_numer)?,
+        None => Vec::new(),
+    };
+
+    if let Some(archive) = &options.archive {
+        archive
+            .write(&archive::ArchivedEntry {
+                block_number: block_numer,
+                fee_recipient,
+                block: &block,
+                receipts: &receipts,
+                balance_before,
+                balance_after,
+                fee_recipient_code: &fee_recipient_code,
+                bodies_from,
+                traces: traces.as_ref(),
+            })
+            .await?;
+    }
+
+    if receipts.len() != block.transactions.len() {
+        return Err(eyre::eyre!("block receipts don't match block transactions"));
+    }
+    let base_fee = block.base_fee_per_gas.unwrap_or_default();
+    let blob_gas_used = block.blob_gas_used.unwrap_or_default();
+    let priority_fees = receipts.iter().fold(U256::zero(), |acc, r| {
+        let gas_price = r.effective_gas_price.unwrap_or_default();
+        acc + r.gas_used.unwrap_or_default() * gas_price.saturating_sub(base_fee)
+    });
+
+    // what the builder collected before paying the proposer
+    let builder_block_value = {
+        let coinbase = block.author.unwrap_or_default();
+        block_transfers
+            .iter()
+            .filter(|t| t.to == coinbase && t.from != coinbase)
+            .fold(priority_fees, |acc, t| acc + t.value)
+    };
+    let transfers: Vec<TransferData> = block_transfers
+        .iter()
+        .filter(|t| t.to == fee_recipient || t.from == fee_recipient)
+        .cloned()
+        .collect();
+
+    let era = Era::of(&block);
+    let withdrawals = match era {
+        Era::PreMerge | Era::Paris => Vec::new(),
+        Era::Shanghai | Era::Cancun => {
+            let mut withdrawals = block.withdrawals.clone().unwrap_or_default();
+            withdrawals.retain(|w| options.chain.native_withdrawals && w.address == fee_recipient);
+            withdrawals
+        }
+    };
+
+    let (payment, transfers, payment_tx_fee) = {
+        let coinbase = block.author.unwrap_or_default();
+        let payment = if era == Era::PreMerge {
+            // the miner reward would pass for a coinbase payment
+            ProposerPayment::Unknown(UnknownReason::PreMerge)
+        } else if let Some(payment) =
+            rules::rule_payment(&options.rules, &block, &receipts, &transfers, fee_recipient)
+        {
+            payment
+        } else if coinbase == fee_recipient {
+            ProposerPayment::Coinbase {
+                coinbase,
+                value: transfers
+                    .iter()
+                    .filter(|t| t.to == fee_recipient)
+                    .fold(priority_fees, |acc, t| acc + t.value),
+            }
+        } else {
+            if let Some(last_tx) = block.transactions.last() {
+                if last_tx.to == Some(fee_recipient) {
+                    ProposerPayment::LastTxDirect {
+                        tx_hash: last_tx.hash,
+                        from: last_tx.from,
+                        to: last_tx.to.unwrap(),
+                        value: last_tx.value,
+                    }
+                } else {
+                    // the payment can reach the recipient through any number of contracts
+                    // (multisend, disperse, ...) and be split over several internal transfers
+                    let payment_transfers: Vec<&TransferData> = transfers
+                        .iter()
+                        .filter(|t| t.tx_hash == last_tx.hash && t.to == fee_recipient)
+                        .collect();
+                    if !payment_transfers.is_empty() {
+                        ProposerPayment::LastTxContract {
+                            tx_hash: last_tx.hash,
+                            from: last_tx.from,
+                            contract: last_tx.to.unwrap_or_default(),
+                            value: payment_transfers
+                                .iter()
+                                .fold(U256::zero(), |acc, t| acc + t.value),
+                        }
+                    } else if let Some(value) =
+                        delegation::self_call_value_to(last_tx, fee_recipient)
+                            .filter(|_| !trace_available)
+                    {
+                        // the sender's delegated code pays out of its own balance, which only the
+                        // calldata shows without traces
+                        ProposerPayment::LastTxContract {
+                            tx_hash: last_tx.hash,
+                            from: last_tx.from,
+                            contract: last_tx.from,
+                            value,
+                        }
+                    } else if let Some(payment) =
+                        entry_point_payment(&block, &receipts, &transfers, fee_recipient)
+                    {
+                        payment
+                    } else if let Some(payment) =
+                        searcher_payment(&block, &receipts, &transfers, fee_recipient)
+                    {
+                        payment
+                    } else if transfers.iter().any(|t| t.to == fee_recipient) {
+                        ProposerPayment::Unknown(UnknownReason::LastTxNotToRecipient)
+                    } else if trace_available {
+                        ProposerPayment::Unknown(UnknownReason::NoMatchingTransfer)
+                    } else {
+                        ProposerPayment::Unknown(UnknownReason::TraceUnavailable)
+                    }
+                }
+            } else {
+                ProposerPayment::Unknown(UnknownReason::EmptyBlock)
+            }
+        };
+
+        // traces of a reverted tx can still contain successful inner calls, so check the receipt
+        let mut transfers = transfers;
+        let mut payment_tx_fee = None;
+        let payment = match (payment.is_last_tx(), block.transactions.last()) {
+            (true, Some(last_tx)) => {
+                let receipt = receipts
+                    .last()
+                    .ok_or_else(|| eyre::eyre!("payment tx receipt not found"))?;
+                payment_tx_fee = Some(
+                    receipt.gas_used.unwrap_or_default()
+                        * receipt
+                            .effective_gas_price
+                            .or(last_tx.gas_price)
+                            .unwrap_or_default(),
+                );
+                if receipt.status == Some(0u64.into()) {
+                    transfers.retain(|t| t.tx_hash != last_tx.hash);
+                    match payment {
+                        ProposerPayment::LastTxDirect {
+                            tx_hash,
+                            from,
+                            to,
+                            value,
+                        } => ProposerPayment::LastTxReverted {
+                            tx_hash,
+                            from,
+                            to,
+                            value,
+                        },
+                        ProposerPayment::LastTxContract {
+                            tx_hash,
+                            from,
+                            contract,
+                            value,
+                        } => ProposerPayment::LastTxReverted {
+                            tx_hash,
+                            from,
+                            to: contract,
+                            value,
+                        },
+                        payment => payment,
+                    }
+                } else {
+                    payment
+                }
+            }
+            _ => payment,
+        };
+        (payment, transfers, payment_tx_fee)
+    };
+
+    // a builder splitting the bid pays others from the same sender within the payment tx
+    let payment_side_transfers = match block.transactions.last() {
+        Some(last_tx) if payment.is_last_tx() => {
+            let senders: Vec<Address> = transfers
+                .iter()
+                .filter(|t| t.tx_hash == last_tx.hash && t.to == fee_recipient)
+                .map(|t| t.from)
+                .collect();
+            block_transfers
+                .into_iter()
+                .filter(|t| {
+                    t.tx_hash == last_tx.hash
+                        && senders.contains(&t.from)
+                        && t.to != fee_recipient
+                        // change returned to the builder
+                        && t.to != last_tx.from
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+
+    let balance_diff = match (balance_before, balance_after) {
+        (Some(before), Some(after)) => I256::from_raw(after) - I256::from_raw(before),
+        _ => {
+            let withdrawn = withdrawals
+                .iter()
+                .fold(U256::zero(), |acc, w| acc + gwei_to_wei(w.amount));
+            let change = match state_diff_change {
+                Some(change) => change?,
+                None => {
+                    rpc::state_diff_balance_change(provider, block_numer, fee_recipient).await?
+                }
+            };
+            change + I256::from_raw(withdrawn)
+        }
+    };
+    let payment_tx_index = payment
+        .tx_hash()
+        .and_then(|tx_hash| block.transactions.iter().position(|tx| tx.hash == tx_hash));
+    let payment_tx_priority_fee = payment_tx_index.and_then(|index| {
+        let receipt = &receipts[index];
+        let gas_price = receipt
+            .effective_gas_price
+            .or(block.transactions[index].gas_price)?;
+        Some(receipt.gas_used.unwrap_or_default() * gas_price.saturating_sub(base_fee))
+    });
+    let payment_from_is_contract = match payment.sender() {
+        Some(from) => Some(delegation::is_contract_code(
+            &provider
+                .get_code(from, Some(BlockNumber::Number(block_numer.into()).into()))
+                .await?,
+        )),
+        None => None,
+    };
+
+    Ok(BlockProposerPaymentData {
+        block_number: block_numer,
+        fee_recipient,
         bid_value,
         fee_recipient_transfers: transfers,
         fee_recipient_withdrawals: withdrawals,
+        builder: builders::identify_builder(
+            &options.chain.builders,
+            &block.extra_data,
+            payment.builder_sender(),
+        ),
         payment,
+        payment_side_transfers,
+        balance_before,
+        balance_after,
         balance_diff,
+        payment_tx_fee,
+        payment_tx_priority_fee,
+        payment_tx_index,
+        timestamp: block.timestamp.as_u64(),
+        era,
+        gas_used: block.gas_used.as_u64(),
+        gas_limit: block.gas_limit.as_u64(),
+        tx_count: block.transactions.len(),
+        tx_type_counts: TxTypeCounts::of(&block.transactions),
+        base_fee_burned: base_fee * block.gas_used,
+        blob_gas_used: blob_gas_used.as_u64(),
+        blob_fee_burned: blob_gas_used * blob_base_fee(block.excess_blob_gas.unwrap_or_default()),
+        priority_fees,
+        builder_block_value,
+        block_hash: block.hash,
+        extra_data: block.extra_data,
+        fee_recipient_is_contract: delegation::is_contract_code(&fee_recipient_code),
+        payment_from_is_contract,
+        trace_available,
     })
 }
 
+/// ERC-4337 EntryPoint deployments, v0.6 and v0.7.
+const ENTRY_POINTS: &[&str] = &[
+    "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789",
+    "0x0000000071727De22E5E9d8BAf0edAc6f37da032",
+];
+
+/// Payment made by searchers when every transfer to the fee recipient comes from a successful
+/// tx sent neither by the coinbase nor by the sender of the last tx, the two addresses a builder
+/// pays from.
+fn searcher_payment(
+    block: &Block<Transaction>,
+    receipts: &[TransactionReceipt],
+    transfers: &[TransferData],
+    fee_recipient: Address,
+) -> Option<ProposerPayment> {
+    let coinbase = block.author.unwrap_or_default();
+    let builder_sender = block.transactions.last().map(|tx| tx.from);
+    let (tx_hash, from, value) =
+        payment_by_tx(block, receipts, transfers, fee_recipient, |tx, _| {
+            tx.from != coinbase && Some(tx.from) != builder_sender
+        })?;
+    Some(ProposerPayment::Searcher {
+        tx_hash,
+        from,
+        value,
+    })
+}
+
+/// Payment made by ERC-4337 bundles when every transfer to the fee recipient comes from an
+/// EntryPoint in a successful tx.
+fn entry_point_payment(
+    block: &Block<Transaction>,
+    receipts: &[TransactionReceipt],
+    transfers: &[TransferData],
+    fee_recipient: Address,
+) -> Option<ProposerPayment> {
+    let entry_points: Vec<Address> = ENTRY_POINTS.iter().map(|a| a.parse().unwrap()).collect();
+    let (tx_hash, from, value) =
+        payment_by_tx(block, receipts, transfers, fee_recipient, |_, transfer| {
+            entry_points.contains(&transfer.from)
+        })?;
+    Some(ProposerPayment::EntryPoint {
+        tx_hash,
+        from,
+        value,
+    })
+}
+
+/// Sums the transfers to the fee recipient if all of them are in successful txs and `accept`ed,
+/// returning the hash and sender of the tx with the largest part next to the total.
+fn payment_by_tx(
+    block: &Block<Transaction>,
+    receipts: &[TransactionReceipt],
+    transfers: &[TransferData],
+    fee_recipient: Address,
+    accept: impl Fn(&Transaction, &TransferData) -> bool,
+) -> Option<(H256, Address, U256)> {
+    let mut by_tx: Vec<(H256, Address, U256)> = Vec::new();
+    for transfer in transfers.iter().filter(|t| t.to == fee_recipient) {
+        let (tx, receipt) = block
+            .transactions
+            .iter()
+            .zip(receipts)
+            .find(|(tx, _)| tx.hash == transfer.tx_hash)?;
+        if receipt.status == Some(0u64.into()) || !accept(tx, transfer) {
+            return None;
+        }
+        match by_tx.iter_mut().find(|(hash, ..)| *hash == tx.hash) {
+            Some((_, _, value)) => *value += transfer.value,
+            None => by_tx.push((tx.hash, tx.from, transfer.value)),
+        }
+    }
+    let (tx_hash, from, _) = *by_tx.iter().max_by_key(|(_, _, value)| *value)?;
+    let value = by_tx
+        .iter()
+        .fold(U256::zero(), |acc, (_, _, value)| acc + *value);
+    Some((tx_hash, from, value))
+}
+
+/// Shortfall below the bid that is still not considered an underpayment.
+#[derive(Debug, Clone, Copy, Default, clap::Args)]
+struct Tolerance {
+    /// Absolute tolerance in wei.
+    #[clap(long = "tolerance-wei", value_parser = parse_u256_decimal, default_value = "0")]
+    wei: U256,
+    /// Relative tolerance as a percentage of the bid value; the larger of the two applies.
+    #[clap(long = "tolerance-pct", default_value = "0")]
+    pct: f64,
+    /// Absolute amount in wei the observed payment has to exceed the bid by to fill `overpaid_by`.
+    #[clap(long = "overpaid-threshold-wei", value_parser = parse_u256_decimal, default_value = "0")]
+    overpaid_wei: U256,
+    /// Relative overpayment threshold as a percentage of the bid value; the larger of the two
+    /// applies.
+    #[clap(long = "overpaid-threshold-pct", default_value = "0")]
+    overpaid_pct: f64,
+}
+
+/// The larger of `wei` and `pct` percent of `bid_value`.
+fn threshold(bid_value: U256, wei: U256, pct: f64) -> U256 {
+    // percentage with 4 decimal places of precision
+    let relative = bid_value * U256::from((pct * 10_000.0) as u64) / U256::from(1_000_000u64);
+    relative.max(wei)
+}
+
+impl Tolerance {
+    fn allowed_shortfall(&self, bid_value: U256) -> U256 {
+        threshold(bid_value, self.wei, self.pct)
+    }
+
+    /// Excess of `observed` over the bid, `None` unless it is above the overpayment threshold.
+    fn overpaid_by(&self, bid_value: U256, observed: I256) -> Option<U256> {
+        let excess = observed - I256::from_raw(bid_value);
+        let threshold = threshold(bid_value, self.overpaid_wei, self.overpaid_pct);
+        (excess > I256::from_raw(threshold)).then(|| excess.into_raw())
+    }
+
+    fn is_underpaid(&self, bid_value: U256, observed: I256) -> bool {
+        observed + I256::from_raw(self.allowed_shortfall(bid_value)) < I256::from_raw(bid_value)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct ProcessingOptions {
+    chain: chain::ChainProfile,
+    tolerance: Tolerance,
+    trace_backend: trace::TraceBackend,
+    balance_source: rpc::BalanceSource,
+    light_blocks: bool,
+    transfer_actions: trace::TransferActions,
+    recipients: recipients::RecipientRegistry,
+    labels: labels::Labels,
+    entities: entities::Entities,
+    beacon: Option<std::sync::Arc<beacon::BeaconClient>>,
+    alerter: Option<alerts::Alerter>,
+    clickhouse: Option<clickhouse::ClickHouseSink>,
+    /// Relay recorded for input rows without a `relay` column.
+    relay_name: Option<String>,
+    results_cache: Option<results::ResultsCache>,
+    /// Keep the raw data every entry is computed from.
+    archive: Option<archive::Archive>,
+    /// Re-query relays for the delivered value of every slot.
+    relay_check: Option<relays::RelayCheck>,
+    /// Look up the best competing bid of every slot.
+    bid_traces: Option<relays::BidTraces>,
+    /// Compare the fee recipient of every block to the one its proposer registered.
+    registration_check: Option<relays::RegistrationCheck>,
+    /// Fee recipients validators are expected to be paid at.
+    expected_recipients: Option<expected::ExpectedRecipients>,
+    /// Abort the run on the first entry that fails instead of skipping it.
+    strict: bool,
+    /// How `file` runs report their progress.
+    progress: file::Progress,
+    /// Classification rules from the config, tried before the built-in heuristics.
+    rules: Vec<rules::Rule>,
+    /// ETH price for the `_usd` columns.
+    prices: Option<prices::PriceSource>,
+}
+
 #[derive(Debug, clap::Parser)]
 enum Command {
     #[clap(name = "file")]
     File {
+        /// Input csv, `-` for stdin.
         #[clap(long)]
         input: PathBuf,
+        /// Output csv, `-` for stdout.
         #[clap(long)]
         output: PathBuf,
+        #[clap(flatten)]
+        tolerance: Tolerance,
+        #[clap(flatten)]
+        filter: file::InputFilter,
+        #[clap(flatten)]
+        dialect: streams::DialectArgs,
+        /// Abort with an error on the first entry that fails to process instead of skipping it.
+        #[clap(long)]
+        strict: bool,
+        /// Relay the input was exported from, recorded on rows that don't name one.
+        #[clap(long)]
+        relay_name: Option<String>,
+        /// Relay to re-query for the value delivered in each slot, flagging rows whose input
+        /// value differs. Rows naming their relays are only checked against those.
+        #[clap(long = "cross-check-relay", value_parser = relays::parse_relay)]
+        cross_check_relays: Vec<relays::Relay>,
+        /// Relay whose received bids are searched for the best bid competing with the delivered
+        /// block, filling `best_bid_value` and `bid_margin`, and for when the delivered block was
+        /// received, filling `winning_bid_ms_into_slot`.
+        #[clap(long = "bid-trace-relay", value_parser = relays::parse_relay)]
+        bid_trace_relays: Vec<relays::Relay>,
+        /// Relay whose validator registrations are compared with the fee recipient of each block,
+        /// flagging `registration_mismatch`. Needs `--beacon-url` for the proposer pubkey.
+        #[clap(long = "registration-relay", value_parser = relays::parse_relay)]
+        registration_relays: Vec<relays::Relay>,
+        /// Csv file with `validator_index,pubkey,fee_recipient` rows, flagging `wrong_recipient`
+        /// when a block of the validator paid another fee recipient. Needs `--beacon-url`.
+        #[clap(long)]
+        expected_recipients: Option<PathBuf>,
+        /// Write a JSON report of the run to this path once it completes.
+        #[clap(long)]
+        report: Option<PathBuf>,
+        /// Write rows in input order rather than as they finish, so a slot sorted input gives a
+        /// sorted output without `finalize`. Needs an input sorted by slot.
+        #[clap(long)]
+        ordered: bool,
+        /// Write a file per `day`, `epoch` or range of `slots:N` slots instead of one output,
+        /// named after the output with the partition appended, e.g. `payments-2024-05-01.csv`.
+        /// Each partition file is resumed on its own.
+        #[clap(long, value_parser = file::parse_partition)]
+        partition_by: Option<file::Partition>,
+        /// Also write a `missed` row for every slot from `--from-slot` to `--to-slot` without a
+        /// block, found with `--beacon-url`, so the output has a row for every slot.
+        #[clap(long)]
+        missed_slots: bool,
     },
+    /// Classify a single block. Without `--fee-recipient` or `--bid-value` they are taken from
+    /// the payload the relays delivered in its slot.
     #[clap(name = "block")]
     Block {
+        /// Block number or `latest`.
+        #[clap(long, required_unless_present = "slot", conflicts_with = "slot")]
+        number: Option<String>,
+        /// Slot of the block, instead of its number. Slots no relay delivered a payload in need
+        /// `--beacon-url` to find their block.
+        #[clap(long)]
+        slot: Option<u64>,
         #[clap(long)]
-        number: u64,
+        fee_recipient: Option<Address>,
         #[clap(long)]
-        fee_recipient: Address,
+        bid_value: Option<String>,
+        /// Relay asked for the delivered payload, the default relays if none are given.
+        /// Repeatable.
+        #[clap(long = "relay", value_parser = relays::parse_relay)]
+        relays: Vec<relays::Relay>,
+        /// Print the row the `file` command would write, as JSON, instead of the report.
         #[clap(long)]
-        bid_value: String,
+        json: bool,
+        /// Annotate the addresses in the report with their ENS names, mainnet only.
+        #[clap(long, conflicts_with = "json")]
+        resolve_ens: bool,
+    },
+    /// Check an input file for problems without making any RPC calls.
+    #[clap(name = "validate")]
+    Validate {
+        #[clap(long)]
+        input: PathBuf,
+        /// Existing output to report how many rows are already processed.
+        #[clap(long)]
+        output: Option<PathBuf>,
+        #[clap(flatten)]
+        dialect: streams::DialectArgs,
+    },
+    /// Scan an output file for anomalies that usually mean a provider or heuristic problem:
+    /// spikes of `unknown` rows, runs of zero balance changes, counts that don't add up and gaps
+    /// in the slot coverage. Fails if any is found.
+    #[clap(name = "quality")]
+    Quality {
+        #[clap(long)]
+        output: PathBuf,
+        #[clap(flatten)]
+        args: quality::QualityArgs,
+    },
+    /// Run a SQL statement over output files loaded into a `results` table, e.g.
+    /// `SELECT builder, AVG(CAST(delta_wei AS DOUBLE)) FROM results GROUP BY builder`.
+    #[clap(name = "query")]
+    Query {
+        /// Output files, all with the same columns.
+        #[clap(long = "input", required = true)]
+        inputs: Vec<PathBuf>,
+        #[clap(long)]
+        sql: String,
+        #[clap(long, value_enum, default_value = "csv")]
+        format: query::QueryFormat,
+    },
+    /// Check every row of an output file against invariants and fail if any is violated, e.g. as
+    /// a nightly check of relay honesty.
+    #[clap(name = "verify")]
+    Verify {
+        #[clap(long)]
+        output: PathBuf,
+        /// Invariants to check.
+        #[clap(
+            long = "check",
+            value_enum,
+            value_delimiter = ',',
+            default_value = "underpaid,unexplained"
+        )]
+        checks: Vec<verify::Invariant>,
+        #[clap(flatten)]
+        tolerance: Tolerance,
+        /// Largest unexplained balance change in wei that still counts as explained.
+        #[clap(long, value_parser = parse_u256_decimal, default_value = "0")]
+        max_unexplained_wei: U256,
+    },
+    /// Sort an output file by slot and drop duplicate slots, keeping the newest row.
+    #[clap(name = "finalize")]
+    Finalize {
+        #[clap(long)]
+        output: PathBuf,
+    },
+    /// Combine output files from sharded runs into one sorted file without duplicate slots.
+    #[clap(name = "merge")]
+    Merge {
+        #[clap(long)]
+        output: PathBuf,
+        #[clap(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Print totals and the payment type distribution of an output file.
+    #[clap(name = "summary")]
+    Summary {
+        #[clap(long)]
+        input: PathBuf,
+        /// Tolerance of the rows written before the `underpaid` column existed.
+        #[clap(flatten)]
+        tolerance: Tolerance,
+    },
+    /// Convert an output file to a typed columnar format.
+    #[clap(name = "export")]
+    Export {
+        #[clap(long)]
+        input: PathBuf,
+        #[clap(long)]
+        output: PathBuf,
+        #[clap(long, value_enum, default_value = "arrow")]
+        format: export::ExportFormat,
+        /// Load a `bigquery` export into this `project.dataset.table`.
+        #[clap(long, requires = "bigquery_token")]
+        bigquery_table: Option<String>,
+        /// OAuth access token for the load, e.g. from `gcloud auth print-access-token`.
+        #[clap(long, env = "BIGQUERY_ACCESS_TOKEN", hide_env_values = true)]
+        bigquery_token: Option<String>,
+    },
+    /// Re-run the payment classification over the responses archived in `--cache-dir`, without any
+    /// RPC calls. Rows whose block isn't fully cached fail.
+    #[clap(name = "reclassify")]
+    Reclassify {
+        /// Input the cached run was produced from.
+        #[clap(long)]
+        input: PathBuf,
+        /// Fresh output csv, `-` for stdout.
+        #[clap(long)]
+        output: PathBuf,
+        #[clap(flatten)]
+        tolerance: Tolerance,
+        #[clap(flatten)]
+        dialect: streams::DialectArgs,
+    },
+    /// Fill in columns added since an output file was written.
+    #[clap(name = "migrate")]
+    Migrate {
+        #[clap(long)]
+        output: PathBuf,
+        /// Input the output was produced from, needed when new columns require reprocessing.
+        #[clap(long)]
+        input: Option<PathBuf>,
+        /// Thresholds of the reprocessed rows and of the `underpaid` and `overpaid_by` columns
+        /// when the output doesn't have them.
+        #[clap(flatten)]
+        tolerance: Tolerance,
+        #[clap(flatten)]
+        dialect: streams::DialectArgs,
+    },
+    /// Fill only the columns an output file is missing, keeping its stored values and fetching
+    /// the least data per row each column needs.
+    #[clap(name = "backfill-columns")]
+    BackfillColumns {
+        #[clap(long)]
+        output: PathBuf,
+        /// Input the output was produced from, needed for columns beyond the block header.
+        #[clap(long)]
+        input: Option<PathBuf>,
+        #[clap(flatten)]
+        tolerance: Tolerance,
+        #[clap(flatten)]
+        dialect: streams::DialectArgs,
+    },
+    /// Recompute a random sample of an output's rows and report any that differ from the stored
+    /// ones.
+    #[clap(name = "audit")]
+    Audit {
+        #[clap(long)]
+        output: PathBuf,
+        /// Input the output was produced from.
+        #[clap(long)]
+        input: PathBuf,
+        /// Number of rows to recompute.
+        #[clap(long, default_value = "1000")]
+        sample: usize,
+        /// Seed of the sample, random when not given.
+        #[clap(long)]
+        seed: Option<u64>,
+        #[clap(flatten)]
+        tolerance: Tolerance,
+        #[clap(flatten)]
+        dialect: streams::DialectArgs,
+    },
+    /// Fetch newly delivered payloads from relays into a local dataset usable as `file` input.
+    #[clap(name = "sync")]
+    Sync {
+        #[clap(long)]
+        output: PathBuf,
+        /// Relay to sync, as a known relay name, a url or `name=url`. Defaults to all known
+        /// relays.
+        #[clap(long = "relay", value_parser = relays::parse_relay)]
+        relays: Vec<relays::Relay>,
+    },
+    /// Serve output rows computed on demand over HTTP as JSON, `GET /slot/{slot}` and
+    /// `GET /block/{number}?fee_recipient=0x…&bid_value=…`.
+    #[clap(name = "serve")]
+    Serve {
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        addr: std::net::SocketAddr,
+        /// Relay asked for the payload delivered in a slot, as a known relay name, a url or
+        /// `name=url`. Defaults to all known relays.
+        #[clap(long = "relay", value_parser = relays::parse_relay)]
+        relays: Vec<relays::Relay>,
+        /// Number of recent results kept in memory.
+        #[clap(long, default_value = "1024")]
+        cache_size: usize,
+        #[clap(flatten)]
+        tolerance: Tolerance,
+    },
+    /// Process relay exports dropped into a directory as they arrive, appending to one output.
+    /// Processed files are listed in `<output>.watched` and never picked up again.
+    #[clap(name = "watch")]
+    Watch {
+        /// Directory polled for `.csv`, `.tsv` and `.json` files, also compressed.
+        #[clap(long)]
+        dir: PathBuf,
+        /// Output csv, outside of the watched directory.
+        #[clap(long)]
+        output: PathBuf,
+        #[clap(flatten)]
+        tolerance: Tolerance,
+        #[clap(flatten)]
+        filter: file::InputFilter,
+        #[clap(flatten)]
+        dialect: streams::DialectArgs,
+        /// Relay the exports come from, recorded on rows that don't name one.
+        #[clap(long)]
+        relay_name: Option<String>,
+        /// Seconds between polls of the directory.
+        #[clap(long, default_value = "60")]
+        interval: u64,
+    },
+    /// Find which relay, if any, delivered each block of a range and print the relay market share.
+    #[clap(name = "scan")]
+    Scan {
+        #[clap(long)]
+        from_block: u64,
+        #[clap(long)]
+        to_block: u64,
+        #[clap(long)]
+        output: PathBuf,
+        /// Relay to look blocks up on, as a known relay name, a url or `name=url`. Defaults to all
+        /// known relays.
+        #[clap(long = "relay", value_parser = relays::parse_relay)]
+        relays: Vec<relays::Relay>,
+    },
+    /// Trace payouts of smoothing pools and other fee distribution contracts that blocks of an
+    /// input paid, writing one row per transfer to an operator.
+    #[clap(name = "distributions")]
+    Distributions {
+        /// Input csv of the `file` command, its fee recipients are looked up in the registry.
+        #[clap(long)]
+        input: PathBuf,
+        #[clap(long)]
+        output: PathBuf,
+        /// Fee recipient kinds traced, see `--recipient-registry`.
+        #[clap(long = "kind", value_delimiter = ',', default_value = "smoothing_pool")]
+        kinds: Vec<String>,
+        /// Contract the pools forward to whose payouts are also traced, e.g. a merkle
+        /// distributor operators claim from. Repeatable.
+        #[clap(long)]
+        follow: Vec<Address>,
+        /// Blocks after a pool block its payouts are looked for in, 28 days by default.
+        #[clap(long, default_value = "201600")]
+        window: u64,
+        #[clap(flatten)]
+        dialect: streams::DialectArgs,
+    },
+    /// Per-period totals of an output file.
+    #[clap(name = "aggregate")]
+    Aggregate {
+        #[clap(long)]
+        input: PathBuf,
+        #[clap(long, value_enum, default_value = "day")]
+        bucket: summarize::Bucket,
+        /// Tolerance of the rows written before the `underpaid` column existed.
+        #[clap(flatten)]
+        tolerance: Tolerance,
+    },
+    /// List the slots where the observed payment fell furthest below the bid.
+    #[clap(name = "top-discrepancies")]
+    TopDiscrepancies {
+        #[clap(long)]
+        input: PathBuf,
+        #[clap(long, default_value = "50")]
+        n: usize,
+    },
+    /// List the addresses each builder paid proposers from, with first and last seen slots.
+    #[clap(name = "builder-payouts")]
+    BuilderPayouts {
+        #[clap(long)]
+        input: PathBuf,
+        /// Fill `payment_from_ens` with the ENS name of each address, needs a mainnet endpoint.
+        #[clap(long)]
+        resolve_ens: bool,
+    },
+    /// Compare two output files slot by slot and print the columns that differ.
+    #[clap(name = "diff")]
+    Diff {
+        #[clap(long)]
+        left: PathBuf,
+        #[clap(long)]
+        right: PathBuf,
+        /// Only compare these columns, e.g. `payment_type,balance_diff,transfers`.
+        #[clap(long, value_delimiter = ',')]
+        columns: Vec<String>,
+    },
+    /// Percentiles and histograms of per-block metrics of an output file, by payment type.
+    #[clap(name = "stats")]
+    Stats {
+        #[clap(long)]
+        input: PathBuf,
+        #[clap(long, value_enum, default_value = "csv")]
+        format: stats::StatsFormat,
+        /// Number of histogram bins.
+        #[clap(long, default_value = "20")]
+        bins: usize,
+    },
+    /// Aggregate an output file into per-group totals.
+    #[clap(name = "summarize")]
+    Summarize {
+        #[clap(long)]
+        input: PathBuf,
+        #[clap(long, value_enum, default_value = "builder")]
+        group_by: summarize::GroupBy,
+        /// Tolerance of the rows written before the `underpaid` column existed.
+        #[clap(flatten)]
+        tolerance: Tolerance,
+    },
+    /// Per fee recipient totals of an output file: blocks, bids, observed payments, withdrawals
+    /// and the worst shortfall.
+    #[clap(name = "by-recipient")]
+    ByRecipient {
+        #[clap(long)]
+        input: PathBuf,
+        /// Tolerance of the rows written before the `underpaid` column existed.
+        #[clap(flatten)]
+        tolerance: Tolerance,
+    },
+    /// Download the builder registry at `--url` into `--builder-registry`, which later runs then
+    /// read instead of the bundled builders.
+    #[clap(name = "update-builders")]
+    UpdateBuilders {
+        #[clap(long, env = "BUILDER_REGISTRY_URL")]
+        url: String,
+    },
+    /// Render an output file as an HTML or Markdown report with the headline numbers,
+    /// per-builder and per-relay tables, the largest shortfalls and charts.
+    #[clap(name = "report")]
+    Report {
+        #[clap(long)]
+        input: PathBuf,
+        /// Written to stdout without one.
+        #[clap(long)]
+        output: Option<PathBuf>,
+        #[clap(long, value_enum, default_value = "html")]
+        format: render::ReportFormat,
+        /// Rows in the list of the largest shortfalls.
+        #[clap(long, default_value = "20")]
+        worst: usize,
+        /// Tolerance of the rows written before the `underpaid` column existed.
+        #[clap(flatten)]
+        tolerance: Tolerance,
     },
 }
 
 #[derive(Debug, clap::Parser)]
+#[clap(subcommand_required = true)]
 struct Cli {
+    /// Taken out by `main` so the subcommands can still borrow the global options.
     #[clap(subcommand)]
-    command: Command,
+    command: Option<Command>,
+    /// TOML file with defaults for the options below.
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+    /// `mainnet`, `holesky`, `gnosis` or a path to a TOML chain profile.
+    #[clap(long, default_value = "mainnet")]
+    chain: String,
     #[clap(long, env = "ETH_RPC_URL")]
-    eth_rpc_url: String,
+    eth_rpc_url: Option<String>,
+    /// Maximum number of entries processed concurrently; lowered automatically while the
+    /// provider is throttling.
     #[clap(long, env = "ETH_RPC_PAR", default_value = "10")]
     rpc_parallel: usize,
+    /// Proxy for RPC, relay and all other HTTP traffic, e.g. `socks5://127.0.0.1:1080`.
+    #[clap(long, env = "ALL_PROXY")]
+    proxy: Option<String>,
+    /// Header sent with every HTTP RPC request, as `Name: value`. Repeatable.
+    #[clap(long = "rpc-header", value_parser = rpc::parse_header)]
+    rpc_headers: Vec<rpc::Header>,
+    /// Bearer token sent as the `Authorization` header of HTTP RPC requests.
+    #[clap(long, env = "ETH_RPC_TOKEN", hide_env_values = true)]
+    rpc_token: Option<String>,
+    /// Seconds before an RPC request is abandoned, and retried unless it's a trace.
+    #[clap(long, default_value = "30")]
+    rpc_timeout: u64,
+    /// Times an RPC call other than a trace is sent again after a timeout, transport error or
+    /// rate limit, keeping the data its entry already fetched.
+    #[clap(long, default_value = "3")]
+    rpc_retries: u32,
+    /// Seconds before a block trace request is abandoned, traces are much slower than other calls.
+    #[clap(long, default_value = "300")]
+    trace_timeout: u64,
+    /// Tracing API used to extract internal value transfers.
+    #[clap(long, value_enum, default_value = "parity")]
+    trace_backend: trace::TraceBackend,
+    /// How the balance change of the fee recipient is obtained.
+    #[clap(long, value_enum, default_value = "get-balance")]
+    balance_source: rpc::BalanceSource,
+    /// Fetch blocks with tx hashes only plus the bodies of the last txs, which are all the
+    /// classification reads in full. The other txs are filled in from their receipts, much less
+    /// data for busy blocks.
+    #[clap(long)]
+    light_blocks: bool,
+    /// Trace actions whose value counts as a transfer.
+    #[clap(long, value_enum, value_delimiter = ',', default_value = "call")]
+    transfer_actions: Vec<trace::TransferAction>,
+    /// Cache raw trace, block, receipt and balance responses in this directory.
+    #[clap(long)]
+    cache_dir: Option<PathBuf>,
+    /// Ignore cached responses and re-fetch (the cache is still updated).
+    #[clap(long, requires = "cache_dir")]
+    refresh: bool,
+    /// Answer every JSON-RPC call from responses recorded in this directory, without an
+    /// endpoint, for deterministic runs. Beacon and relay APIs are still called.
+    #[clap(long, conflicts_with = "cache_dir")]
+    fixtures: Option<PathBuf>,
+    /// Call the endpoint and record every response into `--fixtures` instead of replaying them.
+    #[clap(long, requires = "fixtures")]
+    record: bool,
+    /// Store computed payment data per block and fee recipient in this directory, so blocks shared
+    /// by several inputs are only traced once.
+    #[clap(long)]
+    results_cache_dir: Option<PathBuf>,
+    /// Unit of the value columns written to and read from outputs. Resuming or reading an output
+    /// needs the unit it was written in.
+    #[clap(long, value_enum, default_value = "wei")]
+    value_unit: units::ValueUnit,
+    /// Write the block, receipts, balances and traces each entry is computed from to this
+    /// directory as gzipped JSON, so classifications can be audited later.
+    #[clap(long)]
+    archive_dir: Option<PathBuf>,
+    /// Log as JSON lines instead of human readable text. The level is set with `RUST_LOG`.
+    #[clap(long)]
+    log_json: bool,
+    /// Don't draw a progress bar, e.g. when stderr is redirected to a log.
+    #[clap(long)]
+    no_progress: bool,
+    /// Print a JSON line with the processed count, rate and ETA to stderr every this many seconds
+    /// instead of drawing a progress bar, for orchestrators capturing stderr.
+    #[clap(long, value_name = "SECONDS", conflicts_with = "no_progress")]
+    progress_json: Option<u64>,
+    /// Csv file with `address,kind` rows extending the bundled registry of fee recipient contracts.
+    #[clap(long)]
+    recipient_registry: Option<PathBuf>,
+    /// Csv file with `address,label` rows used for the label columns.
+    #[clap(long)]
+    labels: Option<PathBuf>,
+    /// Csv file with `validator_index,pubkey,fee_recipient,entity` rows naming the staking entity
+    /// of proposers for the `entity` column, on top of the bundled fee recipients of pools.
+    #[clap(long)]
+    entities: Option<PathBuf>,
+    /// Builder registry written by `update-builders`. Once it exists it replaces the bundled
+    /// builders of the chain.
+    #[clap(long)]
+    builder_registry: Option<PathBuf>,
+    /// TOML file with `[[builders]]` tables replacing the builders of the same name or adding to
+    /// them, on top of the registry.
+    #[clap(long)]
+    builder_overrides: Option<PathBuf>,
+    /// Beacon node API used for the proposer index and pubkey columns.
+    #[clap(long, env = "BEACON_URL")]
+    beacon_url: Option<String>,
+    /// ETH price source for the `bid_value_usd` and `payment_usd` columns: `chainlink` for the
+    /// ETH / USD feed as of each block, `chainlink:ADDRESS` for another feed, or a csv file with
+    /// `timestamp,price` rows, the timestamp in unix seconds or as a `YYYY-MM-DD` date.
+    #[clap(long)]
+    usd_prices: Option<String>,
+    /// Webhook (Slack or Discord compatible) notified about unknown and underpaid blocks.
+    #[clap(long, env = "ALERT_WEBHOOK_URL")]
+    alert_webhook: Option<String>,
+    /// Minimum shortfall in wei for an underpaid block to trigger an alert.
+    #[clap(long, value_parser = parse_u256_decimal, default_value = "0")]
+    alert_min_shortfall_wei: U256,
+    /// ClickHouse HTTP endpoint that also receives every row written by `file`.
+    #[clap(long, env = "CLICKHOUSE_URL")]
+    clickhouse_url: Option<String>,
+    #[clap(long, default_value = "proposer_payments")]
+    clickhouse_table: String,
+    #[clap(long, env = "CLICKHOUSE_USER")]
+    clickhouse_user: Option<String>,
+    #[clap(long, env = "CLICKHOUSE_PASSWORD", hide_env_values = true)]
+    clickhouse_password: Option<String>,
+    /// Serve Prometheus metrics on this address, e.g. `127.0.0.1:9100`.
+    #[clap(long)]
+    metrics_addr: Option<std::net::SocketAddr>,
+    /// Only set through the `[[rules]]` of `--config`.
+    #[clap(skip)]
+    rules: Vec<rules::Rule>,
+}
+
+/// Input row of the `block` subcommand for the block `number` (or `latest`) or the block of
+/// `slot`. The fee recipient and bid not given are taken from the payload the relays delivered in
+/// the slot. A block no relay delivered was built locally, so it has the coinbase as fee recipient
+/// and no bid.
+#[allow(clippy::too_many_arguments)]
+async fn lookup_block(
+    provider: &EthProvider,
+    chain: &chain::ChainProfile,
+    beacon: Option<&beacon::BeaconClient>,
+    relays: &[relays::Relay],
+    number: Option<&str>,
+    slot: Option<u64>,
+    fee_recipient: Option<Address>,
+    bid_value: Option<U256>,
+) -> eyre::Result<BoostRelayDataEntry> {
+    let (slot, number) = match (slot, number) {
+        (Some(slot), _) => (slot, None),
+        (None, Some(number)) => {
+            let id = match number {
+                "latest" => BlockNumber::Latest,
+                number => BlockNumber::Number(number.parse::<u64>()?.into()),
+            };
+            let block = provider
+                .get_block(id)
+                .await?
+                .ok_or_else(|| eyre::eyre!("block {number} not found"))?;
+            let slot = chain
+                .slot_at(block.timestamp.as_u64())
+                .ok_or_else(|| eyre::eyre!("block {number} is before the beacon chain"))?;
+            (slot, block.number.map(|number| number.as_u64()))
+        }
+        (None, None) => eyre::bail!("block needs --number or --slot"),
+    };
+    let delivered = if fee_recipient.is_none() || bid_value.is_none() {
+        relays::delivered_entry(&rpc::http_client(), relays, slot)
+            .await?
+            // a payload that didn't end up canonical
+            .filter(|entry| number.map_or(true, |number| entry.block_number == number))
+    } else {
+        None
+    };
+    let number = match (number, &delivered) {
+        (Some(number), _) => number,
+        (None, Some(entry)) => entry.block_number,
+        (None, None) => {
+            let beacon = beacon.ok_or_else(|| {
+                eyre::eyre!("no relay delivered slot {slot}, finding its block needs --beacon-url")
+            })?;
+            beacon
+                .execution_block(slot)
+                .await?
+                .ok_or_else(|| eyre::eyre!("slot {slot} has no execution block"))?
+                .0
+        }
+    };
+    let fee_recipient = match fee_recipient.or(delivered.as_ref().map(|e| e.proposer_fee_recipient))
+    {
+        Some(fee_recipient) => fee_recipient,
+        None => {
+            tracing::info!(
+                slot,
+                "no relay delivered the block, taking it as locally built"
+            );
+            provider
+                .get_block(number)
+                .await?
+                .ok_or_else(|| eyre::eyre!("block {number} not found"))?
+                .author
+                .unwrap_or_default()
+        }
+    };
+    let value = bid_value
+        .or(delivered.as_ref().map(|entry| entry.value))
+        .unwrap_or_default();
+    Ok(BoostRelayDataEntry {
+        slot,
+        proposer_fee_recipient: fee_recipient,
+        value,
+        block_hash: None,
+        block_number: number,
+        relay: delivered.as_ref().and_then(|entry| entry.relay.clone()),
+        builder_pubkey: delivered.and_then(|entry| entry.builder_pubkey),
+        extra: BTreeMap::new(),
+    })
+}
+
+/// Fills in the block number and fee recipient of an input row with only a slot from the beacon
+/// block of the slot, consensus-side datasets often have neither.
+async fn resolve_execution_block(
+    options: &ProcessingOptions,
+    mut input: BoostRelayDataEntry,
+) -> eyre::Result<BoostRelayDataEntry> {
+    if input.block_number != 0 && !input.proposer_fee_recipient.is_zero() {
+        return Ok(input);
+    }
+    let beacon = options.beacon.as_ref().ok_or_else(|| {
+        eyre::eyre!(
+            "slot {} has no block number or fee recipient, resolving them needs --beacon-url",
+            input.slot
+        )
+    })?;
+    let (block_number, fee_recipient) = beacon
+        .execution_block(input.slot)
+        .await?
+        .ok_or_else(|| eyre::eyre!("slot {} has no execution block", input.slot))?;
+    if input.block_number == 0 {
+        input.block_number = block_number;
+    }
+    if input.proposer_fee_recipient.is_zero() {
+        input.proposer_fee_recipient = fee_recipient;
+    }
+    Ok(input)
 }
 
 async fn process_input_entry(
-    provider: &Provider<Http>,
+    provider: &EthProvider,
+    options: &ProcessingOptions,
     input: BoostRelayDataEntry,
 ) -> eyre::Result<OutputFileEntry> {
+    let input = resolve_execution_block(options, input).await?;
     let data = get_block_proposer_payment_data(
-        &provider,
+        provider,
+        options,
         input.block_number,
         input.proposer_fee_recipient,
         input.value,
-        Some(input.block_hash),
+        input.block_hash,
     )
     .await?;
-    Ok(OutputFileEntry {
+    if input.block_hash.is_some() && input.block_hash != data.block_hash {
+        tracing::warn!(
+            slot = input.slot,
+            block_number = data.block_number,
+            "block hash doesn't match the canonical block, reorged or never landed"
+        );
+    }
+    if data.timestamp != options.chain.slot_timestamp(input.slot) {
+        tracing::warn!(
+            slot = input.slot,
+            block_number = data.block_number,
+            chain = %options.chain.name,
+            "block timestamp doesn't match the slot"
+        );
+    }
+    let (proposer_index, proposer_pubkey) = match &options.beacon {
+        Some(beacon) => match beacon.proposer_index(input.slot).await? {
+            Some(index) => (Some(index), Some(beacon.validator_pubkey(index).await?)),
+            None => (None, None),
+        },
+        None => (None, None),
+    };
+    let block_hash = input.block_hash.or(data.block_hash).unwrap_or_default();
+    let fee_recipient = input.proposer_fee_recipient;
+    let mut entry = output_entry(options, input, data, proposer_index, proposer_pubkey);
+    if let Some(check) = &options.relay_check {
+        entry.relay_value = check
+            .delivered_value(entry.slot, entry.relay.as_deref())
+            .await?;
+        entry.relay_value_mismatch = entry
+            .relay_value
+            .map_or(false, |value| value != entry.bid_value);
+        if entry.relay_value_mismatch {
+            tracing::warn!(
+                slot = entry.slot,
+                bid_value = %entry.bid_value,
+                relay_value = ?entry.relay_value,
+                "input value doesn't match the relay API"
+            );
+        }
+    }
+    if let Some(prices) = &options.prices {
+        let price = prices
+            .eth_usd(provider, entry.block_number, entry.timestamp)
+            .await?;
+        entry.bid_value_usd =
+            price.map(|price| prices::to_usd(I256::from_raw(entry.bid_value), price));
+        entry.payment_usd = price.map(|price| prices::to_usd(entry.observed_payment, price));
+    }
+    if let Some(bid_traces) = &options.bid_traces {
+        let bids = bid_traces.slot_bids(entry.slot, block_hash).await?;
+        entry.best_bid_value = bids.best_competing;
+        entry.bid_margin = entry
+            .best_bid_value
+            .map(|best| I256::from_raw(entry.bid_value) - I256::from_raw(best));
+        let slot_start_ms = options.chain.slot_timestamp(entry.slot) as i64 * 1000;
+        entry.winning_bid_ms_into_slot = bids
+            .delivered_received_ms
+            .map(|received| received as i64 - slot_start_ms);
+    }
+    if let (Some(check), Some(pubkey)) = (&options.registration_check, &entry.proposer_pubkey) {
+        entry.registered_fee_recipient = check.registered_fee_recipient(pubkey).await?;
+        entry.registration_mismatch = entry
+            .registered_fee_recipient
+            .map_or(false, |registered| registered != fee_recipient);
+        if entry.registration_mismatch {
+            tracing::warn!(
+                slot = entry.slot,
+                fee_recipient = ?fee_recipient,
+                registered = ?entry.registered_fee_recipient,
+                "block paid a fee recipient other than the registered one"
+            );
+        }
+    }
+    if let Some(expected) = &options.expected_recipients {
+        entry.expected_fee_recipient =
+            expected.get(entry.proposer_index, entry.proposer_pubkey.as_deref());
+        entry.wrong_recipient = entry
+            .expected_fee_recipient
+            .map_or(false, |expected| expected != fee_recipient);
+        if entry.wrong_recipient {
+            tracing::warn!(
+                slot = entry.slot,
+                fee_recipient = ?fee_recipient,
+                expected = ?entry.expected_fee_recipient,
+                "block paid a fee recipient other than the expected one"
+            );
+        }
+    }
+    Ok(entry)
+}
+
+fn account_kind(is_contract: bool) -> String {
+    if is_contract { "contract" } else { "eoa" }.to_string()
+}
+
+/// Output row of `input` from the payment data of its block.
+fn output_entry(
+    options: &ProcessingOptions,
+    input: BoostRelayDataEntry,
+    data: BlockProposerPaymentData,
+    proposer_index: Option<u64>,
+    proposer_pubkey: Option<String>,
+) -> OutputFileEntry {
+    let other_transfers = data.other_transfers();
+    let transfers_in = other_transfers
+        .iter()
+        .filter(|t| t.to == data.fee_recipient)
+        .collect::<Vec<_>>();
+    let transfers_out = other_transfers
+        .iter()
+        .filter(|t| t.from == data.fee_recipient)
+        .collect::<Vec<_>>();
+    OutputFileEntry {
         slot: input.slot,
         block_number: data.block_number,
+        block_hash_match: input.block_hash.map(|hash| Some(hash) == data.block_hash),
         bid_value: data.bid_value,
+        bid_value_eth: format_eth(data.bid_value),
+        bid_value_usd: None,
         balance_diff: data.balance_diff,
+        balance_diff_eth: format_eth(data.balance_diff),
+        balance_diff_execution: data.execution_balance_diff(),
         payment_type: match data.payment {
             ProposerPayment::LastTxDirect { .. } => "last_tx_direct".to_string(),
             ProposerPayment::LastTxContract { .. } => "last_tx_contract".to_string(),
-            ProposerPayment::Coinbase(..) => "coinbase".to_string(),
-            ProposerPayment::Unknown => "unknown".to_string(),
+            ProposerPayment::LastTxReverted { .. } => "last_tx_reverted".to_string(),
+            ProposerPayment::Coinbase { .. } => "coinbase".to_string(),
+            ProposerPayment::Searcher { .. } => "searcher".to_string(),
+            ProposerPayment::EntryPoint { .. } => "entry_point".to_string(),
+            ProposerPayment::Rule { .. } => "rule".to_string(),
+            ProposerPayment::Unknown(..) => "unknown".to_string(),
         },
-        withdrawals: data.fee_recipient_withdrawals.len(),
-        transfers: if data.payment.is_last_tx() {
-            data.fee_recipient_transfers.len() - 1
-        } else {
-            data.fee_recipient_transfers.len()
+        unknown_reason: match data.payment {
+            ProposerPayment::Unknown(reason) => Some(reason.as_str().to_string()),
+            _ => None,
+        },
+        payment_rule: match &data.payment {
+            ProposerPayment::Rule { rule, .. } => Some(rule.clone()),
+            _ => None,
         },
-        transfers_in: data
-            .fee_recipient_transfers
+        payment_reverted: data.payment.is_reverted(),
+        payment_tx_hash: data.payment.tx_hash(),
+        payment_tx_index: data.payment_tx_index,
+        payment_from: data.payment.sender(),
+        payment_from_label: data
+            .payment
+            .sender()
+            .and_then(|from| options.labels.get(from))
+            .map(str::to_string),
+        payment_from_kind: data.payment_from_is_contract.map(account_kind),
+        payment_value: data.payment.value(),
+        payment_tx_fee: data.payment_tx_fee,
+        payment_tx_priority_fee: data.payment_tx_priority_fee,
+        payment_tx_zero_priority_fee: data.payment_tx_priority_fee == Some(U256::zero()),
+        payment_side_recipients: data.payment_side_recipients(),
+        payment_net_value: data.payment_net_value(),
+        observed_payment: data.observed_payment(),
+        payment_usd: None,
+        delta_wei: data.delta(),
+        payment_ratio: data.payment_ratio(),
+        // there was no bid to compare with before the merge
+        underpaid: Some(
+            data.era != Era::PreMerge
+                && options
+                    .tolerance
+                    .is_underpaid(data.bid_value, data.observed_payment()),
+        ),
+        overpaid_by: options
+            .tolerance
+            .overpaid_by(data.bid_value, data.observed_payment())
+            .filter(|_| data.era != Era::PreMerge),
+        withdrawals: data.fee_recipient_withdrawals.len(),
+        withdrawals_value: data.withdrawals_value(),
+        transfers: other_transfers.len(),
+        transfers_in: transfers_in.len(),
+        transfers_out: transfers_out.len(),
+        transfers_in_value: transfers_in
             .iter()
-            .filter(|t| t.to == data.fee_recipient)
-            .count()
-            - if data.payment.is_last_tx() { 1 } else { 0 },
-        transfers_out: data
-            .fee_recipient_transfers
+            .fold(U256::zero(), |acc, t| acc + t.value),
+        transfers_out_value: transfers_out
             .iter()
-            .filter(|t| t.from == data.fee_recipient)
-            .count(),
+            .fold(U256::zero(), |acc, t| acc + t.value),
+        unexplained_diff: data.unexplained_diff(),
+        trace_data: data.trace_available,
+        gas_used: data.gas_used,
+        gas_limit: data.gas_limit,
+        tx_count: data.tx_count,
+        tx_count_legacy: data.tx_type_counts.legacy,
+        tx_count_eip2930: data.tx_type_counts.eip2930,
+        tx_count_eip1559: data.tx_type_counts.eip1559,
+        tx_count_eip4844: data.tx_type_counts.eip4844,
+        tx_count_eip7702: data.tx_type_counts.eip7702,
+        block_fullness_pct: fullness_pct(data.gas_used, data.gas_limit),
+        base_fee_burned: data.base_fee_burned,
+        blob_gas_used: data.blob_gas_used,
+        blob_fee_burned: data.blob_fee_burned,
+        total_priority_fees: data.priority_fees,
+        builder_block_value: data.builder_block_value,
+        builder_margin: data.builder_margin(),
+        timestamp: data.timestamp,
+        date_utc: date_utc(data.timestamp),
+        era: data.era.as_str().to_string(),
+        builder: data.builder.or_else(|| {
+            builders::builder_by_pubkey(&options.chain.builders, input.builder_pubkey.as_deref())
+        }),
+        builder_pubkey: input.builder_pubkey,
+        relay: input
+            .relay
+            .filter(|relay| !relay.is_empty())
+            .or_else(|| options.relay_name.clone()),
+        fee_recipient: Some(data.fee_recipient),
+        recipient_kind: options.recipients.kind(data.fee_recipient).to_string(),
+        fee_recipient_label: options.labels.get(data.fee_recipient).map(str::to_string),
+        fee_recipient_account_kind: account_kind(data.fee_recipient_is_contract),
+        is_contract: data.fee_recipient_is_contract,
+        vertically_integrated: data.vertically_integrated(&options.chain.builders),
+        entity: options
+            .entities
+            .get(
+                proposer_index,
+                proposer_pubkey.as_deref(),
+                Some(data.fee_recipient),
+            )
+            .map(str::to_string),
+        proposer_index,
+        proposer_pubkey,
+        input_extra: (!input.extra.is_empty())
+            .then(|| serde_json::to_string(&input.extra).expect("string map serializes")),
+        relay_value: None,
+        relay_value_mismatch: false,
+        best_bid_value: None,
+        bid_margin: None,
+        winning_bid_ms_into_slot: None,
+        registered_fee_recipient: None,
+        registration_mismatch: false,
+        expected_fee_recipient: None,
+        wrong_recipient: false,
+    }
+}
+
+fn init_logging(json: bool) {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    if json {
+        subscriber.json().init();
+    } else {
+        subscriber.init();
+    }
+}
+
+/// Exit code of a `file` run that completed but failed some entries, which a rerun retries.
+/// Errors that abort the run exit with 1.
+const EXIT_COMPLETED_WITH_FAILURES: u8 = 2;
+
+/// Options of every subcommand that processes blocks, from the global flags. Flags of a single
+/// subcommand, e.g. `--strict` of `file`, are set by its caller.
+fn processing_options(
+    cli: &Cli,
+    chain: chain::ChainProfile,
+    tolerance: Tolerance,
+) -> eyre::Result<ProcessingOptions> {
+    let prices = cli
+        .usd_prices
+        .as_deref()
+        .map(|source| prices::PriceSource::resolve(source, &chain))
+        .transpose()?;
+    Ok(ProcessingOptions {
+        chain,
+        tolerance,
+        trace_backend: cli.trace_backend,
+        balance_source: cli.balance_source,
+        light_blocks: cli.light_blocks,
+        prices,
+        transfer_actions: trace::TransferActions(cli.transfer_actions.clone()),
+        results_cache: cli
+            .results_cache_dir
+            .clone()
+            .map(|dir| results::ResultsCache { dir }),
+        archive: cli.archive_dir.clone().map(|dir| archive::Archive { dir }),
+        recipients: recipients::RecipientRegistry::load(cli.recipient_registry.as_deref())?,
+        rules: cli.rules.clone(),
+        entities: entities::Entities::load(cli.entities.as_deref())?,
+        labels: match &cli.labels {
+            Some(path) => labels::Labels::load(path)?,
+            None => Default::default(),
+        },
+        beacon: cli
+            .beacon_url
+            .as_deref()
+            .map(|url| std::sync::Arc::new(beacon::BeaconClient::new(url))),
+        alerter: cli
+            .alert_webhook
+            .clone()
+            .map(|url| alerts::Alerter::new(url, cli.alert_min_shortfall_wei)),
+        clickhouse: cli.clickhouse_url.as_deref().map(|url| {
+            clickhouse::ClickHouseSink::new(
+                url,
+                cli.clickhouse_table.clone(),
+                cli.clickhouse_user.clone(),
+                cli.clickhouse_password.clone(),
+            )
+        }),
+        progress: file::Progress::from_flags(cli.no_progress, cli.progress_json),
+        ..Default::default()
     })
 }
 
+/// Exit code of a `file` run stopped by SIGINT or SIGTERM, as a shell reports a SIGINT.
+const EXIT_INTERRUPTED: u8 = 130;
+
 #[tokio::main]
-async fn main() -> eyre::Result<()> {
-    let cli = Cli::parse();
-    let provider = Provider::try_from(cli.eth_rpc_url.as_str())?;
+async fn main() -> eyre::Result<ExitCode> {
+    let started_at = chrono::Utc::now();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(path) = cli.config.clone() {
+        config::Config::load(&path)?.apply(&mut cli, &matches)?;
+    }
+    init_logging(cli.log_json);
+    units::set(cli.value_unit);
+    if let Some(addr) = cli.metrics_addr {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr).await {
+                tracing::error!(error = %e, "metrics server failed");
+            }
+        });
+    }
+    if let Some(proxy) = &cli.proxy {
+        rpc::set_proxy(proxy)?;
+    }
+    let cache = match &cli.fixtures {
+        Some(dir) => Some(rpc::ResponseCache {
+            dir: dir.clone(),
+            refresh: cli.record,
+            fixtures: true,
+        }),
+        None => cli.cache_dir.clone().map(|dir| rpc::ResponseCache {
+            dir,
+            refresh: cli.refresh,
+            fixtures: false,
+        }),
+    };
+    let timeouts = rpc::Timeouts {
+        request: std::time::Duration::from_secs(cli.rpc_timeout),
+        trace: std::time::Duration::from_secs(cli.trace_timeout),
+    };
+    let mut headers = cli.rpc_headers.clone();
+    if let Some(token) = &cli.rpc_token {
+        headers.push(rpc::Header {
+            name: "Authorization".to_string(),
+            value: format!("Bearer {token}"),
+        });
+    }
+    let connect_options = rpc::ConnectOptions {
+        cache: cache.clone(),
+        timeouts,
+        headers,
+        retries: cli.rpc_retries,
+    };
+    let mut chain_profile = chain::ChainProfile::resolve(&cli.chain)?;
+    chain_profile.builders = builders::load(
+        chain_profile.builders,
+        &chain_profile.name,
+        cli.builder_registry.as_deref(),
+        cli.builder_overrides.as_deref(),
+    )?;
+    let replay = cache.clone().filter(|cache| cache.fixtures && !cli.record);
+    let provider = || {
+        let replayed = replay.clone().map(rpc::offline);
+        let connected = rpc::connect(cli.eth_rpc_url.as_deref(), connect_options.clone());
+        let chain = &chain_profile;
+        async move {
+            match replayed {
+                Some(provider) => Ok(provider),
+                None => {
+                    let provider = connected.await?;
+                    chain.check_chain_id(&provider).await?;
+                    Ok(provider)
+                }
+            }
+        }
+    };
 
-    match cli.command {
+    let Some(command) = cli.command.take() else {
+        unreachable!("clap requires a subcommand");
+    };
+    // returned once the command is done rather than exiting right away, so the outputs are
+    // flushed and the logs written
+    let mut exit_code = ExitCode::SUCCESS;
+    match command {
         Command::Block {
             number,
+            slot,
             fee_recipient,
             bid_value,
+            relays,
+            json,
+            resolve_ens,
         } => {
-            let bid_value = U256::from_dec_str(&bid_value)?;
-            let data =
-                get_block_proposer_payment_data(&provider, number, fee_recipient, bid_value, None)
-                    .await?;
-            println!("{:#?}", data);
-        }
-        Command::File { input, output } => {
-            let processed_entries = if output.exists() {
-                // read output file
-                let mut reader = csv::Reader::from_path(&output)?;
-                let mut entries = Vec::new();
-                for entry in reader.deserialize() {
-                    let entry: OutputFileEntry = entry?;
-                    entries.push(entry);
-                }
-                entries
+            let bid_value = bid_value
+                .map(|value| U256::from_dec_str(&value))
+                .transpose()?;
+            let relays = if relays.is_empty() {
+                relays::default_relays()
             } else {
-                Vec::new()
+                relays
             };
-
-            let processed_set = processed_entries
-                .iter()
-                .map(|e| e.slot)
-                .collect::<std::collections::HashSet<_>>();
-
-            let input = {
-                let input =
-                    csv::Reader::from_path(&input)?.into_deserialize::<BoostRelayDataEntry>();
-                let mut entries = Vec::new();
-                for entry in input {
-                    let entry = entry?;
-                    if processed_set.contains(&entry.slot) {
-                        continue;
+            let provider = provider().await?;
+            let options = processing_options(&cli, chain_profile, Default::default())?;
+            let input = lookup_block(
+                &provider,
+                &options.chain,
+                options.beacon.as_deref(),
+                &relays,
+                number.as_deref(),
+                slot,
+                fee_recipient,
+                bid_value,
+            )
+            .await?;
+            let data = get_block_proposer_payment_data(
+                &provider,
+                &options,
+                input.block_number,
+                input.proposer_fee_recipient,
+                input.value,
+                None,
+            )
+            .await?;
+            let fee_recipient = input.proposer_fee_recipient;
+            let entry = output_entry(&options, input, data.clone(), None, None);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&entry)?);
+            } else {
+                let mut names = HashMap::new();
+                if resolve_ens {
+                    let mut addresses = vec![fee_recipient];
+                    addresses.extend(data.payment.sender());
+                    for transfer in &data.fee_recipient_transfers {
+                        addresses.extend([transfer.from, transfer.to]);
+                    }
+                    let ens = ens::EnsNames::new(provider).await?;
+                    for address in addresses {
+                        if let Some(name) = ens.name(address).await {
+                            names.insert(address, name);
+                        }
+                    }
+                }
+                report::print_block(&data, &entry, &names);
+            }
+        }
+        Command::File {
+            input,
+            output,
+            tolerance,
+            filter,
+            dialect,
+            strict,
+            relay_name,
+            cross_check_relays,
+            bid_trace_relays,
+            registration_relays,
+            expected_recipients,
+            report,
+            ordered,
+            missed_slots,
+            partition_by,
+        } => {
+            let missed_slots = match (missed_slots, filter.from_slot, filter.to_slot) {
+                (false, ..) => None,
+                (true, Some(from), Some(to)) => {
+                    if streams::is_stdio(&output) {
+                        eyre::bail!("--missed-slots needs an output file");
+                    }
+                    if filter.sample.is_some() || filter.limit.is_some() || filter.shard.is_some() {
+                        eyre::bail!("--missed-slots needs every row of the range processed");
+                    }
+                    if partition_by.is_some() {
+                        eyre::bail!("--missed-slots can't be combined with --partition-by");
                     }
-                    entries.push(entry);
+                    Some((from, to))
                 }
-                entries
+                (true, ..) => eyre::bail!("--missed-slots needs --from-slot and --to-slot"),
             };
-
-            let mut output = csv::Writer::from_path(&output)?;
-            for processed in processed_entries {
-                output.serialize(processed)?;
+            let remote_output = s3::is_s3(&output).then(|| output.clone());
+            if remote_output.is_some() && partition_by.is_some() {
+                eyre::bail!("--partition-by writes a directory, it can't be an s3:// URL");
+            }
+            let input = if s3::is_s3(&input) {
+                s3::fetch_input(&input).await?
+            } else {
+                input
+            };
+            let output = match &remote_output {
+                Some(url) => s3::stage_output(url).await?,
+                None => output,
+            };
+            if !registration_relays.is_empty() && cli.beacon_url.is_none() {
+                eyre::bail!("--registration-relay needs --beacon-url for the proposer pubkeys");
+            }
+            if expected_recipients.is_some() && cli.beacon_url.is_none() {
+                eyre::bail!("--expected-recipients needs --beacon-url for the proposers");
             }
-            output.flush()?;
-
-            let progress = ProgressBar::new(input.len() as u64);
-            progress.set_style(
-                ProgressStyle::default_bar()
-                    .template(
-                        "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg} ({eta})",
-                    )
-                    .unwrap()
-                    .progress_chars("##-"),
+            let provider = provider().await?;
+            let options = std::sync::Arc::new(ProcessingOptions {
+                relay_name,
+                relay_check: (!cross_check_relays.is_empty())
+                    .then(|| relays::RelayCheck::new(cross_check_relays)),
+                bid_traces: (!bid_trace_relays.is_empty())
+                    .then(|| relays::BidTraces::new(bid_trace_relays)),
+                registration_check: (!registration_relays.is_empty())
+                    .then(|| relays::RegistrationCheck::new(registration_relays)),
+                expected_recipients: expected_recipients
+                    .as_deref()
+                    .map(expected::ExpectedRecipients::load)
+                    .transpose()?,
+                strict,
+                ..processing_options(&cli, chain_profile, tolerance)?
+            });
+            let run_report = file::run(
+                provider,
+                options.clone(),
+                &input,
+                &output,
+                filter,
+                &dialect,
+                cli.rpc_parallel,
+                ordered,
+                partition_by,
+            )
+            .await?;
+            if let (Some((from, to)), false) = (missed_slots, run_report.interrupted) {
+                let beacon = options
+                    .beacon
+                    .as_deref()
+                    .ok_or_else(|| eyre::eyre!("--missed-slots needs --beacon-url"))?;
+                let missed = file::write_missed_slots(
+                    beacon,
+                    &options.chain,
+                    &options.entities,
+                    &output,
+                    &dialect,
+                    options.clickhouse.clone(),
+                    from,
+                    to,
+                    cli.rpc_parallel,
+                )
+                .await?;
+                tracing::info!(missed, "wrote missed slots");
+            }
+            tracing::info!(
+                processed = run_report.processed,
+                skipped = run_report.skipped,
+                failed = run_report.failed,
+                unknown = run_report.unknown,
+                deferred = run_report.deferred,
+                "run completed"
             );
-            for chunk in input.chunks(cli.rpc_parallel) {
-                let mut tasks = Vec::new();
-                for entry in chunk {
-                    let provider = provider.clone();
-                    let entry = entry.clone();
-                    let progress = progress.clone();
-
-                    tasks.push(tokio::spawn(async move {
-                        let res = process_input_entry(&provider, entry).await;
-                        progress.inc(1);
-                        res
-                    }));
-                }
-                let mut processed = Vec::new();
-                for res in futures::future::join_all(tasks).await {
-                    let res = match res? {
-                        Ok(res) => res,
-                        Err(e) => {
-                            eprintln!("Error: {}", e);
-                            continue;
-                        }
-                    };
-                    processed.push(res);
-                }
-                // sort
-                processed.sort_by_key(|e| e.slot);
-                for processed in processed {
-                    output.serialize(processed)?;
+            if let Some(path) = &report {
+                run_report.write(path)?;
+            }
+            if !streams::is_stdio(&output) {
+                let rpc_url = match &cli.fixtures {
+                    Some(_) if !cli.record => None,
+                    _ => cli.eth_rpc_url.as_deref(),
+                };
+                meta::OutputMetadata::append(
+                    &output,
+                    meta::RunMetadata::new(
+                        rpc_url,
+                        &options.chain,
+                        started_at,
+                        &run_report,
+                        cli.config.as_deref(),
+                    )?,
+                )?;
+            }
+            if let Some(url) = &remote_output {
+                s3::upload_output(url).await?;
+            }
+            if run_report.interrupted {
+                if file::can_resume(&input, &output) {
+                    eprintln!(
+                        "interrupted, run the same command again to resume where this run stopped"
+                    );
+                } else {
+                    eprintln!(
+                        "interrupted, stdin input and stdout or compressed output can't be resumed"
+                    );
                 }
-                output.flush()?;
+                exit_code = ExitCode::from(EXIT_INTERRUPTED);
+            } else if run_report.failed > 0 {
+                exit_code = ExitCode::from(EXIT_COMPLETED_WITH_FAILURES);
+            }
+        }
+        Command::Validate {
+            input,
+            output,
+            dialect,
+        } => {
+            validate::validate(&input, output.as_deref(), &dialect)?;
+        }
+        Command::Quality { output, args } => {
+            quality::quality(&output, &chain_profile, &args)?;
+        }
+        Command::Query {
+            inputs,
+            sql,
+            format,
+        } => {
+            query::query(&inputs, &sql, format).await?;
+        }
+        Command::Verify {
+            output,
+            checks,
+            tolerance,
+            max_unexplained_wei,
+        } => {
+            verify::verify(&output, &checks, &tolerance, max_unexplained_wei)?;
+        }
+        Command::Finalize { output } => {
+            finalize::finalize(&output)?;
+        }
+        Command::Merge { output, inputs } => {
+            finalize::merge(&inputs, &output)?;
+        }
+        Command::Summary { input, tolerance } => {
+            summarize::summary(&input, &tolerance)?;
+        }
+        Command::Summarize {
+            input,
+            group_by,
+            tolerance,
+        } => {
+            summarize::summarize(&input, group_by, &tolerance)?;
+        }
+        Command::ByRecipient { input, tolerance } => {
+            summarize::by_recipient(&input, &tolerance)?;
+        }
+        Command::UpdateBuilders { url } => {
+            let path = cli
+                .builder_registry
+                .as_deref()
+                .ok_or_else(|| eyre::eyre!("update-builders needs --builder-registry"))?;
+            let builders = builders::update(&url, &chain_profile.name, path).await?;
+            tracing::info!(builders, "updated builder registry {}", path.display());
+        }
+        Command::Report {
+            input,
+            output,
+            format,
+            worst,
+            tolerance,
+        } => {
+            render::render(
+                &input,
+                output.as_deref(),
+                format,
+                &chain_profile,
+                worst,
+                &tolerance,
+            )?;
+        }
+        Command::Aggregate {
+            input,
+            bucket,
+            tolerance,
+        } => {
+            summarize::aggregate(&input, bucket, &chain_profile, &tolerance)?;
+        }
+        Command::TopDiscrepancies { input, n } => {
+            summarize::top_discrepancies(&input, n)?;
+        }
+        Command::BuilderPayouts { input, resolve_ens } => {
+            let ens = match resolve_ens {
+                true => Some(ens::EnsNames::new(provider().await?).await?),
+                false => None,
+            };
+            summarize::builder_payouts(&input, ens.as_ref()).await?;
+        }
+        Command::Diff {
+            left,
+            right,
+            columns,
+        } => {
+            diff::diff(&left, &right, &columns)?;
+        }
+        Command::Stats {
+            input,
+            format,
+            bins,
+        } => {
+            stats::stats(&input, format, bins)?;
+        }
+        Command::Export {
+            input,
+            output,
+            format,
+            bigquery_table,
+            bigquery_token,
+        } => {
+            if bigquery_table.is_some() && format != export::ExportFormat::Bigquery {
+                eyre::bail!("--bigquery-table needs --format bigquery");
             }
-            progress.finish();
+            export::export(&input, &output, format)?;
+            if let (Some(table), Some(token)) = (bigquery_table, bigquery_token) {
+                export::load_bigquery(&output, &table, &token).await?;
+            }
+        }
+        Command::Reclassify {
+            input,
+            output,
+            tolerance,
+            dialect,
+        } => {
+            let cache = cache.ok_or_else(|| {
+                eyre::eyre!("reclassify reads the archived responses from --cache-dir")
+            })?;
+            let options = std::sync::Arc::new(ProcessingOptions {
+                // a cached result would be returned instead of reclassifying the block
+                results_cache: None,
+                archive: None,
+                ..processing_options(&cli, chain_profile, tolerance)?
+            });
+            let run_report = file::run(
+                rpc::offline(cache),
+                options,
+                &input,
+                &output,
+                Default::default(),
+                &dialect,
+                cli.rpc_parallel,
+                false,
+                None,
+            )
+            .await?;
+            tracing::info!(
+                processed = run_report.processed,
+                failed = run_report.failed,
+                unknown = run_report.unknown,
+                "reclassify completed"
+            );
+            if run_report.interrupted {
+                exit_code = ExitCode::from(EXIT_INTERRUPTED);
+            } else if run_report.failed > 0 {
+                exit_code = ExitCode::from(EXIT_COMPLETED_WITH_FAILURES);
+            }
+        }
+        Command::Migrate {
+            output,
+            input,
+            tolerance,
+            dialect,
+        } => {
+            let provider = provider().await?;
+            let options = std::sync::Arc::new(processing_options(&cli, chain_profile, tolerance)?);
+            migrate::migrate(
+                provider,
+                options,
+                &output,
+                input.as_deref(),
+                &dialect,
+                cli.rpc_parallel,
+            )
+            .await?;
+        }
+        Command::BackfillColumns {
+            output,
+            input,
+            tolerance,
+            dialect,
+        } => {
+            let provider = provider().await?;
+            let options = std::sync::Arc::new(processing_options(&cli, chain_profile, tolerance)?);
+            migrate::backfill(
+                provider,
+                options,
+                &output,
+                input.as_deref(),
+                &dialect,
+                cli.rpc_parallel,
+            )
+            .await?;
+        }
+        Command::Audit {
+            output,
+            input,
+            sample,
+            seed,
+            tolerance,
+            dialect,
+        } => {
+            let seed = seed.unwrap_or_else(|| {
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos() as u64
+            });
+            tracing::info!(seed, "sampling {sample} rows");
+            let provider = provider().await?;
+            let options = ProcessingOptions {
+                // a cached result would be compared with itself, and the audit must not write
+                results_cache: None,
+                archive: None,
+                ..processing_options(&cli, chain_profile, tolerance)?
+            };
+            audit::audit(
+                &provider,
+                &options,
+                &output,
+                &input,
+                &dialect,
+                sample,
+                seed,
+                cli.rpc_parallel,
+            )
+            .await?;
+        }
+        Command::Sync { output, relays } => {
+            let relays = if relays.is_empty() {
+                relays::default_relays()
+            } else {
+                relays
+            };
+            relays::sync(&output, &relays).await?;
+        }
+        Command::Serve {
+            addr,
+            relays,
+            cache_size,
+            tolerance,
+        } => {
+            let relays = if relays.is_empty() {
+                relays::default_relays()
+            } else {
+                relays
+            };
+            let provider = provider().await?;
+            let options = std::sync::Arc::new(processing_options(&cli, chain_profile, tolerance)?);
+            serve::serve(addr, provider, options, relays, cache_size).await?;
+        }
+        Command::Watch {
+            dir,
+            output,
+            tolerance,
+            filter,
+            dialect,
+            relay_name,
+            interval,
+        } => {
+            let provider = provider().await?;
+            let options = std::sync::Arc::new(ProcessingOptions {
+                relay_name,
+                ..processing_options(&cli, chain_profile, tolerance)?
+            });
+            let interrupted = watch::watch(
+                provider,
+                options,
+                &dir,
+                &output,
+                filter,
+                &dialect,
+                cli.rpc_parallel,
+                std::time::Duration::from_secs(interval),
+            )
+            .await?;
+            if interrupted {
+                exit_code = ExitCode::from(EXIT_INTERRUPTED);
+            }
+        }
+        Command::Distributions {
+            input,
+            output,
+            kinds,
+            follow,
+            window,
+            dialect,
+        } => {
+            if cli.trace_backend == trace::TraceBackend::Geth {
+                eyre::bail!("distributions needs trace_filter, which geth doesn't support");
+            }
+            let provider = provider().await?;
+            distributions::distributions(
+                &provider,
+                &recipients::RecipientRegistry::load(cli.recipient_registry.as_deref())?,
+                &kinds,
+                &follow,
+                window,
+                &input,
+                &output,
+                &dialect,
+                cli.rpc_parallel,
+            )
+            .await?;
+        }
+        Command::Scan {
+            from_block,
+            to_block,
+            output,
+            relays,
+        } => {
+            let relays = if relays.is_empty() {
+                relays::default_relays()
+            } else {
+                relays
+            };
+            let provider = provider().await?;
+            scan::scan(
+                &provider,
+                &chain_profile,
+                &relays,
+                from_block,
+                to_block,
+                &output,
+                cli.rpc_parallel,
+            )
+            .await?;
         }
     }
-    Ok(())
+    Ok(exit_code)
 }
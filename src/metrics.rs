@@ -0,0 +1,85 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_int_counter, register_int_counter_vec, Encoder, Histogram,
+    IntCounter, IntCounterVec, TextEncoder,
+};
+
+pub static ENTRIES_PROCESSED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "proposer_payment_entries_processed_total",
+        "Entries processed and written to the output"
+    )
+    .unwrap()
+});
+
+pub static ENTRIES_FAILED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "proposer_payment_entries_failed_total",
+        "Entries skipped because processing failed"
+    )
+    .unwrap()
+});
+
+pub static PAYMENT_TYPES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proposer_payment_payment_types_total",
+        "Processed entries by classified payment type",
+        &["payment_type"]
+    )
+    .unwrap()
+});
+
+pub static RPC_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proposer_payment_rpc_errors_total",
+        "Failed JSON-RPC requests by method",
+        &["method"]
+    )
+    .unwrap()
+});
+
+pub static RPC_RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proposer_payment_rpc_retries_total",
+        "JSON-RPC requests sent again after a transient failure, by method",
+        &["method"]
+    )
+    .unwrap()
+});
+
+pub static ENTRY_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "proposer_payment_entry_duration_seconds",
+        "Time to fetch and classify a single entry",
+        vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]
+    )
+    .unwrap()
+});
+
+/// Serves all registered metrics in the Prometheus text format on every path.
+pub async fn serve(addr: SocketAddr) -> eyre::Result<()> {
+    let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+    Server::try_bind(&addr)?.serve(make_service).await?;
+    Ok(())
+}
+
+async fn handle(_request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        return Ok(Response::builder()
+            .status(500)
+            .body(Body::from(e.to_string()))
+            .unwrap());
+    }
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, encoder.format_type())
+        .body(Body::from(buffer))
+        .unwrap())
+}
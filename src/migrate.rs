@@ -0,0 +1,480 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use ethers::prelude::*;
+use futures::stream::{StreamExt, TryStreamExt};
+
+use crate::file::{output_header, output_record, read_entries, replace_output};
+use crate::rpc::EthProvider;
+use crate::streams::{open_input, Dialect, DialectArgs, InputSource};
+use crate::{
+    blob_base_fee, date_utc, format_eth, fullness_pct, gwei_to_wei, process_input_entry,
+    read_output_file_as, schema, BoostRelayDataEntry, Era, OutputFileEntry, ProcessingOptions,
+    TxTypeCounts,
+};
+
+/// Columns that only depend on the block header and its txs and are filled in without tracing
+/// the block.
+const HEADER_COLUMNS: &[&str] = &[
+    "timestamp",
+    "date_utc",
+    "era",
+    "gas_used",
+    "gas_limit",
+    "tx_count",
+    "tx_count_legacy",
+    "tx_count_eip2930",
+    "tx_count_eip1559",
+    "tx_count_eip4844",
+    "tx_count_eip7702",
+    "block_fullness_pct",
+    "base_fee_burned",
+    "blob_gas_used",
+    "blob_fee_burned",
+];
+
+/// Columns filled from the block with its tx hashes and withdrawals, given the fee recipient of
+/// the input row. The payment tx is only known this way for payments made by the last tx.
+const BLOCK_COLUMNS: &[&str] = &[
+    "payment_tx_hash",
+    "payment_tx_index",
+    "withdrawals",
+    "withdrawals_value",
+];
+
+/// Block columns rows of other payment types than [LAST_TX_PAYMENTS] are reprocessed for.
+const PAYMENT_TX_COLUMNS: &[&str] = &["payment_tx_hash", "payment_tx_index"];
+
+/// Columns copied from the input row.
+const INPUT_ROW_COLUMNS: &[&str] = &["fee_recipient"];
+
+/// Payment types whose payment tx is the last tx of the block.
+const LAST_TX_PAYMENTS: &[&str] = &["last_tx_direct", "last_tx_contract", "last_tx_reverted"];
+
+/// Columns computed from other columns of the row.
+const DERIVED_COLUMNS: &[&str] = &[
+    "bid_value_eth",
+    "balance_diff_eth",
+    "overpaid_by",
+    "is_contract",
+    // only untraced rows have no unexplained diff
+    "trace_data",
+    "entity",
+];
+
+/// Columns only filled by opt-in checks, left empty when migrating.
+const OPTIONAL_COLUMNS: &[&str] = &[
+    "relay_value",
+    "relay_value_mismatch",
+    "best_bid_value",
+    "bid_margin",
+    "winning_bid_ms_into_slot",
+    "bid_value_usd",
+    "payment_usd",
+    "registered_fee_recipient",
+    "registration_mismatch",
+    "expected_fee_recipient",
+    "wrong_recipient",
+    "input_extra",
+    // rows written before rules existed weren't classified by one
+    "payment_rule",
+];
+
+/// Upgrades `output` to the current columns, fetching only what the missing columns need.
+///
+/// When only block header columns are missing the headers are fetched, anything else needs the
+/// original `input` to reprocess the rows.
+pub async fn migrate(
+    provider: EthProvider,
+    options: Arc<ProcessingOptions>,
+    output: &Path,
+    input: Option<&Path>,
+    dialect_args: &DialectArgs,
+    rpc_parallel: usize,
+) -> eyre::Result<()> {
+    let dialect = dialect_args.output(output);
+    let missing = missing_columns(output, dialect)?;
+    if missing.is_empty() {
+        tracing::info!(
+            version = ?schema::read_version(output)?,
+            "{} already has the current columns",
+            output.display()
+        );
+        schema::write_version(output, None)?;
+        return Ok(());
+    }
+    tracing::info!(missing = %missing.join(","), "migrating {}", output.display());
+
+    let entries = read_output_file_as(output, dialect)?;
+    let missing: Vec<String> = missing
+        .into_iter()
+        .filter(|column| {
+            !OPTIONAL_COLUMNS.contains(&column.as_str())
+                && !DERIVED_COLUMNS.contains(&column.as_str())
+        })
+        .collect();
+    let entries = if missing.is_empty() {
+        entries
+    } else if missing
+        .iter()
+        .all(|column| HEADER_COLUMNS.contains(&column.as_str()))
+    {
+        fill_header_columns(&provider, entries, rpc_parallel).await?
+    } else {
+        let input = input.ok_or_else(|| {
+            eyre::eyre!(
+                "missing columns {} need the rows to be reprocessed, pass the original --input",
+                missing.join(",")
+            )
+        })?;
+        let source = dialect_args.input(input)?;
+        reprocess(&provider, &options, input, &source, entries, rpc_parallel).await?
+    };
+    let derive = |column: &str| missing.iter().any(|c| c == column);
+    let entries = entries.into_iter().map(|mut entry| {
+        entry.bid_value_eth = format_eth(entry.bid_value);
+        entry.balance_diff_eth = format_eth(entry.balance_diff);
+        // a stored value was computed with the threshold of the run that wrote it
+        if derive("overpaid_by") {
+            entry.overpaid_by = options
+                .tolerance
+                .overpaid_by(entry.bid_value, entry.observed_payment);
+        }
+        if entry.underpaid.is_none() {
+            entry.underpaid = Some(entry.derive_underpaid(&options.tolerance));
+        }
+        entry.is_contract = entry.fee_recipient_account_kind == "contract";
+        entry.trace_data = entry.unexplained_diff.is_some();
+        if entry.entity.is_none() {
+            entry.entity = entity(&options, &entry);
+        }
+        Ok(entry)
+    });
+    replace_output(output, dialect, None, entries)
+}
+
+/// Columns of the current schema `output` doesn't have.
+fn missing_columns(output: &Path, dialect: Dialect) -> eyre::Result<Vec<String>> {
+    schema::check_full(output)?;
+    if !dialect.has_headers {
+        eyre::bail!(
+            "{} has no header row to tell its columns from, it can't be upgraded in place",
+            output.display()
+        );
+    }
+    let columns: HashSet<String> = dialect
+        .reader(open_input(output)?)
+        .headers()?
+        .iter()
+        .map(str::to_string)
+        .collect();
+    Ok(output_header()?
+        .iter()
+        .filter(|column| !columns.contains(*column))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Fills only the columns `output` is missing and keeps every stored value, fetching the least
+/// data each column needs: block headers, blocks with their tx hashes, and only for the rest
+/// reprocessing the row from `input`.
+pub async fn backfill(
+    provider: EthProvider,
+    options: Arc<ProcessingOptions>,
+    output: &Path,
+    input: Option<&Path>,
+    dialect_args: &DialectArgs,
+    rpc_parallel: usize,
+) -> eyre::Result<()> {
+    let dialect = dialect_args.output(output);
+    let missing = missing_columns(output, dialect)?;
+    if missing.is_empty() {
+        tracing::info!("{} already has the current columns", output.display());
+        schema::write_version(output, None)?;
+        return Ok(());
+    }
+    tracing::info!(missing = %missing.join(","), "backfilling {}", output.display());
+
+    let is = |columns: &[&str], column: &String| columns.contains(&column.as_str());
+    let mut entries = read_output_file_as(output, dialect)?;
+    if missing.iter().any(|column| is(HEADER_COLUMNS, column)) {
+        entries = fill_header_columns(&provider, entries, rpc_parallel).await?;
+    }
+    let block_columns: Vec<&String> = missing
+        .iter()
+        .filter(|column| is(BLOCK_COLUMNS, column))
+        .collect();
+    let input_row_columns = missing.iter().any(|column| is(INPUT_ROW_COLUMNS, column));
+    let reprocessed: Vec<&String> = missing
+        .iter()
+        .filter(|column| {
+            ![
+                HEADER_COLUMNS,
+                BLOCK_COLUMNS,
+                INPUT_ROW_COLUMNS,
+                DERIVED_COLUMNS,
+                OPTIONAL_COLUMNS,
+            ]
+            .iter()
+            .any(|columns| is(columns, column))
+        })
+        .collect();
+    if !block_columns.is_empty() || input_row_columns || !reprocessed.is_empty() {
+        let input = input.ok_or_else(|| {
+            eyre::eyre!(
+                "missing columns {} need the input rows, pass the original --input",
+                missing.join(",")
+            )
+        })?;
+        let mut inputs = HashMap::new();
+        for row in read_entries(input, &dialect_args.input(input)?)? {
+            let row = row?;
+            inputs.insert(row.slot, row);
+        }
+        if input_row_columns {
+            for entry in &mut entries {
+                if let Some(input) = inputs.get(&entry.slot) {
+                    entry.fee_recipient = Some(input.proposer_fee_recipient);
+                }
+            }
+        }
+        if !block_columns.is_empty() {
+            entries = fill_block_columns(&provider, entries, &inputs, &block_columns, rpc_parallel)
+                .await?;
+        }
+        let mut reprocessed = reprocessed;
+        reprocessed.extend(
+            block_columns
+                .iter()
+                .copied()
+                .filter(|column| is(PAYMENT_TX_COLUMNS, column)),
+        );
+        if !reprocessed.is_empty() {
+            entries = fill_reprocessed_columns(
+                &provider,
+                &options,
+                entries,
+                &inputs,
+                &reprocessed,
+                rpc_parallel,
+            )
+            .await?;
+        }
+    }
+    let derive = |column: &str| missing.iter().any(|c| c == column);
+    let entries = entries.into_iter().map(|mut entry| {
+        if derive("bid_value_eth") {
+            entry.bid_value_eth = format_eth(entry.bid_value);
+        }
+        if derive("balance_diff_eth") {
+            entry.balance_diff_eth = format_eth(entry.balance_diff);
+        }
+        if derive("overpaid_by") {
+            entry.overpaid_by = options
+                .tolerance
+                .overpaid_by(entry.bid_value, entry.observed_payment);
+        }
+        if derive("is_contract") {
+            entry.is_contract = entry.fee_recipient_account_kind == "contract";
+        }
+        if derive("trace_data") {
+            entry.trace_data = entry.unexplained_diff.is_some();
+        }
+        if derive("entity") {
+            entry.entity = entity(&options, &entry);
+        }
+        Ok(entry)
+    });
+    replace_output(output, dialect, None, entries)
+}
+
+fn entity(options: &ProcessingOptions, entry: &OutputFileEntry) -> Option<String> {
+    options
+        .entities
+        .get(
+            entry.proposer_index,
+            entry.proposer_pubkey.as_deref(),
+            entry.fee_recipient,
+        )
+        .map(str::to_string)
+}
+
+async fn fill_block_columns(
+    provider: &EthProvider,
+    entries: Vec<OutputFileEntry>,
+    inputs: &HashMap<u64, BoostRelayDataEntry>,
+    columns: &[&String],
+    rpc_parallel: usize,
+) -> eyre::Result<Vec<OutputFileEntry>> {
+    let missing = |column: &str| columns.iter().any(|c| c.as_str() == column);
+    let (payment_tx, withdrawals) = (
+        missing("payment_tx_hash") || missing("payment_tx_index"),
+        missing("withdrawals") || missing("withdrawals_value"),
+    );
+    futures::stream::iter(entries)
+        .map(|mut entry| async move {
+            if entry.is_missed() {
+                return Ok(entry);
+            }
+            let Some(input) = inputs.get(&entry.slot) else {
+                tracing::warn!(slot = entry.slot, "slot not in the input, keeping the row");
+                return Ok(entry);
+            };
+            let block = provider
+                .get_block(entry.block_number)
+                .await?
+                .ok_or_else(|| eyre::eyre!("block {} not found", entry.block_number))?;
+            if payment_tx && LAST_TX_PAYMENTS.contains(&entry.payment_type.as_str()) {
+                entry.payment_tx_hash = block.transactions.last().copied();
+                entry.payment_tx_index = block.transactions.len().checked_sub(1);
+            }
+            if withdrawals {
+                let withdrawals: Vec<_> = block
+                    .withdrawals
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|w| w.address == input.proposer_fee_recipient)
+                    .collect();
+                entry.withdrawals = withdrawals.len();
+                entry.withdrawals_value = withdrawals
+                    .iter()
+                    .fold(U256::zero(), |acc, w| acc + gwei_to_wei(w.amount));
+            }
+            Ok::<_, eyre::Report>(entry)
+        })
+        .buffered(rpc_parallel.max(1))
+        .try_collect()
+        .await
+}
+
+/// Reprocesses the rows that need it and copies only `columns` from the result, rows of other
+/// payment types than last tx ones only need it for the payment tx columns.
+async fn fill_reprocessed_columns(
+    provider: &EthProvider,
+    options: &ProcessingOptions,
+    entries: Vec<OutputFileEntry>,
+    inputs: &HashMap<u64, BoostRelayDataEntry>,
+    columns: &[&String],
+    rpc_parallel: usize,
+) -> eyre::Result<Vec<OutputFileEntry>> {
+    let header = output_header()?;
+    let indices: Vec<usize> = header
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| columns.iter().any(|c| c.as_str() == *column))
+        .map(|(i, _)| i)
+        .collect();
+    let only_payment_tx = columns
+        .iter()
+        .all(|column| PAYMENT_TX_COLUMNS.contains(&column.as_str()));
+    let header = &header;
+    let indices = &indices;
+    futures::stream::iter(entries)
+        .map(|entry| async move {
+            if entry.is_missed()
+                || (only_payment_tx && LAST_TX_PAYMENTS.contains(&entry.payment_type.as_str()))
+            {
+                return Ok(entry);
+            }
+            let Some(input) = inputs.get(&entry.slot).cloned() else {
+                tracing::warn!(slot = entry.slot, "slot not in the input, keeping the row");
+                return Ok(entry);
+            };
+            let computed = match process_input_entry(provider, options, input).await {
+                Ok(computed) => computed,
+                Err(e) => {
+                    tracing::warn!(slot = entry.slot, error = %format!("{e:#}"), "failed to reprocess, keeping the row");
+                    return Ok(entry);
+                }
+            };
+            let (mut stored, computed) = (output_record(&entry)?, output_record(&computed)?);
+            stored = stored
+                .iter()
+                .enumerate()
+                .map(|(i, field)| if indices.contains(&i) { &computed[i] } else { field })
+                .collect();
+            Ok::<_, eyre::Report>(stored.deserialize(Some(header))?)
+        })
+        .buffered(rpc_parallel.max(1))
+        .try_collect()
+        .await
+}
+
+/// Fills the header columns of every row but the `missed` ones, which have no block.
+async fn fill_header_columns(
+    provider: &EthProvider,
+    entries: Vec<OutputFileEntry>,
+    rpc_parallel: usize,
+) -> eyre::Result<Vec<OutputFileEntry>> {
+    futures::stream::iter(entries)
+        .map(|mut entry| async move {
+            if entry.is_missed() {
+                return Ok(entry);
+            }
+            let block = provider
+                .get_block_with_txs(entry.block_number)
+                .await?
+                .ok_or_else(|| eyre::eyre!("block {} not found", entry.block_number))?;
+            let blob_gas_used = block.blob_gas_used.unwrap_or_default();
+            entry.timestamp = block.timestamp.as_u64();
+            entry.date_utc = date_utc(entry.timestamp);
+            entry.era = Era::of(&block).as_str().to_string();
+            entry.gas_used = block.gas_used.as_u64();
+            entry.gas_limit = block.gas_limit.as_u64();
+            entry.tx_count = block.transactions.len();
+            let counts = TxTypeCounts::of(&block.transactions);
+            entry.tx_count_legacy = counts.legacy;
+            entry.tx_count_eip2930 = counts.eip2930;
+            entry.tx_count_eip1559 = counts.eip1559;
+            entry.tx_count_eip4844 = counts.eip4844;
+            entry.tx_count_eip7702 = counts.eip7702;
+            entry.block_fullness_pct = fullness_pct(entry.gas_used, entry.gas_limit);
+            entry.base_fee_burned = block.base_fee_per_gas.unwrap_or_default() * block.gas_used;
+            entry.blob_gas_used = blob_gas_used.as_u64();
+            entry.blob_fee_burned =
+                blob_gas_used * blob_base_fee(block.excess_blob_gas.unwrap_or_default());
+            Ok::<_, eyre::Report>(entry)
+        })
+        .buffered(rpc_parallel.max(1))
+        .try_collect()
+        .await
+}
+
+/// Reprocesses every row found in `input`. Rows that fail keep their old values.
+async fn reprocess(
+    provider: &EthProvider,
+    options: &ProcessingOptions,
+    input: &Path,
+    source: &InputSource,
+    entries: Vec<OutputFileEntry>,
+    rpc_parallel: usize,
+) -> eyre::Result<Vec<OutputFileEntry>> {
+    let mut inputs = HashMap::new();
+    for row in read_entries(input, source)? {
+        let row = row?;
+        inputs.insert(row.slot, row);
+    }
+
+    let migrated: Vec<OutputFileEntry> = futures::stream::iter(entries)
+        .map(|entry| {
+            let input = inputs.remove(&entry.slot);
+            async move {
+                let Some(input) = input else {
+                    tracing::warn!(slot = entry.slot, "slot not in the input, keeping the row");
+                    return entry;
+                };
+                match process_input_entry(provider, options, input).await {
+                    Ok(migrated) => migrated,
+                    Err(e) => {
+                        tracing::warn!(slot = entry.slot, error = %format!("{e:#}"), "failed to reprocess, keeping the row");
+                        entry
+                    }
+                }
+            }
+        })
+        .buffered(rpc_parallel.max(1))
+        .collect()
+        .await;
+    Ok(migrated)
+}
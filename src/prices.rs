@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use ethers::abi::{self, ParamType};
+use ethers::prelude::*;
+use serde::Deserialize;
+
+use crate::chain::ChainProfile;
+use crate::rpc::EthProvider;
+
+/// Chainlink ETH / USD price feed on mainnet.
+const MAINNET_ETH_USD_FEED: &str = "0x5f4eC3Df9cbd43714FE2740f5E3616155c5b8419";
+
+#[derive(Debug, Deserialize)]
+struct PriceRow {
+    /// Unix seconds or a `YYYY-MM-DD` date, taken as midnight UTC.
+    timestamp: String,
+    #[serde(alias = "usd")]
+    price: f64,
+}
+
+/// Where the ETH price in USD of a block comes from, for the `_usd` columns.
+#[derive(Debug, Clone)]
+pub enum PriceSource {
+    /// Latest round of a Chainlink feed as of the block.
+    Chainlink {
+        feed: Address,
+        /// Decimals of the feed's answer, looked up on first use.
+        decimals: std::sync::Arc<tokio::sync::OnceCell<u8>>,
+    },
+    /// Historical prices by unix time, the latest at or before the block applies.
+    Table(std::sync::Arc<BTreeMap<u64, f64>>),
+}
+
+impl PriceSource {
+    /// `chainlink` for the bundled ETH / USD feed of the chain, `chainlink:ADDRESS` for another
+    /// feed, otherwise a path to a csv file with `timestamp,price` rows.
+    pub fn resolve(source: &str, chain: &ChainProfile) -> eyre::Result<Self> {
+        let chainlink = |feed: Address| PriceSource::Chainlink {
+            feed,
+            decimals: Default::default(),
+        };
+        if source == "chainlink" {
+            if chain.name != "mainnet" {
+                eyre::bail!(
+                    "no bundled Chainlink feed on {}, pass chainlink:ADDRESS",
+                    chain.name
+                );
+            }
+            return Ok(chainlink(MAINNET_ETH_USD_FEED.parse()?));
+        }
+        if let Some(feed) = source.strip_prefix("chainlink:") {
+            return Ok(chainlink(feed.parse()?));
+        }
+        Self::load(Path::new(source))
+    }
+
+    fn load(path: &Path) -> eyre::Result<Self> {
+        let mut prices = BTreeMap::new();
+        for (row, price) in csv::Reader::from_path(path)?
+            .into_deserialize::<PriceRow>()
+            .enumerate()
+        {
+            let price = price?;
+            let timestamp = match price.timestamp.parse::<u64>() {
+                Ok(timestamp) => timestamp,
+                Err(_) => chrono::NaiveDate::parse_from_str(&price.timestamp, "%Y-%m-%d")
+                    .map_err(|_| {
+                        eyre::eyre!(
+                            "{} row {}: expected unix seconds or YYYY-MM-DD, got {}",
+                            path.display(),
+                            row + 1,
+                            price.timestamp
+                        )
+                    })?
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap_or_default()
+                    .and_utc()
+                    .timestamp() as u64,
+            };
+            prices.insert(timestamp, price.price);
+        }
+        Ok(PriceSource::Table(std::sync::Arc::new(prices)))
+    }
+
+    /// Price of 1 ETH in USD at the block, `None` if the table doesn't go back that far.
+    pub async fn eth_usd(
+        &self,
+        provider: &EthProvider,
+        block_number: u64,
+        timestamp: u64,
+    ) -> eyre::Result<Option<f64>> {
+        match self {
+            PriceSource::Table(prices) => Ok(prices
+                .range(..=timestamp)
+                .next_back()
+                .map(|(_, price)| *price)),
+            PriceSource::Chainlink { feed, decimals } => {
+                let decimals = *decimals
+                    .get_or_try_init(|| async {
+                        let output = call(provider, *feed, "decimals()", block_number).await?;
+                        let decimals = abi::decode(&[ParamType::Uint(8)], &output)?;
+                        Ok::<_, eyre::Report>(
+                            decimals[0].clone().into_uint().unwrap_or_default().as_u32() as u8,
+                        )
+                    })
+                    .await?;
+                let output = call(provider, *feed, "latestRoundData()", block_number).await?;
+                let round = abi::decode(
+                    &[
+                        ParamType::Uint(80),
+                        ParamType::Int(256),
+                        ParamType::Uint(256),
+                        ParamType::Uint(256),
+                        ParamType::Uint(80),
+                    ],
+                    &output,
+                )?;
+                let answer = I256::from_raw(round[1].clone().into_int().unwrap_or_default());
+                Ok(Some(answer.as_i128() as f64 / 10f64.powi(decimals as i32)))
+            }
+        }
+    }
+}
+
+/// Calls the argumentless `function` of `contract` as of the end of `block_number`.
+async fn call(
+    provider: &EthProvider,
+    contract: Address,
+    function: &str,
+    block_number: u64,
+) -> eyre::Result<Bytes> {
+    let tx = TransactionRequest::new()
+        .to(contract)
+        .data(ethers::utils::id(function).to_vec());
+    Ok(provider
+        .call(
+            &tx.into(),
+            Some(BlockNumber::Number(block_number.into()).into()),
+        )
+        .await?)
+}
+
+/// `wei` in USD at `price`, rounded to cents.
+pub fn to_usd(wei: I256, price: f64) -> f64 {
+    let eth = wei.as_i128() as f64 / 1e18;
+    (eth * price * 100.0).round() / 100.0
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::chain::ChainProfile;
+use crate::summarize::Bucket;
+use crate::{read_output_file, OutputFileEntry};
+
+/// Number of individual anomalies printed per kind before only counting them.
+const MAX_REPORTED: usize = 10;
+
+/// Days with fewer blocks are too small for their share of `unknown` rows to mean anything.
+const MIN_DAY_BLOCKS: u64 = 50;
+
+/// Thresholds of the `quality` subcommand.
+#[derive(Debug, Clone, clap::Args)]
+pub struct QualityArgs {
+    /// A day is a spike when its share of `unknown` rows is this many times the share over the
+    /// whole file and at least 5 percentage points above it.
+    #[clap(long, default_value = "3")]
+    pub unknown_spike_factor: f64,
+    /// Consecutive rows with a zero balance change reported as a run, a node answering balance
+    /// calls from a stale state looks like this.
+    #[clap(long, default_value = "5")]
+    pub zero_diff_run: usize,
+    /// Slots without a row between two rows reported as a gap in the coverage. Relays don't
+    /// deliver every block, so short gaps are normal.
+    #[clap(long, default_value = "100")]
+    pub max_gap: u64,
+}
+
+#[derive(Debug, Default)]
+struct Anomalies {
+    unknown_spikes: usize,
+    zero_diff_runs: usize,
+    unreconciled: usize,
+    gaps: usize,
+}
+
+impl Anomalies {
+    fn total(&self) -> usize {
+        self.unknown_spikes + self.zero_diff_runs + self.unreconciled + self.gaps
+    }
+}
+
+/// Scans `output` for patterns that usually mean a provider or heuristic problem rather than real
+/// payments, prints them and fails if there are any.
+pub fn quality(output: &Path, chain: &ChainProfile, args: &QualityArgs) -> eyre::Result<()> {
+    let mut entries = read_output_file(output)?;
+    entries.retain(|entry| !entry.is_missed());
+    entries.sort_unstable_by_key(|entry| entry.slot);
+    let mut anomalies = Anomalies::default();
+
+    anomalies.unknown_spikes = unknown_spikes(&entries, chain, args.unknown_spike_factor);
+    anomalies.zero_diff_runs = zero_diff_runs(&entries, args.zero_diff_run);
+    for entry in &entries {
+        if let Some(problem) = unreconciled(entry) {
+            anomalies.unreconciled += 1;
+            if anomalies.unreconciled <= MAX_REPORTED {
+                println!(
+                    "slot {} (block {}): {problem}",
+                    entry.slot, entry.block_number
+                );
+            }
+        }
+    }
+    for pair in entries.windows(2) {
+        // duplicate slots aren't a gap
+        let missing = (pair[1].slot - pair[0].slot).saturating_sub(1);
+        if missing > args.max_gap {
+            anomalies.gaps += 1;
+            if anomalies.gaps <= MAX_REPORTED {
+                println!(
+                    "slots {} to {}: no rows for {missing} slots",
+                    pair[0].slot + 1,
+                    pair[1].slot - 1
+                );
+            }
+        }
+    }
+
+    println!("rows:                 {}", entries.len());
+    println!("unknown spikes:       {}", anomalies.unknown_spikes);
+    println!("zero diff runs:       {}", anomalies.zero_diff_runs);
+    println!("unreconciled counts:  {}", anomalies.unreconciled);
+    println!("coverage gaps:        {}", anomalies.gaps);
+    if anomalies.total() > 0 {
+        eyre::bail!("{} anomalies", anomalies.total());
+    }
+    Ok(())
+}
+
+/// Days whose share of `unknown` rows is far above the share over all of `entries`.
+fn unknown_spikes(entries: &[OutputFileEntry], chain: &ChainProfile, factor: f64) -> usize {
+    let unknown = |entry: &&OutputFileEntry| entry.payment_type == "unknown";
+    if entries.is_empty() {
+        return 0;
+    }
+    let overall = entries.iter().filter(unknown).count() as f64 / entries.len() as f64;
+    let mut days: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    for entry in entries {
+        let (blocks, unknowns) = days.entry(Bucket::Day.key(chain, entry.slot)).or_default();
+        *blocks += 1;
+        *unknowns += unknown(&entry) as u64;
+    }
+    let mut spikes = 0;
+    for (day, (blocks, unknowns)) in days {
+        let share = unknowns as f64 / blocks as f64;
+        if blocks >= MIN_DAY_BLOCKS && share > overall * factor && share > overall + 0.05 {
+            spikes += 1;
+            if spikes <= MAX_REPORTED {
+                println!(
+                    "{day}: {unknowns} of {blocks} rows unknown ({:.1}%, {:.1}% overall)",
+                    share * 100.0,
+                    overall * 100.0
+                );
+            }
+        }
+    }
+    spikes
+}
+
+/// Runs of at least `min_len` consecutive rows whose fee recipient balance didn't change, which
+/// a paid proposer's balance always does.
+fn zero_diff_runs(entries: &[OutputFileEntry], min_len: usize) -> usize {
+    let mut runs = 0;
+    let mut report = |run: &[OutputFileEntry]| {
+        if run.len() >= min_len.max(1) {
+            runs += 1;
+            if runs <= MAX_REPORTED {
+                println!(
+                    "slots {} to {}: {} consecutive rows with a zero balance change",
+                    run[0].slot,
+                    run[run.len() - 1].slot,
+                    run.len()
+                );
+            }
+        }
+    };
+    let mut start = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        if !entry.balance_diff.is_zero() {
+            report(&entries[start..i]);
+            start = i + 1;
+        }
+    }
+    report(&entries[start..]);
+    runs
+}
+
+/// Why the counts of `entry` contradict each other, `None` if they add up.
+fn unreconciled(entry: &OutputFileEntry) -> Option<String> {
+    let by_type = entry.tx_count_legacy
+        + entry.tx_count_eip2930
+        + entry.tx_count_eip1559
+        + entry.tx_count_eip4844
+        + entry.tx_count_eip7702;
+    // rows written before the per-type counts have none of them
+    if by_type != 0 && by_type != entry.tx_count {
+        return Some(format!(
+            "{by_type} txs by type but tx_count is {}",
+            entry.tx_count
+        ));
+    }
+    if let Some(index) = entry
+        .payment_tx_index
+        .filter(|index| *index >= entry.tx_count)
+    {
+        return Some(format!(
+            "payment tx index {index} in a block of {} txs",
+            entry.tx_count
+        ));
+    }
+    if entry.withdrawals == 0 && !entry.withdrawals_value.is_zero() {
+        return Some(format!(
+            "no withdrawals but withdrawals_value is {} wei",
+            entry.withdrawals_value
+        ));
+    }
+    None
+}
@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use datafusion::datasource::MemTable;
+use datafusion::prelude::SessionContext;
+
+use crate::export::BatchReader;
+
+/// Table the output files are loaded into.
+const TABLE: &str = "results";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum QueryFormat {
+    Csv,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Loads `inputs` into the `results` table and prints the result of `sql` to stdout.
+///
+/// Columns are typed as in an arrow export, so wei amounts are 256 bit decimals. Cast them to
+/// `DOUBLE` for averages and other aggregates decimals don't support.
+pub async fn query(inputs: &[PathBuf], sql: &str, format: QueryFormat) -> eyre::Result<()> {
+    let mut schema = None;
+    let mut batches = Vec::new();
+    for input in inputs {
+        let mut reader = BatchReader::open(input)?;
+        match &schema {
+            None => schema = Some(reader.schema()),
+            Some(schema) if *schema != reader.schema() => eyre::bail!(
+                "{} has other columns than {}, run `migrate` on the older file first",
+                input.display(),
+                inputs[0].display()
+            ),
+            Some(_) => {}
+        }
+        while let Some(batch) = reader.next_batch()? {
+            batches.push(batch);
+        }
+    }
+    let schema = schema.ok_or_else(|| eyre::eyre!("no input files"))?;
+
+    let ctx = SessionContext::new();
+    ctx.register_table(TABLE, Arc::new(MemTable::try_new(schema, vec![batches])?))?;
+    let results = ctx.sql(sql).await?.collect().await?;
+
+    let stdout = std::io::stdout().lock();
+    match format {
+        QueryFormat::Csv => {
+            let mut writer = arrow::csv::Writer::new(stdout);
+            for batch in &results {
+                writer.write(batch)?;
+            }
+        }
+        QueryFormat::Json => {
+            let mut writer = arrow::json::LineDelimitedWriter::new(stdout);
+            writer.write_batches(&results.iter().collect::<Vec<_>>())?;
+            writer.finish()?;
+        }
+    }
+    Ok(())
+}
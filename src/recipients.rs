@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use ethers::types::Address;
+use serde::Deserialize;
+
+/// Fee recipient kind used when an address isn't in the registry.
+pub const OTHER: &str = "other";
+
+/// Fee distribution contracts whose balance changes don't map one to one to a single proposer.
+const KNOWN_RECIPIENTS: &[(&str, &str)] = &[
+    // Lido execution layer rewards vault
+    (
+        "0x388C818CA8B9251b393131C08a736A67ccB19297",
+        "lido_el_vault",
+    ),
+    // Rocket Pool smoothing pool
+    (
+        "0xd4E96eF8eee8678dBFf4d535E033Ed1a4F7605b7",
+        "smoothing_pool",
+    ),
+];
+
+#[derive(Debug, Deserialize)]
+struct RegistryRow {
+    address: Address,
+    kind: String,
+}
+
+/// Known fee recipient contracts by address, bundled and optionally extended from a csv file.
+#[derive(Debug, Clone)]
+pub struct RecipientRegistry {
+    kinds: HashMap<Address, String>,
+}
+
+impl Default for RecipientRegistry {
+    fn default() -> Self {
+        Self {
+            kinds: KNOWN_RECIPIENTS
+                .iter()
+                .map(|(address, kind)| (address.parse().unwrap(), kind.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl RecipientRegistry {
+    /// Bundled registry with entries from `path` (columns `address,kind`) added on top.
+    pub fn load(path: Option<&Path>) -> eyre::Result<Self> {
+        let mut registry = Self::default();
+        if let Some(path) = path {
+            for row in csv::Reader::from_path(path)?.into_deserialize::<RegistryRow>() {
+                let row = row?;
+                registry.kinds.insert(row.address, row.kind);
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn kind(&self, address: Address) -> &str {
+        self.kinds
+            .get(&address)
+            .map(String::as_str)
+            .unwrap_or(OTHER)
+    }
+}
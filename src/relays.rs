@@ -0,0 +1,474 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use ethers::types::{Address, H256, U256};
+use serde::Deserialize;
+
+use crate::streams::{create_output_as, open_input, Compression, Dialect};
+use crate::BoostRelayDataEntry;
+
+/// Relays synced when none are given on the command line.
+pub const DEFAULT_RELAYS: &[(&str, &str)] = &[
+    ("flashbots", "https://boost-relay.flashbots.net"),
+    ("ultrasound", "https://relay.ultrasound.money"),
+    (
+        "bloxroute_max_profit",
+        "https://bloxroute.max-profit.blxrbdn.com",
+    ),
+    (
+        "bloxroute_regulated",
+        "https://bloxroute.regulated.blxrbdn.com",
+    ),
+    ("agnostic", "https://agnostic-relay.net"),
+    ("aestus", "https://mainnet.aestus.live"),
+];
+
+/// Largest page the relay data API returns.
+const PAGE_LIMIT: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relay {
+    pub name: String,
+    pub url: String,
+}
+
+/// Parses `name=url`, the name of a default relay, or a bare url named after its host.
+pub fn parse_relay(s: &str) -> Result<Relay, String> {
+    if let Some((name, url)) = s.split_once('=') {
+        return Ok(Relay {
+            name: name.to_string(),
+            url: url.trim_end_matches('/').to_string(),
+        });
+    }
+    if let Some((name, url)) = DEFAULT_RELAYS.iter().find(|(name, _)| *name == s) {
+        return Ok(Relay {
+            name: name.to_string(),
+            url: url.to_string(),
+        });
+    }
+    let host = s
+        .split("://")
+        .nth(1)
+        .and_then(|rest| rest.split('/').next())
+        .filter(|host| !host.is_empty())
+        .ok_or_else(|| format!("expected a relay name, url or name=url, got {s}"))?;
+    Ok(Relay {
+        name: host.to_string(),
+        url: s.trim_end_matches('/').to_string(),
+    })
+}
+
+pub fn default_relays() -> Vec<Relay> {
+    DEFAULT_RELAYS
+        .iter()
+        .map(|(name, url)| Relay {
+            name: name.to_string(),
+            url: url.to_string(),
+        })
+        .collect()
+}
+
+/// Entry of `/relay/v1/data/bidtraces/proposer_payload_delivered`, numbers are strings.
+#[derive(Debug, Deserialize)]
+struct PayloadDelivered {
+    slot: String,
+    block_hash: H256,
+    block_number: String,
+    builder_pubkey: String,
+    proposer_fee_recipient: Address,
+    value: String,
+}
+
+impl PayloadDelivered {
+    fn into_entry(self, relay: &str) -> eyre::Result<BoostRelayDataEntry> {
+        Ok(BoostRelayDataEntry {
+            slot: self.slot.parse()?,
+            proposer_fee_recipient: self.proposer_fee_recipient,
+            value: U256::from_dec_str(&self.value)?,
+            block_hash: Some(self.block_hash),
+            block_number: self.block_number.parse()?,
+            relay: Some(relay.to_string()),
+            builder_pubkey: Some(self.builder_pubkey),
+            extra: BTreeMap::new(),
+        })
+    }
+}
+
+/// Reads a JSON array of delivered payloads as saved from the relay API. Fields the tool doesn't
+/// use, e.g. `gas_used` or `num_tx`, are kept in `extra`.
+pub fn read_payloads_json(reader: impl std::io::Read) -> eyre::Result<Vec<BoostRelayDataEntry>> {
+    const FIELDS: &[&str] = &[
+        "slot",
+        "block_hash",
+        "block_number",
+        "builder_pubkey",
+        "proposer_fee_recipient",
+        "value",
+    ];
+    let payloads: Vec<serde_json::Map<String, serde_json::Value>> =
+        serde_json::from_reader(reader)?;
+    payloads
+        .into_iter()
+        .map(|payload| {
+            let delivered: PayloadDelivered =
+                serde_json::from_value(serde_json::Value::Object(payload.clone()))?;
+            let mut entry = delivered.into_entry("")?;
+            entry.relay = None;
+            entry.extra = payload
+                .into_iter()
+                .filter(|(field, _)| !FIELDS.contains(&field.as_str()))
+                .map(|(field, value)| match value {
+                    serde_json::Value::String(value) => (field, value),
+                    value => (field, value.to_string()),
+                })
+                .collect();
+            Ok(entry)
+        })
+        .collect()
+}
+
+/// Fetches payloads delivered by `relay` after `after`, newest first.
+async fn fetch_delivered(
+    client: &reqwest::Client,
+    relay: &Relay,
+    after: Option<u64>,
+) -> eyre::Result<Vec<BoostRelayDataEntry>> {
+    let mut entries = Vec::new();
+    let mut cursor: Option<u64> = None;
+    loop {
+        let mut url = format!(
+            "{}/relay/v1/data/bidtraces/proposer_payload_delivered?limit={PAGE_LIMIT}",
+            relay.url
+        );
+        if let Some(cursor) = cursor {
+            url.push_str(&format!("&cursor={cursor}"));
+        }
+        let page: Vec<PayloadDelivered> = client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let page_len = page.len();
+
+        let mut lowest = None;
+        let mut reached_synced = false;
+        for payload in page {
+            let entry = payload.into_entry(&relay.name)?;
+            lowest = Some(lowest.map_or(entry.slot, |lowest: u64| lowest.min(entry.slot)));
+            if after.map_or(false, |after| entry.slot <= after) {
+                reached_synced = true;
+                continue;
+            }
+            entries.push(entry);
+        }
+        tracing::debug!(relay = %relay.name, fetched = entries.len(), "fetched page");
+
+        match lowest {
+            Some(lowest) if !reached_synced && page_len >= PAGE_LIMIT && lowest > 0 => {
+                cursor = Some(lowest - 1)
+            }
+            _ => break,
+        }
+    }
+    Ok(entries)
+}
+
+/// Entry of `/relay/v1/data/bidtraces/builder_blocks_received`, numbers are strings.
+#[derive(Debug, Deserialize)]
+struct BidReceived {
+    block_hash: H256,
+    value: String,
+    /// When the relay received the bid, not reported by every relay.
+    #[serde(default)]
+    timestamp_ms: Option<String>,
+}
+
+/// Fetches a relay data API bid trace endpoint filtered to `slot`.
+async fn fetch_slot<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    relay: &Relay,
+    endpoint: &str,
+    slot: u64,
+) -> eyre::Result<Vec<T>> {
+    Ok(client
+        .get(format!(
+            "{}/relay/v1/data/bidtraces/{endpoint}?slot={slot}",
+            relay.url
+        ))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+/// Names of the relays that report delivering `block_hash` in `slot`.
+pub async fn delivering_relays(
+    client: &reqwest::Client,
+    relays: &[Relay],
+    slot: u64,
+    block_hash: H256,
+) -> eyre::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for relay in relays {
+        let delivered: Vec<PayloadDelivered> =
+            fetch_slot(client, relay, "proposer_payload_delivered", slot).await?;
+        if delivered.iter().any(|p| p.block_hash == block_hash) {
+            names.push(relay.name.clone());
+        }
+    }
+    Ok(names)
+}
+
+/// Payload delivered in `slot` according to the first of `relays` that reports one.
+pub async fn delivered_entry(
+    client: &reqwest::Client,
+    relays: &[Relay],
+    slot: u64,
+) -> eyre::Result<Option<BoostRelayDataEntry>> {
+    for relay in relays {
+        let delivered: Vec<PayloadDelivered> =
+            fetch_slot(client, relay, "proposer_payload_delivered", slot).await?;
+        if let Some(payload) = delivered.into_iter().next() {
+            return Ok(Some(payload.into_entry(&relay.name)?));
+        }
+    }
+    Ok(None)
+}
+
+/// Looks up the value relays currently report as delivered for a slot, to catch stale exports.
+#[derive(Debug, Clone)]
+pub struct RelayCheck {
+    client: reqwest::Client,
+    relays: Vec<Relay>,
+}
+
+impl RelayCheck {
+    pub fn new(relays: Vec<Relay>) -> Self {
+        Self {
+            client: crate::rpc::http_client(),
+            relays,
+        }
+    }
+
+    /// Value delivered in `slot` according to the first relay that reports it, asking only the
+    /// relays in the comma separated `names` when any of them are known. `None` if no relay
+    /// reports the slot.
+    pub async fn delivered_value(
+        &self,
+        slot: u64,
+        names: Option<&str>,
+    ) -> eyre::Result<Option<U256>> {
+        let named: Vec<&Relay> = self
+            .relays
+            .iter()
+            .filter(|relay| {
+                names
+                    .unwrap_or_default()
+                    .split(',')
+                    .any(|name| name == relay.name)
+            })
+            .collect();
+        let relays = if named.is_empty() {
+            self.relays.iter().collect()
+        } else {
+            named
+        };
+        for relay in relays {
+            let delivered: Vec<PayloadDelivered> =
+                fetch_slot(&self.client, relay, "proposer_payload_delivered", slot).await?;
+            if let Some(payload) = delivered.into_iter().next() {
+                return Ok(Some(U256::from_dec_str(&payload.value)?));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Looks up the bids relays received for a slot, to compare the delivered block against the
+/// best one it beat.
+#[derive(Debug, Clone)]
+pub struct BidTraces {
+    client: reqwest::Client,
+    relays: Vec<Relay>,
+}
+
+impl BidTraces {
+    pub fn new(relays: Vec<Relay>) -> Self {
+        Self {
+            client: crate::rpc::http_client(),
+            relays,
+        }
+    }
+
+    /// Bids the relays received in `slot`, compared with the `delivered` block.
+    pub async fn slot_bids(&self, slot: u64, delivered: H256) -> eyre::Result<SlotBids> {
+        let mut bids = SlotBids::default();
+        for relay in &self.relays {
+            let received: Vec<BidReceived> =
+                fetch_slot(&self.client, relay, "builder_blocks_received", slot).await?;
+            for bid in received {
+                if bid.block_hash == delivered {
+                    if let Some(ms) = bid.timestamp_ms {
+                        let ms = ms.parse()?;
+                        bids.delivered_received_ms =
+                            Some(bids.delivered_received_ms.map_or(ms, |first| first.min(ms)));
+                    }
+                    continue;
+                }
+                let value = U256::from_dec_str(&bid.value)?;
+                bids.best_competing =
+                    Some(bids.best_competing.map_or(value, |best| best.max(value)));
+            }
+        }
+        Ok(bids)
+    }
+}
+
+/// What the bid traces of the relays tell about a slot.
+#[derive(Debug, Clone, Default)]
+pub struct SlotBids {
+    /// Highest bid for a block other than the delivered one, `None` without competing bids.
+    pub best_competing: Option<U256>,
+    /// Unix time in ms the first of the relays received the delivered block.
+    pub delivered_received_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedRegistration {
+    message: Registration,
+}
+
+#[derive(Debug, Deserialize)]
+struct Registration {
+    fee_recipient: Address,
+}
+
+/// Looks up the fee recipient proposers registered with relays, to catch blocks paying elsewhere.
+#[derive(Debug, Clone)]
+pub struct RegistrationCheck {
+    client: reqwest::Client,
+    relays: Vec<Relay>,
+}
+
+impl RegistrationCheck {
+    pub fn new(relays: Vec<Relay>) -> Self {
+        Self {
+            client: crate::rpc::http_client(),
+            relays,
+        }
+    }
+
+    /// Fee recipient `pubkey` registered with the first relay that knows the validator. Relays
+    /// only keep the latest registration, which may postdate the block.
+    pub async fn registered_fee_recipient(&self, pubkey: &str) -> eyre::Result<Option<Address>> {
+        for relay in &self.relays {
+            let response = self
+                .client
+                .get(format!(
+                    "{}/relay/v1/data/validator_registration?pubkey={pubkey}",
+                    relay.url
+                ))
+                .send()
+                .await?;
+            // relays answer 400 or 404 for validators that never registered with them
+            if matches!(
+                response.status(),
+                reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::BAD_REQUEST
+            ) {
+                continue;
+            }
+            let registration: SignedRegistration = response.error_for_status()?.json().await?;
+            return Ok(Some(registration.message.fee_recipient));
+        }
+        Ok(None)
+    }
+}
+
+/// Relays a stored row was delivered by.
+fn relay_names(entry: &BoostRelayDataEntry) -> impl Iterator<Item = &str> {
+    entry
+        .relay
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .filter(|name| !name.is_empty())
+}
+
+/// Brings the relay dataset at `path` up to date, fetching only slots newer than the last one
+/// stored for every relay.
+///
+/// The dataset has the input format of the `file` command with one row per slot. When several
+/// relays delivered the same block their names are joined with commas in the `relay` column.
+pub async fn sync(path: &Path, relays: &[Relay]) -> eyre::Result<()> {
+    let mut by_slot: BTreeMap<u64, BoostRelayDataEntry> = BTreeMap::new();
+    if path.exists() {
+        let reader = Dialect::for_path(path).reader(open_input(path)?);
+        for entry in reader.into_deserialize::<BoostRelayDataEntry>() {
+            let entry = entry?;
+            by_slot.insert(entry.slot, entry);
+        }
+    }
+    let stored = by_slot.len();
+
+    let client = crate::rpc::http_client();
+    for relay in relays {
+        let last_synced = by_slot
+            .values()
+            .filter(|entry| relay_names(entry).any(|name| name == relay.name))
+            .map(|entry| entry.slot)
+            .max();
+        let entries = match fetch_delivered(&client, relay, last_synced).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::error!(relay = %relay.name, error = %format!("{e:#}"), "failed to sync relay");
+                continue;
+            }
+        };
+        tracing::info!(relay = %relay.name, last_synced, new = entries.len(), "synced relay");
+
+        for entry in entries {
+            match by_slot.get_mut(&entry.slot) {
+                Some(existing) => {
+                    if existing.block_hash != entry.block_hash {
+                        tracing::warn!(
+                            slot = entry.slot,
+                            relay = %relay.name,
+                            "relays disagree on the delivered block, keeping the stored one"
+                        );
+                    }
+                    if !relay_names(existing).any(|name| name == relay.name) {
+                        let mut names: Vec<&str> = relay_names(existing).collect();
+                        names.push(&relay.name);
+                        names.sort_unstable();
+                        existing.relay = Some(names.join(","));
+                    }
+                }
+                None => {
+                    by_slot.insert(entry.slot, entry);
+                }
+            }
+        }
+    }
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let dialect = Dialect::for_path(path);
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(dialect.delimiter)
+        .from_writer(create_output_as(tmp.as_ref(), Compression::of(path))?);
+    for entry in by_slot.values() {
+        writer.serialize(entry)?;
+    }
+    drop(writer.into_inner().map_err(|e| e.into_error())?);
+    std::fs::rename(&tmp, path)?;
+    tracing::info!(
+        rows = by_slot.len(),
+        new = by_slot.len() - stored,
+        "synced {}",
+        path.display()
+    );
+    Ok(())
+}
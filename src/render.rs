@@ -0,0 +1,406 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::chain::ChainProfile;
+use crate::summarize::{Bucket, GroupBy, GroupTotals};
+use crate::{format_eth, read_output_totals, OutputFileEntry, Tolerance};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// Standalone page with inline styles.
+    Html,
+    Markdown,
+}
+
+/// Groups listed in the per-builder and per-relay tables, by number of blocks.
+const TOP_GROUPS: usize = 20;
+
+/// Width of the longest bar of a Markdown chart, in characters.
+const MARKDOWN_BAR_WIDTH: usize = 40;
+
+struct Table {
+    title: String,
+    header: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+/// Horizontal bar chart.
+struct Chart {
+    title: String,
+    bars: Vec<(String, f64)>,
+}
+
+enum Section {
+    Table(Table),
+    Chart(Chart),
+}
+
+/// Renders an output file as a report with the headline numbers, per-builder and per-relay
+/// tables, the `worst` shortfalls below the bid and charts of the payment types and the daily
+/// underpayments. Written to `output`, or stdout without one.
+pub fn render(
+    input: &Path,
+    output: Option<&Path>,
+    format: ReportFormat,
+    chain: &ChainProfile,
+    worst: usize,
+    tolerance: &Tolerance,
+) -> eyre::Result<()> {
+    let entries = read_output_totals(input, tolerance)?;
+    let title = format!(
+        "Proposer payments, {}",
+        input.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let sections = vec![
+        Section::Table(headline(&entries)),
+        Section::Chart(payment_types(&entries)),
+        Section::Chart(underpaid_by_day(&entries, chain)),
+        Section::Table(groups(&entries, GroupBy::Builder, "Builders")),
+        Section::Table(groups(&entries, GroupBy::Relay, "Relays")),
+        Section::Table(worst_shortfalls(&entries, worst)),
+    ];
+    let report = match format {
+        ReportFormat::Html => html(&title, &sections),
+        ReportFormat::Markdown => markdown(&title, &sections),
+    };
+    match output {
+        Some(path) => std::fs::write(path, report)?,
+        None => print!("{report}"),
+    }
+    Ok(())
+}
+
+fn pct(part: u64, whole: u64) -> String {
+    if whole == 0 {
+        return "-".to_string();
+    }
+    format!("{:.2}%", part as f64 * 100.0 / whole as f64)
+}
+
+fn paid_in_full(totals: &GroupTotals) -> String {
+    totals
+        .paid_in_full_pct()
+        .map_or("-".to_string(), |pct| format!("{pct:.2}%"))
+}
+
+fn headline(entries: &[OutputFileEntry]) -> Table {
+    let mut totals = GroupTotals::default();
+    for entry in entries {
+        totals.add(entry);
+    }
+    let slots = match (
+        entries.iter().map(|e| e.slot).min(),
+        entries.iter().map(|e| e.slot).max(),
+    ) {
+        (Some(first), Some(last)) => format!("{first} to {last}"),
+        _ => "-".to_string(),
+    };
+    let dates = match (
+        entries
+            .iter()
+            .map(|e| &e.date_utc)
+            .filter(|d| !d.is_empty())
+            .min(),
+        entries
+            .iter()
+            .map(|e| &e.date_utc)
+            .filter(|d| !d.is_empty())
+            .max(),
+    ) {
+        (Some(first), Some(last)) => format!("{first} to {last}"),
+        _ => "-".to_string(),
+    };
+    let rows = [
+        ("slots", slots),
+        ("dates (UTC)", dates),
+        ("blocks", totals.blocks.to_string()),
+        ("total bid value (ETH)", format_eth(totals.bid_value)),
+        ("total observed payment (ETH)", format_eth(totals.observed)),
+        ("average delta (ETH)", format_eth(totals.avg_delta())),
+        ("paid in full", paid_in_full(&totals)),
+        (
+            "underpaid",
+            format!(
+                "{} ({})",
+                totals.underpaid,
+                pct(totals.underpaid, totals.blocks)
+            ),
+        ),
+        ("overpaid", totals.overpaid.to_string()),
+        ("vertically integrated", totals.integrated.to_string()),
+        ("missed slots", totals.missed.to_string()),
+        (
+            "unknown payment",
+            format!(
+                "{} ({})",
+                totals.payment_type("unknown"),
+                pct(totals.payment_type("unknown"), totals.blocks)
+            ),
+        ),
+    ];
+    Table {
+        title: "Summary".to_string(),
+        header: vec!["", ""],
+        rows: rows
+            .into_iter()
+            .map(|(label, value)| vec![label.to_string(), value])
+            .collect(),
+    }
+}
+
+fn payment_types(entries: &[OutputFileEntry]) -> Chart {
+    let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+    for entry in entries.iter().filter(|entry| !entry.is_missed()) {
+        *counts.entry(entry.payment_type.as_str()).or_default() += 1;
+    }
+    let mut bars: Vec<(String, f64)> = counts
+        .into_iter()
+        .map(|(payment_type, count)| (payment_type.to_string(), count as f64))
+        .collect();
+    bars.sort_by(|a, b| b.1.total_cmp(&a.1));
+    Chart {
+        title: "Blocks by payment type".to_string(),
+        bars,
+    }
+}
+
+fn underpaid_by_day(entries: &[OutputFileEntry], chain: &ChainProfile) -> Chart {
+    let mut days: BTreeMap<String, GroupTotals> = BTreeMap::new();
+    for entry in entries.iter().filter(|entry| !entry.is_missed()) {
+        days.entry(Bucket::Day.key(chain, entry.slot))
+            .or_default()
+            .add(entry);
+    }
+    Chart {
+        title: "Underpaid blocks by day (% of blocks)".to_string(),
+        bars: days
+            .into_iter()
+            .map(|(day, totals)| {
+                let share = totals.underpaid as f64 * 100.0 / totals.blocks as f64;
+                (day, share)
+            })
+            .collect(),
+    }
+}
+
+fn groups(entries: &[OutputFileEntry], group_by: GroupBy, title: &str) -> Table {
+    let mut groups: BTreeMap<String, GroupTotals> = BTreeMap::new();
+    for entry in entries.iter().filter(|entry| group_by.includes(entry)) {
+        groups.entry(group_by.key(entry)).or_default().add(entry);
+    }
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by(|a, b| b.1.blocks.cmp(&a.1.blocks).then_with(|| a.0.cmp(&b.0)));
+    let listed = groups.len().min(TOP_GROUPS);
+    Table {
+        title: if groups.len() > listed {
+            format!("{title} (top {listed} of {} by blocks)", groups.len())
+        } else {
+            title.to_string()
+        },
+        header: vec![
+            "name",
+            "blocks",
+            "total bid (ETH)",
+            "average delta (ETH)",
+            "underpaid",
+            "unknown",
+            "paid in full",
+        ],
+        rows: groups
+            .into_iter()
+            .take(listed)
+            .map(|(group, totals)| {
+                vec![
+                    if group.is_empty() {
+                        "(unidentified)".to_string()
+                    } else {
+                        group
+                    },
+                    totals.blocks.to_string(),
+                    format_eth(totals.bid_value),
+                    format_eth(totals.avg_delta()),
+                    totals.underpaid.to_string(),
+                    totals.payment_type("unknown").to_string(),
+                    paid_in_full(&totals),
+                ]
+            })
+            .collect(),
+    }
+}
+
+/// Like `top-discrepancies`, vertically integrated blocks are left out.
+fn worst_shortfalls(entries: &[OutputFileEntry], n: usize) -> Table {
+    let mut worst: Vec<&OutputFileEntry> = entries
+        .iter()
+        .filter(|entry| !entry.vertically_integrated && entry.delta_wei.is_negative())
+        .collect();
+    worst.sort_unstable_by_key(|entry| entry.delta_wei);
+    Table {
+        title: "Largest shortfalls below the bid".to_string(),
+        header: vec![
+            "slot",
+            "builder",
+            "relay",
+            "bid (ETH)",
+            "observed (ETH)",
+            "shortfall (ETH)",
+            "payment type",
+        ],
+        rows: worst
+            .into_iter()
+            .take(n)
+            .map(|entry| {
+                vec![
+                    entry.slot.to_string(),
+                    entry.builder.clone().unwrap_or_default(),
+                    entry.relay.clone().unwrap_or_default(),
+                    format_eth(entry.bid_value),
+                    format_eth(entry.observed_payment),
+                    format_eth(-entry.delta_wei),
+                    entry.payment_type.clone(),
+                ]
+            })
+            .collect(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html(title: &str, sections: &[Section]) -> String {
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n\
+         body {{ font-family: sans-serif; max-width: 60em; margin: 2em auto; }}\n\
+         table {{ border-collapse: collapse; margin-bottom: 2em; }}\n\
+         td, th {{ border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: right; }}\n\
+         td:first-child, th:first-child {{ text-align: left; }}\n\
+         .chart {{ margin-bottom: 2em; }}\n\
+         .row {{ display: flex; align-items: center; }}\n\
+         .label {{ width: 12em; }}\n\
+         .bar {{ background: #4a7ab5; height: 1em; margin-right: 0.5em; }}\n\
+         </style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape(title),
+        escape(title)
+    );
+    for section in sections {
+        match section {
+            Section::Table(table) => {
+                let _ = writeln!(out, "<h2>{}</h2>\n<table>", escape(&table.title));
+                if table.header.iter().any(|column| !column.is_empty()) {
+                    out.push_str("<tr>");
+                    for column in &table.header {
+                        let _ = write!(out, "<th>{}</th>", escape(column));
+                    }
+                    out.push_str("</tr>\n");
+                }
+                for row in &table.rows {
+                    out.push_str("<tr>");
+                    for field in row {
+                        let _ = write!(out, "<td>{}</td>", escape(field));
+                    }
+                    out.push_str("</tr>\n");
+                }
+                out.push_str("</table>\n");
+            }
+            Section::Chart(chart) => {
+                let _ = writeln!(
+                    out,
+                    "<h2>{}</h2>\n<div class=\"chart\">",
+                    escape(&chart.title)
+                );
+                let max = chart.bars.iter().map(|(_, v)| *v).fold(0.0, f64::max);
+                for (label, value) in &chart.bars {
+                    let width = if max > 0.0 { value * 30.0 / max } else { 0.0 };
+                    let _ = writeln!(
+                        out,
+                        "<div class=\"row\"><span class=\"label\">{}</span>\
+                         <span class=\"bar\" style=\"width: {width:.2}em\"></span>{}</div>",
+                        escape(label),
+                        format_value(*value)
+                    );
+                }
+                out.push_str("</div>\n");
+            }
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn markdown(title: &str, sections: &[Section]) -> String {
+    let mut out = format!("# {title}\n");
+    for section in sections {
+        match section {
+            Section::Table(table) => {
+                let _ = writeln!(out, "\n## {}\n", table.title);
+                let cell = |field: &str| field.replace('|', "\\|");
+                let _ = writeln!(
+                    out,
+                    "| {} |",
+                    table
+                        .header
+                        .iter()
+                        .map(|column| cell(column))
+                        .collect::<Vec<_>>()
+                        .join(" | ")
+                );
+                let _ = writeln!(
+                    out,
+                    "|{}",
+                    table
+                        .header
+                        .iter()
+                        .enumerate()
+                        .map(|(i, _)| if i == 0 { " --- |" } else { " ---: |" })
+                        .collect::<String>()
+                );
+                for row in &table.rows {
+                    let _ = writeln!(
+                        out,
+                        "| {} |",
+                        row.iter()
+                            .map(|field| cell(field))
+                            .collect::<Vec<_>>()
+                            .join(" | ")
+                    );
+                }
+            }
+            Section::Chart(chart) => {
+                let _ = writeln!(out, "\n## {}\n\n```", chart.title);
+                let max = chart.bars.iter().map(|(_, v)| *v).fold(0.0, f64::max);
+                let label_width = chart.bars.iter().map(|(l, _)| l.len()).max().unwrap_or(0);
+                for (label, value) in &chart.bars {
+                    let width = if max > 0.0 {
+                        (value * MARKDOWN_BAR_WIDTH as f64 / max).round() as usize
+                    } else {
+                        0
+                    };
+                    let _ = writeln!(
+                        out,
+                        "{label:<label_width$} {} {}",
+                        "█".repeat(width),
+                        format_value(*value)
+                    );
+                }
+                out.push_str("```\n");
+            }
+        }
+    }
+    out
+}
+
+/// Counts without decimals, shares with two.
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{value:.0}")
+    } else {
+        format!("{value:.2}")
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use ethers::types::Address;
+
+use crate::{
+    format_eth, gwei_to_wei, BlockProposerPaymentData, OutputFileEntry, ProposerPayment,
+    UnknownReason,
+};
+
+/// Prints the human readable report of the `block` command: the block, the fee recipient, how
+/// the payment was classified and every transfer and withdrawal behind it. Addresses with an
+/// entry in `names` are annotated with it.
+pub fn print_block(
+    data: &BlockProposerPaymentData,
+    entry: &OutputFileEntry,
+    names: &HashMap<Address, String>,
+) {
+    let address = |address: Address| match names.get(&address) {
+        Some(name) => format!("{address:?} ({name})"),
+        None => format!("{address:?}"),
+    };
+
+    println!(
+        "block {} (slot {}, {})",
+        data.block_number, entry.slot, entry.date_utc
+    );
+    if let Some(hash) = data.block_hash {
+        println!("  hash:              {hash:?}");
+    }
+    println!("  era:               {}", entry.era);
+    println!(
+        "  builder:           {}",
+        entry.builder.as_deref().unwrap_or("unknown")
+    );
+    println!(
+        "  extra data:        {}",
+        String::from_utf8_lossy(&data.extra_data)
+    );
+    println!(
+        "  txs:               {}, {} of {} gas ({:.1}%)",
+        data.tx_count, data.gas_used, data.gas_limit, entry.block_fullness_pct
+    );
+    println!(
+        "  builder value:     {} ETH",
+        format_eth(data.builder_block_value)
+    );
+
+    println!();
+    println!("fee recipient {}", address(data.fee_recipient));
+    println!(
+        "  kind:              {} ({})",
+        entry.recipient_kind, entry.fee_recipient_account_kind
+    );
+    if let Some(label) = &entry.fee_recipient_label {
+        println!("  label:             {label}");
+    }
+    if let (Some(before), Some(after)) = (data.balance_before, data.balance_after) {
+        println!("  balance before:    {} ETH", format_eth(before));
+        println!("  balance after:     {} ETH", format_eth(after));
+    }
+    println!("  balance diff:      {} ETH", entry.balance_diff_eth);
+
+    println!();
+    println!("classification: {}", entry.payment_type);
+    println!("  {}", explain(&data.payment, &address));
+    println!("  bid:               {} ETH", entry.bid_value_eth);
+    println!(
+        "  observed payment:  {} ETH",
+        format_eth(entry.observed_payment)
+    );
+    println!("  delta:             {} ETH", format_eth(entry.delta_wei));
+    if entry.underpaid == Some(true) {
+        println!("  underpaid beyond the tolerance");
+    }
+    if let Some(overpaid_by) = entry.overpaid_by {
+        println!("  overpaid by {} ETH", format_eth(overpaid_by));
+    }
+    for transfer in &data.payment_side_transfers {
+        println!(
+            "  the payment tx also paid {} ETH to {}",
+            format_eth(transfer.value),
+            address(transfer.to)
+        );
+    }
+    if entry.vertically_integrated {
+        println!("  vertically integrated, the payment doesn't reflect a competitive bid");
+    }
+    match entry.unexplained_diff {
+        Some(diff) if !diff.is_zero() => println!(
+            "  {} ETH of the balance change is not explained by transfers, withdrawals or fees",
+            format_eth(diff)
+        ),
+        Some(_) => {}
+        None => println!("  the block could not be traced, internal transfers are missing"),
+    }
+
+    let payment_indices = data.payment_transfer_indices();
+    println!();
+    println!("transfers ({}):", data.fee_recipient_transfers.len());
+    for (i, transfer) in data.fee_recipient_transfers.iter().enumerate() {
+        println!(
+            "  {:?} {} -> {} {} ETH{}",
+            transfer.tx_hash,
+            address(transfer.from),
+            address(transfer.to),
+            format_eth(transfer.value),
+            if payment_indices.contains(&i) {
+                " (payment)"
+            } else {
+                ""
+            }
+        );
+    }
+
+    println!();
+    println!("withdrawals ({}):", data.fee_recipient_withdrawals.len());
+    for withdrawal in &data.fee_recipient_withdrawals {
+        println!(
+            "  validator {} {} ETH",
+            withdrawal.validator_index,
+            format_eth(gwei_to_wei(withdrawal.amount))
+        );
+    }
+}
+
+/// Why the block got its classification, in a sentence.
+fn explain(payment: &ProposerPayment, address: &dyn Fn(Address) -> String) -> String {
+    match payment {
+        ProposerPayment::LastTxDirect {
+            tx_hash,
+            from,
+            value,
+            ..
+        } => format!(
+            "the last tx {tx_hash:?} from {} sends {} ETH directly to the fee recipient",
+            address(*from),
+            format_eth(*value)
+        ),
+        ProposerPayment::LastTxContract {
+            tx_hash,
+            from,
+            contract,
+            value,
+        } if contract == from => format!(
+            "the last tx {tx_hash:?} from {} runs its delegated code, which transfers {} ETH to the fee recipient",
+            address(*from),
+            format_eth(*value)
+        ),
+        ProposerPayment::LastTxContract {
+            tx_hash,
+            from,
+            contract,
+            value,
+        } => format!(
+            "the last tx {tx_hash:?} from {} calls {}, which transfers {} ETH to the fee recipient",
+            address(*from),
+            address(*contract),
+            format_eth(*value)
+        ),
+        ProposerPayment::LastTxReverted {
+            tx_hash,
+            from,
+            value,
+            ..
+        } => format!(
+            "the last tx {tx_hash:?} from {} would pay {} ETH to the fee recipient but reverted",
+            address(*from),
+            format_eth(*value)
+        ),
+        ProposerPayment::Coinbase { value, .. } => format!(
+            "the fee recipient is the coinbase and received {} ETH in priority fees and direct transfers",
+            format_eth(*value)
+        ),
+        ProposerPayment::Searcher {
+            tx_hash,
+            from,
+            value,
+        } => format!(
+            "the builder didn't pay, txs of others transferred {} ETH to the fee recipient, the largest {tx_hash:?} from {}",
+            format_eth(*value),
+            address(*from)
+        ),
+        ProposerPayment::EntryPoint {
+            tx_hash,
+            from,
+            value,
+        } => format!(
+            "ERC-4337 bundles paid {} ETH to the fee recipient, the largest {tx_hash:?} bundled by {}",
+            format_eth(*value),
+            address(*from)
+        ),
+        ProposerPayment::Rule {
+            rule,
+            tx_hash,
+            from,
+            to,
+            value,
+        } => format!(
+            "rule {rule} matched tx {tx_hash:?} from {} to {}, which transfers {} ETH to the fee recipient",
+            address(*from),
+            address(*to),
+            format_eth(*value)
+        ),
+        ProposerPayment::Unknown(reason) => format!(
+            "no payment identified: {}",
+            match reason {
+                UnknownReason::EmptyBlock => "the block has no transactions",
+                UnknownReason::LastTxNotToRecipient =>
+                    "the fee recipient received transfers, but not from the last tx",
+                UnknownReason::NoMatchingTransfer =>
+                    "nothing in the block transferred value to the fee recipient",
+                UnknownReason::TraceUnavailable =>
+                    "the block could not be traced, so internal payments can't be seen",
+                UnknownReason::PreMerge => "proof-of-work block, mined before the merge",
+            }
+        ),
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use ethers::types::{Address, H256};
+
+use crate::{BlockProposerPaymentData, ProcessingOptions};
+
+/// On-disk store of computed payment data keyed by block and fee recipient, so a block already
+/// processed for one input is not traced again for another.
+///
+/// Entries are kept per chain, trace backend, transfer actions and classification rules since
+/// those change the result. The bid value and builder are filled in from the current run on a hit.
+#[derive(Debug, Clone)]
+pub struct ResultsCache {
+    pub dir: PathBuf,
+}
+
+/// FNV-1a, a hash that stays the same across builds unlike the std hasher.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl ResultsCache {
+    fn path(
+        &self,
+        options: &ProcessingOptions,
+        block_number: u64,
+        fee_recipient: Address,
+    ) -> PathBuf {
+        let actions = options
+            .transfer_actions
+            .0
+            .iter()
+            .map(|action| format!("{action:?}").to_lowercase())
+            .collect::<Vec<_>>()
+            .join("_");
+        let rules = if options.rules.is_empty() {
+            String::new()
+        } else {
+            let rules = serde_json::to_vec(&options.rules).expect("rules serialize");
+            format!("-rules{:016x}", fnv1a(&rules))
+        };
+        self.dir
+            .join(
+                format!(
+                    "{}-{:?}-{actions}{rules}",
+                    options.chain.name, options.trace_backend
+                )
+                .to_lowercase(),
+            )
+            .join(block_number.to_string())
+            .join(format!("{fee_recipient:?}.json"))
+    }
+
+    /// Cached data of the block, `None` when missing or for a different block hash.
+    pub async fn get(
+        &self,
+        options: &ProcessingOptions,
+        block_number: u64,
+        fee_recipient: Address,
+        block_hash: Option<H256>,
+    ) -> Option<BlockProposerPaymentData> {
+        let path = self.path(options, block_number, fee_recipient);
+        let cached: BlockProposerPaymentData =
+            serde_json::from_slice(&tokio::fs::read(&path).await.ok()?)
+                .map_err(
+                    |e| tracing::warn!(error = %e, path = %path.display(), "invalid cached result"),
+                )
+                .ok()?;
+        // the block was reorged since it was cached, or the input names another one
+        if block_hash.is_some() && block_hash != cached.block_hash {
+            return None;
+        }
+        Some(cached)
+    }
+
+    pub async fn put(
+        &self,
+        options: &ProcessingOptions,
+        data: &BlockProposerPaymentData,
+    ) -> eyre::Result<()> {
+        let path = self.path(options, data.block_number, data.fee_recipient);
+        tokio::fs::create_dir_all(path.parent().expect("cache path has a parent")).await?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(data)?).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+}
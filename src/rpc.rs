@@ -0,0 +1,785 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ethers::prelude::*;
+use ethers::providers::{HttpClientError, JsonRpcError, RpcError};
+use once_cell::sync::OnceCell;
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::metrics;
+
+pub type EthProvider = Provider<Client>;
+
+/// JSON-RPC transport the calls that aren't answered from the cache are sent over.
+#[derive(Debug, Clone)]
+pub enum Transport {
+    Http {
+        url: Url,
+        client: reqwest::Client,
+    },
+    /// No endpoint, every call has to be answered from the [ResponseCache].
+    Offline,
+}
+
+fn not_cached(method: &str) -> ProviderError {
+    ProviderError::CustomError(format!("{method} response is not in the cache"))
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    id: usize,
+    #[serde(default)]
+    result: Option<serde_json::Value>,
+    #[serde(default)]
+    error: Option<JsonRpcError>,
+}
+
+/// Posts a JSON-RPC request. The HTTP status is checked before the body is decoded: a rate
+/// limited or failing endpoint often answers with a text or HTML page, which would otherwise
+/// fail as a malformed response and not be retried.
+async fn post<R: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &Url,
+    body: &serde_json::Value,
+) -> Result<R, ProviderError> {
+    async {
+        client
+            .post(url.clone())
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<R>()
+            .await
+    }
+    .await
+    .map_err(ProviderError::HTTPError)
+}
+
+fn error_response(error: JsonRpcError) -> ProviderError {
+    ProviderError::JsonRpcClientError(Box::new(HttpClientError::JsonRpcError(error)))
+}
+
+impl Transport {
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, ProviderError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let res = match self {
+            Transport::Http { url, client } => {
+                async {
+                    let body = serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": 0,
+                        "method": method,
+                        "params": serde_json::to_value(params)?,
+                    });
+                    let response: RpcResponse = post(client, url, &body).await?;
+                    match response.error {
+                        Some(error) => Err(error_response(error)),
+                        None => Ok(serde_json::from_value(response.result.unwrap_or_default())?),
+                    }
+                }
+                .await
+            }
+            Transport::Offline => Err(not_cached(method)),
+        };
+        if res.is_err() {
+            metrics::RPC_ERRORS.with_label_values(&[method]).inc();
+        }
+        res
+    }
+
+    /// Sends `calls` as one JSON-RPC batch.
+    async fn batch(
+        &self,
+        calls: &[(&str, serde_json::Value)],
+    ) -> Result<Vec<serde_json::Value>, ProviderError> {
+        let (url, client) = match self {
+            Transport::Http { url, client } => (url, client),
+            Transport::Offline => return Err(not_cached(calls[0].0)),
+        };
+
+        let body: Vec<_> = calls
+            .iter()
+            .enumerate()
+            .map(|(id, (method, params))| {
+                serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+            })
+            .collect();
+        let responses: Vec<RpcResponse> = post(client, url, &serde_json::Value::Array(body))
+            .await
+            .map_err(|e| {
+                metrics::RPC_ERRORS.with_label_values(&["batch"]).inc();
+                e
+            })?;
+
+        let mut results = vec![None; calls.len()];
+        for response in responses {
+            let (method, _) = calls
+                .get(response.id)
+                .ok_or_else(|| ProviderError::CustomError("unexpected batch response id".into()))?;
+            if let Some(error) = response.error {
+                metrics::RPC_ERRORS.with_label_values(&[method]).inc();
+                return Err(error_response(error));
+            }
+            results[response.id] = Some(response.result.unwrap_or_default());
+        }
+        results
+            .into_iter()
+            .map(|result| {
+                result.ok_or_else(|| ProviderError::CustomError("missing batch response".into()))
+            })
+            .collect()
+    }
+}
+
+/// On-disk cache of raw responses for calls whose result can't change, i.e. ones pinned to a
+/// block number or a tx hash.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    pub dir: PathBuf,
+    /// Skip cached responses but still write fresh ones.
+    pub refresh: bool,
+    /// Store every call, also ones whose result changes over time and empty results, so a run
+    /// can be replayed exactly without an endpoint.
+    pub fixtures: bool,
+}
+
+const CACHED_METHODS: &[&str] = &[
+    "trace_block",
+    "trace_filter",
+    "debug_traceBlockByNumber",
+    "eth_getBlockByNumber",
+    "eth_getBalance",
+    "eth_getCode",
+    "eth_getTransactionReceipt",
+    "eth_getBlockReceipts",
+    "eth_getTransactionByBlockNumberAndIndex",
+    "trace_replayBlockTransactions",
+];
+
+impl ResponseCache {
+    fn path<T: Serialize>(&self, method: &str, params: &T) -> Option<PathBuf> {
+        if !self.fixtures && !CACHED_METHODS.contains(&method) {
+            return None;
+        }
+        let params = serde_json::to_string(params).ok()?;
+        let key = params
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join("_");
+        // block tags resolve to different blocks over time
+        if !self.fixtures
+            && ["latest", "pending", "safe", "finalized", "earliest"]
+                .iter()
+                .any(|tag| key.contains(tag))
+        {
+            return None;
+        }
+        let key = if key.is_empty() {
+            "none".to_string()
+        } else {
+            key
+        };
+        Some(self.dir.join(method).join(format!("{key}.json")))
+    }
+
+    async fn read(&self, path: &PathBuf) -> Option<serde_json::Value> {
+        if self.refresh {
+            return None;
+        }
+        let cached = tokio::fs::read(path).await.ok()?;
+        serde_json::from_slice(&cached).ok()
+    }
+
+    async fn write(&self, path: &PathBuf, value: &serde_json::Value) -> Result<(), ProviderError> {
+        if value.is_null() && !self.fixtures {
+            return Ok(());
+        }
+        // written next to the entry and renamed, so neither a crash nor a concurrent reader sees a
+        // partly written response. Concurrent entries can fetch the same response, so every write
+        // gets its own temporary file.
+        static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(
+            ".{}.{}.tmp",
+            std::process::id(),
+            NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+        ));
+        let write = async {
+            tokio::fs::create_dir_all(path.parent().expect("cache path has a parent")).await?;
+            tokio::fs::write(&tmp, serde_json::to_vec(value)?).await?;
+            tokio::fs::rename(&tmp, path).await
+        };
+        write
+            .await
+            .map_err(|e: std::io::Error| ProviderError::CustomError(e.to_string()))
+    }
+}
+
+/// Limits on how long a single request may take, so a hung one fails and gets retried instead of
+/// stalling its entry forever.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub request: Duration,
+    /// For block traces, which take far longer than anything else.
+    pub trace: Duration,
+}
+
+const TRACE_METHODS: &[&str] = &[
+    "trace_block",
+    "trace_filter",
+    "debug_traceBlockByNumber",
+    "trace_replayBlockTransactions",
+];
+
+impl Timeouts {
+    fn of(&self, method: &str) -> Duration {
+        if TRACE_METHODS.contains(&method) {
+            self.trace
+        } else {
+            self.request
+        }
+    }
+}
+
+/// JSON-RPC error codes of rate limits, `-32005` being the "limit exceeded" of EIP-1474.
+const RATE_LIMITED: &[i64] = &[-32005, 429];
+
+/// Whether a failed call may succeed when sent again: timeouts, transport errors, rate limits and
+/// server errors. Other error responses of the node, like a missing method or pruned state, would
+/// fail again.
+fn is_transient(error: &ProviderError) -> bool {
+    if let ProviderError::HTTPError(e) = error {
+        return e.status().map_or(true, |status| {
+            status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+        });
+    }
+    match RpcError::as_error_response(error) {
+        Some(response) => RATE_LIMITED.contains(&response.code),
+        None => RpcError::as_serde_error(error).is_none(),
+    }
+}
+
+/// Whether `error` tells the endpoint is overloaded: rate limited or timing out. Entries failing
+/// this way make the concurrency back off.
+pub fn is_throttled(error: &ProviderError) -> bool {
+    match error {
+        ProviderError::HTTPError(e) => is_throttled_http(e),
+        ProviderError::CustomError(message) => message.contains(" timed out after "),
+        _ => RpcError::as_error_response(error).map_or(false, |response| {
+            let message = response.message.to_lowercase();
+            RATE_LIMITED.contains(&response.code)
+                || ["rate limit", "too many requests"]
+                    .iter()
+                    .any(|pattern| message.contains(pattern))
+        }),
+    }
+}
+
+/// Whether an HTTP request timed out or was answered with 429 Too Many Requests.
+pub fn is_throttled_http(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS)
+}
+
+/// Delay before retry `attempt` (from 1) of a call.
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(250 << attempt.min(6))
+}
+
+async fn with_timeout<R>(
+    method: &str,
+    timeout: Duration,
+    request: impl std::future::Future<Output = Result<R, ProviderError>>,
+) -> Result<R, ProviderError> {
+    tokio::time::timeout(timeout, request)
+        .await
+        .unwrap_or_else(|_| {
+            metrics::RPC_ERRORS.with_label_values(&[method]).inc();
+            Err(ProviderError::CustomError(format!(
+                "{method} timed out after {}s",
+                timeout.as_secs_f64()
+            )))
+        })
+}
+
+/// Balances at the end of recently fetched blocks. The balance after block N is the balance
+/// before block N + 1, so consecutive blocks paying the same fee recipient share one call.
+#[derive(Debug, Clone, Default)]
+pub struct BalanceMemo(Arc<Mutex<HashMap<(Address, u64), U256>>>);
+
+impl BalanceMemo {
+    const MAX_ENTRIES: usize = 16_384;
+    /// Blocks kept once the memo is full, entries are processed roughly in block order.
+    const KEEP_BLOCKS: u64 = 1_024;
+
+    fn get(&self, address: Address, block_number: u64) -> Option<U256> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&(address, block_number))
+            .copied()
+    }
+
+    fn insert(&self, address: Address, block_number: u64, balance: U256) {
+        let mut balances = self.0.lock().unwrap();
+        balances.insert((address, block_number), balance);
+        if balances.len() > Self::MAX_ENTRIES {
+            let oldest = block_number.saturating_sub(Self::KEEP_BLOCKS);
+            balances.retain(|(_, block), _| *block >= oldest);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    transport: Transport,
+    cache: Option<ResponseCache>,
+    timeouts: Timeouts,
+    balances: BalanceMemo,
+    /// Set once the node answered a trace call with method not found, later blocks aren't traced.
+    traces_unsupported: Arc<AtomicBool>,
+    /// Times a call other than a trace is sent again after a transient failure, so a flaky
+    /// balance query doesn't fail an entry whose traces were already fetched.
+    retries: u32,
+}
+
+#[async_trait]
+impl JsonRpcClient for Client {
+    type Error = ProviderError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let Some((cache, path)) = self
+            .cache
+            .as_ref()
+            .and_then(|c| Some((c, c.path(method, &params)?)))
+        else {
+            return self.send(method, &params).await;
+        };
+
+        if let Some(cached) = cache.read(&path).await {
+            return Ok(serde_json::from_value(cached)?);
+        }
+
+        let value: serde_json::Value = self.send(method, &params).await?;
+        cache.write(&path, &value).await?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+impl Client {
+    /// Balance of `address` after `block_number` if an earlier block fetched it. Never with a
+    /// response cache or fixtures: every call has to reach them, as a replay may process the
+    /// blocks in another order and miss the balances the memo answered.
+    fn memoized_balance(&self, address: Address, block_number: u64) -> Option<U256> {
+        if self.cache.is_some() {
+            return None;
+        }
+        self.balances.get(address, block_number)
+    }
+
+    /// The client without its response cache, for calls whose response can still change, like
+    /// those about blocks that aren't finalized. Fixtures still record every call.
+    pub fn uncached(&self) -> Self {
+        Self {
+            cache: self.cache.clone().filter(|cache| cache.fixtures),
+            ..self.clone()
+        }
+    }
+
+    /// Whether the node answered an earlier trace call with method not found.
+    pub fn traces_unsupported(&self) -> bool {
+        self.traces_unsupported.load(Ordering::Relaxed)
+    }
+
+    /// Stops tracing later blocks.
+    pub fn set_traces_unsupported(&self) {
+        self.traces_unsupported.store(true, Ordering::Relaxed);
+    }
+
+    /// Retries of `methods`, traces are too expensive to send again and have their own timeout.
+    fn retries_of<'a>(&self, mut methods: impl Iterator<Item = &'a str>) -> u32 {
+        if methods.any(|method| TRACE_METHODS.contains(&method)) {
+            0
+        } else {
+            self.retries
+        }
+    }
+
+    /// Sends a call to the endpoint, retrying transient failures.
+    async fn send<T, R>(&self, method: &str, params: &T) -> Result<R, ProviderError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let timeout = self.timeouts.of(method);
+        let retries = self.retries_of(std::iter::once(method));
+        let mut attempt = 0;
+        loop {
+            match with_timeout(method, timeout, self.transport.request(method, params)).await {
+                Err(e) if attempt < retries && is_transient(&e) => {
+                    attempt += 1;
+                    metrics::RPC_RETRIES.with_label_values(&[method]).inc();
+                    tracing::debug!(method, attempt, error = %e, "retrying");
+                    tokio::time::sleep(backoff(attempt)).await;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Sends several calls in a single batch, answering the ones in the cache from it.
+    pub async fn batch(
+        &self,
+        calls: Vec<(&str, serde_json::Value)>,
+    ) -> Result<Vec<serde_json::Value>, ProviderError> {
+        let mut results = vec![None; calls.len()];
+        let mut paths = vec![None; calls.len()];
+        if let Some(cache) = &self.cache {
+            for (i, (method, params)) in calls.iter().enumerate() {
+                if let Some(path) = cache.path(method, params) {
+                    results[i] = cache.read(&path).await;
+                    paths[i] = Some(path);
+                }
+            }
+        }
+
+        let missing: Vec<usize> = (0..calls.len()).filter(|i| results[*i].is_none()).collect();
+        if !missing.is_empty() {
+            let batch: Vec<_> = missing.iter().map(|i| calls[*i].clone()).collect();
+            let timeout = batch
+                .iter()
+                .map(|(method, _)| self.timeouts.of(method))
+                .max()
+                .unwrap_or(self.timeouts.request);
+            let retries = self.retries_of(batch.iter().map(|(method, _)| *method));
+            let mut attempt = 0;
+            let fetched = loop {
+                match with_timeout("batch", timeout, self.transport.batch(&batch)).await {
+                    Err(e) if attempt < retries && is_transient(&e) => {
+                        attempt += 1;
+                        metrics::RPC_RETRIES.with_label_values(&["batch"]).inc();
+                        tracing::debug!(attempt, error = %e, "retrying batch");
+                        tokio::time::sleep(backoff(attempt)).await;
+                    }
+                    res => break res?,
+                }
+            };
+            for (i, value) in missing.into_iter().zip(fetched) {
+                if let (Some(cache), Some(path)) = (&self.cache, &paths[i]) {
+                    cache.write(path, &value).await?;
+                }
+                results[i] = Some(value);
+            }
+        }
+        Ok(results.into_iter().map(Option::unwrap_or_default).collect())
+    }
+}
+
+/// Where the balance change of the fee recipient comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BalanceSource {
+    /// `eth_getBalance` before and after the block, needs archive state.
+    #[default]
+    GetBalance,
+    /// `trace_replayBlockTransactions` with the `stateDiff` tracer plus withdrawals, cheaper on
+    /// Erigon and reth and works without deep archive state.
+    StateDiff,
+}
+
+/// Which tx bodies of a block to fetch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TxBodies {
+    #[default]
+    All,
+    /// Only the bodies of the last this many txs, the others are filled in from their receipts
+    /// with what the heuristics read of them: hash, sender, receiver, type and gas price. Their
+    /// value and input aren't known, see [BlockState::bodies_from].
+    Last(usize),
+}
+
+/// Block, its receipts and the balance of an address before and after it, fetched in one batch.
+#[derive(Debug, Clone)]
+pub struct BlockState {
+    pub block: Option<Block<Transaction>>,
+    pub receipts: Vec<TransactionReceipt>,
+    /// Both `None` with [BalanceSource::StateDiff].
+    pub balance_before: Option<U256>,
+    pub balance_after: Option<U256>,
+    /// Code of the address after the block, empty for an EOA.
+    pub code: Bytes,
+    /// Index of the first tx whose body was fetched. The txs before it were filled in from their
+    /// receipts with [TxBodies::Last], their value and input are zero rather than the real ones.
+    pub bodies_from: usize,
+}
+
+pub async fn fetch_block_state(
+    provider: &EthProvider,
+    block_number: u64,
+    address: Address,
+    source: BalanceSource,
+    bodies: TxBodies,
+) -> eyre::Result<BlockState> {
+    let client = provider.as_ref();
+    let block = BlockNumber::Number(block_number.into());
+    let parent = block_number.checked_sub(1);
+    let with_balances = source == BalanceSource::GetBalance;
+    // nothing was held before the genesis block
+    let known_before = match parent {
+        Some(parent) => client.memoized_balance(address, parent),
+        None => Some(U256::zero()),
+    };
+    let known_after = client.memoized_balance(address, block_number);
+    let mut calls = vec![
+        (
+            "eth_getBlockByNumber",
+            serde_json::json!([block, bodies == TxBodies::All]),
+        ),
+        ("eth_getBlockReceipts", serde_json::json!([block])),
+        ("eth_getCode", serde_json::json!([address, block])),
+    ];
+    if let (true, None, Some(parent)) = (with_balances, known_before, parent) {
+        let parent = BlockNumber::Number(parent.into());
+        calls.push(("eth_getBalance", serde_json::json!([address, parent])));
+    }
+    if with_balances && known_after.is_none() {
+        calls.push(("eth_getBalance", serde_json::json!([address, block])));
+    }
+    let mut results = client.batch(calls).await?.into_iter();
+    let mut next = || results.next().unwrap_or_default();
+    let block = next();
+    let receipts: Vec<TransactionReceipt> = serde_json::from_value(next())?;
+    let code = serde_json::from_value(next())?;
+    let (block, bodies_from) = match bodies {
+        TxBodies::All => (serde_json::from_value(block)?, 0),
+        TxBodies::Last(count) => {
+            let bodies_from = receipts.len().saturating_sub(count);
+            let block = with_last_tx_bodies(client, block, &receipts, bodies_from).await?;
+            (block, bodies_from)
+        }
+    };
+    if !with_balances {
+        return Ok(BlockState {
+            block,
+            receipts,
+            balance_before: None,
+            balance_after: None,
+            code,
+            bodies_from,
+        });
+    }
+    let balance_before = match known_before {
+        Some(balance) => balance,
+        None => serde_json::from_value(next())?,
+    };
+    let balance_after = match known_after {
+        Some(balance) => balance,
+        None => serde_json::from_value(next())?,
+    };
+    if let Some(parent) = parent {
+        client.balances.insert(address, parent, balance_before);
+    }
+    client.balances.insert(address, block_number, balance_after);
+    Ok(BlockState {
+        block,
+        receipts,
+        balance_before: Some(balance_before),
+        balance_after: Some(balance_after),
+        code,
+        bodies_from,
+    })
+}
+
+/// `block` fetched with tx hashes only, with the bodies of its txs from index `first_fetched` on
+/// fetched and the others filled in from `receipts`.
+async fn with_last_tx_bodies(
+    client: &Client,
+    mut block: serde_json::Value,
+    receipts: &[TransactionReceipt],
+    first_fetched: usize,
+) -> eyre::Result<Option<Block<Transaction>>> {
+    if block.is_null() {
+        return Ok(None);
+    }
+    let hashes = block["transactions"].as_array().map_or(0, Vec::len);
+    if hashes != receipts.len() {
+        eyre::bail!("block receipts don't match block transactions");
+    }
+    let number = block["number"].clone();
+    let calls = (first_fetched..receipts.len())
+        .map(|index| {
+            (
+                "eth_getTransactionByBlockNumberAndIndex",
+                serde_json::json!([number, U64::from(index)]),
+            )
+        })
+        .collect::<Vec<_>>();
+    let fetched = if calls.is_empty() {
+        Vec::new()
+    } else {
+        client.batch(calls).await?
+    };
+    let transactions = receipts[..first_fetched]
+        .iter()
+        .map(|receipt| {
+            serde_json::to_value(Transaction {
+                hash: receipt.transaction_hash,
+                block_hash: receipt.block_hash,
+                block_number: receipt.block_number,
+                transaction_index: Some(receipt.transaction_index),
+                from: receipt.from,
+                to: receipt.to,
+                gas_price: receipt.effective_gas_price,
+                transaction_type: receipt.transaction_type,
+                ..Default::default()
+            })
+        })
+        .chain(fetched.into_iter().map(Ok))
+        .collect::<Result<Vec<_>, _>>()?;
+    block["transactions"] = serde_json::Value::Array(transactions);
+    Ok(serde_json::from_value(block)?)
+}
+
+/// Balance change of `address` from the transactions of the block, summed from the `stateDiff`
+/// of every transaction. Withdrawals aren't part of any transaction and are not included.
+pub async fn state_diff_balance_change(
+    provider: &EthProvider,
+    block_number: u64,
+    address: Address,
+) -> eyre::Result<I256> {
+    let traces = provider
+        .trace_replay_block_transactions(
+            BlockNumber::Number(block_number.into()),
+            vec![TraceType::StateDiff],
+        )
+        .await?;
+    let mut change = I256::zero();
+    for trace in traces {
+        let Some(diff) = trace.state_diff.and_then(|d| d.0.get(&address).cloned()) else {
+            continue;
+        };
+        change += match diff.balance {
+            Diff::Same => I256::zero(),
+            Diff::Born(balance) => I256::from_raw(balance),
+            Diff::Died(balance) => -I256::from_raw(balance),
+            Diff::Changed(ChangedType { from, to }) => I256::from_raw(to) - I256::from_raw(from),
+        };
+    }
+    Ok(change)
+}
+
+/// Proxy for all outgoing HTTP traffic, set once at startup from `--proxy`.
+static PROXY: OnceCell<reqwest::Proxy> = OnceCell::new();
+
+/// Routes every HTTP client built afterwards through `url`, `http://`, `https://` or `socks5://`.
+pub fn set_proxy(url: &str) -> eyre::Result<()> {
+    PROXY
+        .set(reqwest::Proxy::all(url)?)
+        .map_err(|_| eyre::eyre!("proxy already set"))
+}
+
+/// Builder for HTTP clients going through the `--proxy`. Without one, reqwest still honors
+/// `HTTP_PROXY` and `HTTPS_PROXY`.
+pub fn http_client_builder() -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder();
+    match PROXY.get() {
+        Some(proxy) => builder.proxy(proxy.clone()),
+        None => builder,
+    }
+}
+
+/// Like `reqwest::Client::new`, which panics the same way, but through the proxy.
+pub fn http_client() -> reqwest::Client {
+    http_client_builder()
+        .build()
+        .expect("failed to build http client")
+}
+
+/// Extra header sent with every HTTP request to the endpoint, e.g. for auth.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+/// Parses `Name: value`.
+pub fn parse_header(s: &str) -> Result<Header, String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected `Name: value`, got {s}"))?;
+    let header = Header {
+        name: name.trim().to_string(),
+        value: value.trim().to_string(),
+    };
+    reqwest::header::HeaderName::from_bytes(header.name.as_bytes())
+        .map_err(|e| format!("invalid header name {:?}: {e}", header.name))?;
+    reqwest::header::HeaderValue::from_str(&header.value)
+        .map_err(|e| format!("invalid value for header {}: {e}", header.name))?;
+    Ok(header)
+}
+
+/// How to reach the endpoint besides its url.
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub cache: Option<ResponseCache>,
+    pub timeouts: Timeouts,
+    pub headers: Vec<Header>,
+    /// See [Client::retries].
+    pub retries: u32,
+}
+
+/// Provider answering only from `cache`, for reprocessing without any RPC calls.
+pub fn offline(cache: ResponseCache) -> EthProvider {
+    Provider::new(Client {
+        transport: Transport::Offline,
+        cache: Some(ResponseCache {
+            refresh: false,
+            ..cache
+        }),
+        timeouts: Timeouts {
+            request: Duration::MAX,
+            trace: Duration::MAX,
+        },
+        balances: BalanceMemo::default(),
+        traces_unsupported: Arc::default(),
+        retries: 0,
+    })
+}
+
+pub async fn connect(url: Option<&str>, options: ConnectOptions) -> eyre::Result<EthProvider> {
+    let url = url.ok_or_else(|| eyre::eyre!("--eth-rpc-url or ETH_RPC_URL is required"))?;
+    let url: Url = url.parse()?;
+    let mut headers = reqwest::header::HeaderMap::new();
+    for header in &options.headers {
+        let mut value = reqwest::header::HeaderValue::from_str(&header.value)?;
+        value.set_sensitive(true);
+        headers.insert(
+            reqwest::header::HeaderName::from_bytes(header.name.as_bytes())?,
+            value,
+        );
+    }
+    let client = http_client_builder().default_headers(headers).build()?;
+    let transport = Transport::Http { url, client };
+    Ok(Provider::new(Client {
+        transport,
+        cache: options.cache,
+        timeouts: options.timeouts,
+        balances: BalanceMemo::default(),
+        traces_unsupported: Arc::default(),
+        retries: options.retries,
+    }))
+}
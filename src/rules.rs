@@ -0,0 +1,72 @@
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::trace::TransferData;
+use crate::ProposerPayment;
+
+/// Classification rule from the `[[rules]]` of `--config`, checked before the built-in
+/// heuristics so payments of builders they don't recognize yet are still attributed.
+///
+/// A tx of the last `last_txs` txs of the block matches when it succeeded, its sender is one of
+/// `from` and its receiver one of `via`, either empty for any, and it transferred value to the fee
+/// recipient. Rules are tried in order and the tx closest to the end of the block wins.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Recorded in the `payment_rule` column of the rows it classifies.
+    pub name: String,
+    #[serde(default)]
+    pub from: Vec<Address>,
+    /// Receivers of the tx, the fee recipient itself for direct transfers or the contracts
+    /// paying it out.
+    #[serde(default)]
+    pub via: Vec<Address>,
+    #[serde(default = "default_last_txs")]
+    pub last_txs: usize,
+}
+
+fn default_last_txs() -> usize {
+    1
+}
+
+/// Payment to `fee_recipient` matched by the first of `rules` that matches any tx.
+pub fn rule_payment(
+    rules: &[Rule],
+    block: &Block<Transaction>,
+    receipts: &[TransactionReceipt],
+    transfers: &[TransferData],
+    fee_recipient: Address,
+) -> Option<ProposerPayment> {
+    rules.iter().find_map(|rule| {
+        block
+            .transactions
+            .iter()
+            .zip(receipts)
+            .rev()
+            .take(rule.last_txs)
+            .find_map(|(tx, receipt)| {
+                let to = tx.to?;
+                if receipt.status == Some(0u64.into())
+                    || (!rule.from.is_empty() && !rule.from.contains(&tx.from))
+                    || (!rule.via.is_empty() && !rule.via.contains(&to))
+                {
+                    return None;
+                }
+                let value = if to == fee_recipient {
+                    tx.value
+                } else {
+                    transfers
+                        .iter()
+                        .filter(|t| t.tx_hash == tx.hash && t.to == fee_recipient)
+                        .fold(U256::zero(), |acc, t| acc + t.value)
+                };
+                (!value.is_zero()).then(|| ProposerPayment::Rule {
+                    rule: rule.name.clone(),
+                    tx_hash: tx.hash,
+                    from: tx.from,
+                    to,
+                    value,
+                })
+            })
+    })
+}
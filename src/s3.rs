@@ -0,0 +1,160 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use futures::StreamExt;
+use object_store::aws::AmazonS3Builder;
+use object_store::ObjectStore;
+use tokio::io::AsyncWriteExt;
+
+/// Input or output given as an `s3://bucket/key` URL. Credentials, region and the endpoint of
+/// S3-compatible stores are read from the usual `AWS_*` environment variables, e.g.
+/// `AWS_ENDPOINT` for MinIO or R2.
+pub fn is_s3(path: &Path) -> bool {
+    path.to_str()
+        .map_or(false, |path| path.starts_with("s3://"))
+}
+
+/// Suffixes of the sidecars synced along with an output, see [crate::schema::path] and
+/// [crate::meta::OutputMetadata::path]. Without its `.schema` a file in another value unit would be
+/// misread.
+const SIDECARS: &[&str] = &[".schema", ".meta.json"];
+
+struct Object {
+    store: Arc<dyn ObjectStore>,
+    location: object_store::path::Path,
+    /// Local copy, keeping the key so the compression and format are still picked from its
+    /// extension, and so an interrupted run resumes from it.
+    staged: PathBuf,
+}
+
+impl Object {
+    fn parse(url: &Path) -> eyre::Result<Self> {
+        let url = url.to_str().unwrap_or_default();
+        let (bucket, key) = url
+            .strip_prefix("s3://")
+            .and_then(|rest| rest.split_once('/'))
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .ok_or_else(|| eyre::eyre!("expected s3://bucket/key, got {url}"))?;
+        let store = AmazonS3Builder::from_env().with_url(url).build()?;
+        Ok(Self {
+            store: Arc::new(store),
+            location: object_store::path::Path::from(key),
+            staged: std::env::temp_dir()
+                .join("proposer-payment-s3")
+                .join(bucket)
+                .join(key),
+        })
+    }
+
+    /// Object named like this one with `suffix` appended, staged next to it.
+    fn sidecar(&self, suffix: &str) -> Self {
+        let mut staged = self.staged.as_os_str().to_owned();
+        staged.push(suffix);
+        Self {
+            store: self.store.clone(),
+            location: object_store::path::Path::from(format!("{}{suffix}", self.location)),
+            staged: staged.into(),
+        }
+    }
+
+    /// Downloads the object to its staged copy, `false` if there is no such object.
+    async fn download(&self) -> eyre::Result<bool> {
+        let mut stream = match self.store.get(&self.location).await {
+            Ok(object) => object.into_stream(),
+            Err(object_store::Error::NotFound { .. }) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(dir) = self.staged.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let mut tmp = self.staged.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        while let Some(chunk) = stream.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp, &self.staged).await?;
+        Ok(true)
+    }
+}
+
+/// Local copy of an input object to read instead of `url`.
+pub async fn fetch_input(url: &Path) -> eyre::Result<PathBuf> {
+    let object = Object::parse(url)?;
+    if !object.download().await? {
+        eyre::bail!("{} not found", url.display());
+    }
+    tracing::info!(
+        "downloaded {} to {}",
+        url.display(),
+        object.staged.display()
+    );
+    Ok(object.staged)
+}
+
+/// Local file to write an output object to before [upload_output]. A staged copy is only left
+/// by a run that failed before uploading it and is kept, otherwise the existing object and its
+/// sidecars are downloaded so the run resumes it.
+pub async fn stage_output(url: &Path) -> eyre::Result<PathBuf> {
+    let object = Object::parse(url)?;
+    if object.staged.exists() {
+        tracing::info!("resuming staged copy {}", object.staged.display());
+        return Ok(object.staged);
+    }
+    if object.download().await? {
+        tracing::info!(
+            "resuming {} from {}",
+            url.display(),
+            object.staged.display()
+        );
+    } else if let Some(dir) = object.staged.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    for suffix in SIDECARS {
+        let sidecar = object.sidecar(suffix);
+        // a sidecar left by an earlier object of the same key doesn't describe this one
+        if !sidecar.download().await? && sidecar.staged.exists() {
+            std::fs::remove_file(&sidecar.staged)?;
+        }
+    }
+    Ok(object.staged)
+}
+
+/// Uploads the staged output of `url` in parts, so outputs of any size are never held in memory.
+/// The object is only replaced once every part is uploaded.
+pub async fn upload_output(url: &Path) -> eyre::Result<()> {
+    let object = Object::parse(url)?;
+    let (id, mut writer) = object.store.put_multipart(&object.location).await?;
+    let upload = async {
+        let mut file = tokio::fs::File::open(&object.staged).await?;
+        tokio::io::copy(&mut file, &mut writer).await?;
+        writer.shutdown().await?;
+        Ok::<_, eyre::Report>(())
+    };
+    if let Err(e) = upload.await {
+        object
+            .store
+            .abort_multipart(&object.location, &id)
+            .await
+            .ok();
+        return Err(e);
+    }
+    for suffix in SIDECARS {
+        let sidecar = object.sidecar(suffix);
+        if sidecar.staged.exists() {
+            let data = tokio::fs::read(&sidecar.staged).await?;
+            sidecar.store.put(&sidecar.location, data.into()).await?;
+        }
+    }
+    tracing::info!("uploaded {} to {}", object.staged.display(), url.display());
+    // the object is the newer copy now, a later run downloads it again
+    for suffix in SIDECARS {
+        let sidecar = object.sidecar(suffix);
+        if sidecar.staged.exists() {
+            std::fs::remove_file(&sidecar.staged)?;
+        }
+    }
+    std::fs::remove_file(&object.staged)?;
+    Ok(())
+}
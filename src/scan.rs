@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use ethers::prelude::*;
+use futures::stream::StreamExt;
+use serde::Serialize;
+
+use crate::chain::ChainProfile;
+use crate::relays::{self, Relay};
+use crate::rpc::EthProvider;
+use crate::streams::{create_output, Dialect};
+use crate::Era;
+
+/// Row of the `scan` output, one per block.
+#[derive(Debug, Serialize)]
+struct ScanEntry {
+    slot: u64,
+    block_number: u64,
+    block_hash: H256,
+    /// Relays that report delivering the block, comma separated.
+    relay: Option<String>,
+    /// No relay reports delivering the block, so the proposer built it without MEV-Boost.
+    locally_built: bool,
+}
+
+/// Looks up every block in `from_block..=to_block` on `relays` and writes which relays delivered
+/// it, then prints each relay's share of the range next to the share of locally built blocks.
+/// Blocks before the merge are skipped. Blocks that can't be scanned are left out and reported,
+/// failing the scan once the others are written.
+pub async fn scan(
+    provider: &EthProvider,
+    chain: &ChainProfile,
+    relays: &[Relay],
+    from_block: u64,
+    to_block: u64,
+    output: &Path,
+    rpc_parallel: usize,
+) -> eyre::Result<()> {
+    let client = crate::rpc::http_client();
+    let results: Vec<(u64, eyre::Result<Option<ScanEntry>>)> =
+        futures::stream::iter(from_block..=to_block)
+            .map(|block_number| {
+                let client = &client;
+                async move {
+                    let entry = async {
+                        let block = provider
+                            .get_block(block_number)
+                            .await?
+                            .ok_or_else(|| eyre::eyre!("block {block_number} not found"))?;
+                        // proof-of-work blocks have no slot, let alone a relay
+                        if Era::of(&block) == Era::PreMerge {
+                            return Ok(None);
+                        }
+                        let block_hash = block.hash.unwrap_or_default();
+                        let slot = chain.slot_at(block.timestamp.as_u64()).ok_or_else(|| {
+                            eyre::eyre!("block {block_number} is before the beacon chain")
+                        })?;
+                        let delivered =
+                            relays::delivering_relays(client, relays, slot, block_hash).await?;
+                        Ok::<_, eyre::Report>(Some(ScanEntry {
+                            slot,
+                            block_number,
+                            block_hash,
+                            locally_built: delivered.is_empty(),
+                            relay: (!delivered.is_empty()).then(|| delivered.join(",")),
+                        }))
+                    }
+                    .await;
+                    (block_number, entry)
+                }
+            })
+            .buffered(rpc_parallel.max(1))
+            .collect()
+            .await;
+
+    let mut entries = Vec::new();
+    let mut pre_merge = 0;
+    let mut failed = Vec::new();
+    for (block_number, result) in results {
+        match result {
+            Ok(Some(entry)) => entries.push(entry),
+            Ok(None) => pre_merge += 1,
+            Err(e) => {
+                tracing::warn!(block_number, "failed to scan block: {e:#}");
+                failed.push(block_number);
+            }
+        }
+    }
+
+    let dialect = Dialect::for_path(output);
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(dialect.delimiter)
+        .from_writer(create_output(output)?);
+    let mut shares: BTreeMap<&str, u64> = BTreeMap::new();
+    for entry in &entries {
+        writer.serialize(entry)?;
+        match &entry.relay {
+            Some(names) => {
+                for name in names.split(',') {
+                    *shares.entry(name).or_default() += 1;
+                }
+            }
+            None => *shares.entry("(local)").or_default() += 1,
+        }
+    }
+    drop(writer.into_inner().map_err(|e| e.into_error())?);
+
+    // blocks delivered by several relays count towards each of them
+    println!("blocks:             {}", entries.len());
+    println!("pre-merge blocks:   {pre_merge}");
+    println!("failed blocks:      {}", failed.len());
+    for (name, count) in shares {
+        println!(
+            "  {:<18}{:>10} ({:.2}%)",
+            name,
+            count,
+            count as f64 * 100.0 / entries.len() as f64
+        );
+    }
+    if !failed.is_empty() {
+        eyre::bail!(
+            "{} blocks failed, rerun the scan for them: {:?}",
+            failed.len(),
+            failed
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use std::path::{Path, PathBuf};
+
+use crate::units::{self, ValueUnit};
+
+/// Version of the output columns, bumped whenever columns are added, removed or change meaning.
+/// Files written before versioning was introduced have no version.
+pub const SCHEMA_VERSION: u32 = 25;
+
+/// Sidecar next to an output holding its schema version, on a second line the unit of its value
+/// columns and, on a third one, the columns picked with `--columns` if it only has those.
+pub fn path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".schema");
+    path.into()
+}
+
+pub fn read_version(output: &Path) -> eyre::Result<Option<u32>> {
+    let path = path(output);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let sidecar = std::fs::read_to_string(&path)?;
+    Ok(Some(
+        sidecar.lines().next().unwrap_or_default().trim().parse()?,
+    ))
+}
+
+/// Unit the value columns of `output` were written in, wei for files that don't record one.
+pub fn read_unit(output: &Path) -> eyre::Result<ValueUnit> {
+    let path = path(output);
+    if !path.exists() {
+        return Ok(ValueUnit::Wei);
+    }
+    match std::fs::read_to_string(&path)?.lines().nth(1) {
+        Some(unit) => unit.trim().parse(),
+        None => Ok(ValueUnit::Wei),
+    }
+}
+
+/// Fails unless `output` was written in the current value unit, rows in another unit would be
+/// misread.
+pub fn check_unit(output: &Path) -> eyre::Result<()> {
+    let unit = read_unit(output)?;
+    if unit != units::get() {
+        eyre::bail!(
+            "{} has values in {unit}, pass --value-unit {unit} to read it",
+            output.display()
+        );
+    }
+    Ok(())
+}
+
+/// Records that `output` uses the current schema and value unit, and only has `columns` if
+/// picked.
+pub fn write_version(output: &Path, columns: Option<&csv::StringRecord>) -> eyre::Result<()> {
+    let mut sidecar = format!("{SCHEMA_VERSION}\n{}\n", units::get());
+    if let Some(columns) = columns {
+        sidecar.push_str(&columns.iter().collect::<Vec<_>>().join(","));
+        sidecar.push('\n');
+    }
+    std::fs::write(path(output), sidecar)?;
+    Ok(())
+}
+
+/// Columns picked with `--columns` that `output` was written with, `None` if it has all of them.
+pub fn read_columns(output: &Path) -> eyre::Result<Option<Vec<String>>> {
+    let path = path(output);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(std::fs::read_to_string(&path)?
+        .lines()
+        .nth(2)
+        .filter(|columns| !columns.trim().is_empty())
+        .map(|columns| columns.trim().split(',').map(str::to_string).collect()))
+}
+
+/// Fails if `output` only has the columns picked with `--columns`, rewriting it under the full
+/// header would fill in every other column with a default.
+pub fn check_full(output: &Path) -> eyre::Result<()> {
+    if let Some(columns) = read_columns(output)? {
+        eyre::bail!(
+            "{} only has the columns {} picked with --columns, it can't be rewritten",
+            output.display(),
+            columns.join(",")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use ethers::providers::Middleware;
+use ethers::types::{Address, U256};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+
+use crate::relays::{self, Relay};
+use crate::rpc::EthProvider;
+use crate::{process_input_entry, BoostRelayDataEntry, ProcessingOptions};
+
+/// Least recently used results, serialized, with at most `capacity` of them.
+struct Lru {
+    capacity: usize,
+    entries: HashMap<String, Arc<String>>,
+    /// Keys from least to most recently used.
+    order: VecDeque<String>,
+}
+
+impl Lru {
+    fn get(&mut self, key: &str) -> Option<Arc<String>> {
+        let value = self.entries.get(key)?.clone();
+        self.order.retain(|k| k != key);
+        self.order.push_back(key.to_string());
+        Some(value)
+    }
+
+    fn put(&mut self, key: String, value: Arc<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(key.clone(), value).is_some() {
+            self.order.retain(|k| *k != key);
+        }
+        self.order.push_back(key);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+struct State {
+    provider: EthProvider,
+    options: Arc<ProcessingOptions>,
+    /// Relays asked for the payload delivered in a slot, in order.
+    relays: Vec<Relay>,
+    client: reqwest::Client,
+    cache: Mutex<Lru>,
+}
+
+/// Failure of a request, answered with its status and message.
+struct Rejection(StatusCode, String);
+
+impl From<eyre::Report> for Rejection {
+    fn from(e: eyre::Report) -> Self {
+        Rejection(StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}"))
+    }
+}
+
+fn bad_request(message: impl Into<String>) -> Rejection {
+    Rejection(StatusCode::BAD_REQUEST, message.into())
+}
+
+/// Serves output rows computed on demand as JSON:
+///
+/// - `GET /slot/{slot}` for the payload `relays` report as delivered in the slot.
+/// - `GET /block/{number}?fee_recipient=0x…&bid_value=…` for any block, the bid value in wei
+///   defaults to zero.
+///
+/// The last `cache_size` rows are kept in memory.
+pub async fn serve(
+    addr: SocketAddr,
+    provider: EthProvider,
+    options: Arc<ProcessingOptions>,
+    relays: Vec<Relay>,
+    cache_size: usize,
+) -> eyre::Result<()> {
+    let state = Arc::new(State {
+        provider,
+        options,
+        relays,
+        client: crate::rpc::http_client(),
+        cache: Mutex::new(Lru {
+            capacity: cache_size,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }),
+    });
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(&state, request).await) }
+            }))
+        }
+    });
+    tracing::info!(%addr, "serving");
+    hyper::Server::try_bind(&addr)?.serve(make_service).await?;
+    Ok(())
+}
+
+async fn handle(state: &State, request: Request<Body>) -> Response<Body> {
+    let uri = request.uri().clone();
+    let result = if request.method() == Method::GET {
+        respond(state, &uri).await
+    } else {
+        Err(Rejection(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "only GET is supported".to_string(),
+        ))
+    };
+    let (status, body) = match result {
+        Ok(json) => (StatusCode::OK, json.to_string()),
+        Err(Rejection(status, message)) => {
+            if status.is_server_error() {
+                tracing::warn!(%uri, error = %message, "request failed");
+            }
+            (status, serde_json::json!({ "error": message }).to_string())
+        }
+    };
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn respond(state: &State, uri: &Uri) -> Result<Arc<String>, Rejection> {
+    let key = uri.to_string();
+    if let Some(cached) = state.cache.lock().unwrap().get(&key) {
+        return Ok(cached);
+    }
+
+    let query: BTreeMap<&str, &str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .collect();
+    let segments: Vec<&str> = uri.path().trim_matches('/').split('/').collect();
+    let input = match segments.as_slice() {
+        ["slot", slot] => {
+            let slot = slot.parse().map_err(|_| bad_request("invalid slot"))?;
+            relays::delivered_entry(&state.client, &state.relays, slot)
+                .await?
+                .ok_or_else(|| {
+                    Rejection(
+                        StatusCode::NOT_FOUND,
+                        format!("no relay reports a payload delivered in slot {slot}"),
+                    )
+                })?
+        }
+        ["block", number] => {
+            let block_number: u64 = number
+                .parse()
+                .map_err(|_| bad_request("invalid block number"))?;
+            let fee_recipient: Address = query
+                .get("fee_recipient")
+                .ok_or_else(|| bad_request("fee_recipient is required"))?
+                .parse()
+                .map_err(|_| bad_request("invalid fee_recipient"))?;
+            let value = match query.get("bid_value") {
+                Some(value) => {
+                    U256::from_dec_str(value).map_err(|_| bad_request("invalid bid_value"))?
+                }
+                None => U256::zero(),
+            };
+            let block = state
+                .provider
+                .get_block(block_number)
+                .await
+                .map_err(eyre::Report::from)?
+                .ok_or_else(|| {
+                    Rejection(
+                        StatusCode::NOT_FOUND,
+                        format!("block {block_number} not found"),
+                    )
+                })?;
+            let slot = state
+                .options
+                .chain
+                .slot_at(block.timestamp.as_u64())
+                .ok_or_else(|| bad_request("block is before the beacon chain"))?;
+            BoostRelayDataEntry {
+                slot,
+                proposer_fee_recipient: fee_recipient,
+                value,
+                block_hash: block.hash,
+                block_number,
+                relay: None,
+                builder_pubkey: None,
+                extra: BTreeMap::new(),
+            }
+        }
+        _ => return Err(Rejection(StatusCode::NOT_FOUND, "unknown path".to_string())),
+    };
+
+    let entry = process_input_entry(&state.provider, &state.options, input).await?;
+    let json = Arc::new(serde_json::to_string(&entry).map_err(eyre::Report::from)?);
+    state.cache.lock().unwrap().put(key, json.clone());
+    Ok(json)
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use ethers::types::{I256, U256};
+use serde::Serialize;
+
+use crate::{i256_to_f64, read_output_file, OutputFileEntry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsFormat {
+    /// One row per statistic, histogram bins included.
+    Csv,
+    /// One object per payment type and metric.
+    Json,
+}
+
+const PERCENTILES: &[u32] = &[0, 1, 5, 25, 50, 75, 95, 99, 100];
+
+/// Per-block values the distributions are computed over.
+const METRICS: &[(&str, fn(&OutputFileEntry) -> I256)] = &[
+    ("delta_wei", |entry| entry.delta_wei),
+    ("balance_diff", |entry| entry.balance_diff),
+    ("transfers", |entry| {
+        I256::from_raw(U256::from(entry.transfers))
+    }),
+];
+
+/// Group holding every row next to the per payment type ones.
+const ALL: &str = "all";
+
+#[derive(Debug, Serialize)]
+struct Bin {
+    lower: f64,
+    upper: f64,
+    count: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Distribution {
+    payment_type: String,
+    metric: &'static str,
+    count: usize,
+    /// Nearest-rank percentiles, exact decimal values.
+    percentiles: BTreeMap<String, String>,
+    histogram: Vec<Bin>,
+}
+
+#[derive(Debug, Serialize)]
+struct StatsRow<'a> {
+    payment_type: &'a str,
+    metric: &'a str,
+    statistic: String,
+    lower: Option<f64>,
+    upper: Option<f64>,
+    value: String,
+}
+
+fn percentile(sorted: &[I256], p: u32) -> I256 {
+    let rank = (sorted.len() * p as usize).div_ceil(100);
+    sorted[rank.saturating_sub(1).min(sorted.len() - 1)]
+}
+
+/// Equal width bins between the smallest and largest value.
+fn histogram(sorted: &[I256], bins: usize) -> Vec<Bin> {
+    let min = i256_to_f64(sorted[0]);
+    let max = i256_to_f64(sorted[sorted.len() - 1]);
+    let bins = if max > min { bins.max(1) } else { 1 };
+    let width = (max - min) / bins as f64;
+    let mut counts = vec![0; bins];
+    for value in sorted {
+        let bin = if width > 0.0 {
+            ((i256_to_f64(*value) - min) / width) as usize
+        } else {
+            0
+        };
+        counts[bin.min(bins - 1)] += 1;
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| Bin {
+            lower: min + width * i as f64,
+            upper: if i + 1 == bins {
+                max
+            } else {
+                min + width * (i + 1) as f64
+            },
+            count,
+        })
+        .collect()
+}
+
+/// Prints percentiles and histograms of per-block metrics of an output file, overall and by
+/// payment type.
+pub fn stats(input: &Path, format: StatsFormat, bins: usize) -> eyre::Result<()> {
+    let mut groups: BTreeMap<String, Vec<Vec<I256>>> = BTreeMap::new();
+    for entry in read_output_file(input)? {
+        // a missed slot has no block, its zero values aren't a payment
+        if entry.is_missed() {
+            continue;
+        }
+        for group in [ALL.to_string(), entry.payment_type.clone()] {
+            let values = groups
+                .entry(group)
+                .or_insert_with(|| vec![Vec::new(); METRICS.len()]);
+            for (values, (_, metric)) in values.iter_mut().zip(METRICS) {
+                values.push(metric(&entry));
+            }
+        }
+    }
+
+    let mut distributions = Vec::new();
+    for (payment_type, values) in groups {
+        for (mut values, (metric, _)) in values.into_iter().zip(METRICS) {
+            values.sort_unstable();
+            distributions.push(Distribution {
+                payment_type: payment_type.clone(),
+                metric,
+                count: values.len(),
+                percentiles: PERCENTILES
+                    .iter()
+                    .map(|p| (format!("p{p}"), percentile(&values, *p).to_string()))
+                    .collect(),
+                histogram: histogram(&values, bins),
+            });
+        }
+    }
+
+    match format {
+        StatsFormat::Json => {
+            serde_json::to_writer_pretty(std::io::stdout(), &distributions)?;
+            println!();
+        }
+        StatsFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            for distribution in &distributions {
+                let row = |statistic: String, lower, upper, value: String| StatsRow {
+                    payment_type: &distribution.payment_type,
+                    metric: distribution.metric,
+                    statistic,
+                    lower,
+                    upper,
+                    value,
+                };
+                writer.serialize(row(
+                    "count".to_string(),
+                    None,
+                    None,
+                    distribution.count.to_string(),
+                ))?;
+                for p in PERCENTILES {
+                    let statistic = format!("p{p}");
+                    let value = distribution.percentiles[&statistic].clone();
+                    writer.serialize(row(statistic, None, None, value))?;
+                }
+                for bin in &distribution.histogram {
+                    writer.serialize(row(
+                        "bin".to_string(),
+                        Some(bin.lower),
+                        Some(bin.upper),
+                        bin.count.to_string(),
+                    ))?;
+                }
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::adapters::InputSchema;
+
+/// Path that stands for stdin or stdout.
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Compression of a file, picked from its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    fn reader(self, file: File) -> std::io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            Compression::None => Box::new(file),
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(file)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(file)?),
+        })
+    }
+
+    /// Wraps `file` in an encoder that finishes the stream when dropped. Both gzip members and
+    /// zstd frames can be concatenated, so this also works for appending to an existing file.
+    fn writer(self, file: File) -> std::io::Result<Box<dyn Write + Send>> {
+        Ok(match self {
+            Compression::None => Box::new(file),
+            Compression::Gzip => Box::new(flate2::write::GzEncoder::new(
+                file,
+                flate2::Compression::default(),
+            )),
+            Compression::Zstd => {
+                Box::new(zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?.auto_finish())
+            }
+        })
+    }
+}
+
+pub fn open_input(path: &Path) -> std::io::Result<Box<dyn Read + Send>> {
+    if is_stdio(path) {
+        return Ok(Box::new(std::io::stdin()));
+    }
+    Compression::of(path).reader(File::open(path)?)
+}
+
+pub fn create_output(path: &Path) -> std::io::Result<Box<dyn Write + Send>> {
+    create_output_as(path, Compression::of(path))
+}
+
+/// Like [create_output], for temporary files that take the compression of the file they replace.
+pub fn create_output_as(
+    path: &Path,
+    compression: Compression,
+) -> std::io::Result<Box<dyn Write + Send>> {
+    if is_stdio(path) {
+        return Ok(Box::new(std::io::stdout()));
+    }
+    compression.writer(File::create(path)?)
+}
+
+pub fn append_output(path: &Path) -> std::io::Result<Box<dyn Write + Send>> {
+    if is_stdio(path) {
+        return Ok(Box::new(std::io::stdout()));
+    }
+    Compression::of(path).writer(OpenOptions::new().append(true).open(path)?)
+}
+
+/// Csv dialect of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub has_headers: bool,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_headers: true,
+        }
+    }
+}
+
+impl Dialect {
+    /// Tab separated for `.tsv` files (also compressed ones), comma separated otherwise.
+    pub fn for_path(path: &Path) -> Self {
+        let is_tsv = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| {
+                name.split('.').skip(1).any(|ext| ext == "tsv")
+            });
+        Self {
+            delimiter: if is_tsv { b'\t' } else { b',' },
+            has_headers: true,
+        }
+    }
+
+    pub fn reader<R: Read>(&self, reader: R) -> csv::Reader<R> {
+        csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .from_reader(reader)
+    }
+
+    /// Writer that never writes a header on its own, see [Dialect::write_header].
+    pub fn writer<W: Write>(&self, writer: W) -> csv::Writer<W> {
+        csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(false)
+            .from_writer(writer)
+    }
+
+    pub fn write_header<W: Write>(
+        &self,
+        writer: &mut csv::Writer<W>,
+        header: &csv::StringRecord,
+    ) -> csv::Result<()> {
+        if self.has_headers {
+            writer.write_record(header)?;
+        }
+        Ok(())
+    }
+}
+
+/// Format of an input file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    /// JSON array as returned by the relay `proposer_payload_delivered` endpoint.
+    Json,
+    /// Parquet file with the csv input columns, read from disk only.
+    Parquet,
+}
+
+impl InputFormat {
+    /// JSON for `.json` files (also compressed ones), Parquet for `.parquet` files, csv otherwise.
+    pub fn for_path(path: &Path) -> Self {
+        let has_extension = |extension: &str| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| {
+                    name.split('.').skip(1).any(|ext| ext == extension)
+                })
+        };
+        if has_extension("json") {
+            InputFormat::Json
+        } else if has_extension("parquet") {
+            InputFormat::Parquet
+        } else {
+            InputFormat::Csv
+        }
+    }
+}
+
+/// How to read an input file.
+#[derive(Debug, Clone)]
+pub struct InputSource {
+    pub dialect: Dialect,
+    pub format: InputFormat,
+    pub schema: InputSchema,
+}
+
+/// Csv dialect options of the `file` command.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct DialectArgs {
+    /// Field delimiter of the input and output, e.g. `;` or `\t`. Defaults to a tab for `.tsv`
+    /// files and a comma otherwise.
+    #[clap(long, value_parser = parse_delimiter)]
+    pub delimiter: Option<u8>,
+    /// The input has no header row, its columns are in the order `slot`,
+    /// `proposer_fee_recipient`, `value`, `block_hash`, `block_number`. `block_hash` may be empty.
+    #[clap(long)]
+    pub no_input_header: bool,
+    /// Don't write a header row. Such an output can't be upgraded to a newer schema in place.
+    #[clap(long)]
+    pub no_output_header: bool,
+    /// Format of the input, picked from its extension by default.
+    #[clap(long, value_enum)]
+    pub input_format: Option<InputFormat>,
+    /// Column names and value unit of a csv input exported in another schema: `standard`,
+    /// `camel-case`, `relayscan`, `bloxroute`, `eden` or a path to a TOML profile.
+    #[clap(long, default_value = "standard")]
+    pub input_schema: String,
+    /// Input column of a standard column, e.g. `--map slot=slot_number --map value=bid_wei` for
+    /// a Dune query export. Applied on top of `--input-schema`.
+    #[clap(long = "map", value_name = "STANDARD=COLUMN", value_parser = parse_column_mapping)]
+    pub column_map: Vec<(String, String)>,
+    /// Input column of `slot`, same as `--map slot=COLUMN`.
+    #[clap(long, value_name = "COLUMN")]
+    pub slot_column: Option<String>,
+    /// Input column of `value`, same as `--map value=COLUMN`.
+    #[clap(long, value_name = "COLUMN")]
+    pub value_column: Option<String>,
+    /// Input column of `block_number`, same as `--map block_number=COLUMN`.
+    #[clap(long, value_name = "COLUMN")]
+    pub block_column: Option<String>,
+    /// Input column of `proposer_fee_recipient`, same as
+    /// `--map proposer_fee_recipient=COLUMN`.
+    #[clap(long, value_name = "COLUMN")]
+    pub recipient_column: Option<String>,
+    /// Only write these output columns, in this order, e.g. `slot,block_number,delta_wei`. Such
+    /// an output can't be upgraded to a newer schema, and resuming it needs the `slot` column.
+    #[clap(long, value_delimiter = ',')]
+    pub columns: Vec<String>,
+}
+
+impl DialectArgs {
+    pub fn input(&self, path: &Path) -> eyre::Result<InputSource> {
+        Ok(InputSource {
+            dialect: Dialect {
+                delimiter: self.delimiter.unwrap_or(Dialect::for_path(path).delimiter),
+                has_headers: !self.no_input_header,
+            },
+            format: self
+                .input_format
+                .unwrap_or_else(|| InputFormat::for_path(path)),
+            schema: {
+                let mut schema = InputSchema::resolve(&self.input_schema)?;
+                schema.columns.extend(self.column_map.iter().cloned());
+                let named = [
+                    ("slot", &self.slot_column),
+                    ("value", &self.value_column),
+                    ("block_number", &self.block_column),
+                    ("proposer_fee_recipient", &self.recipient_column),
+                ];
+                for (standard, column) in named {
+                    if let Some(column) = column {
+                        schema.columns.insert(standard.to_string(), column.clone());
+                    }
+                }
+                schema
+            },
+        })
+    }
+
+    pub fn output(&self, path: &Path) -> Dialect {
+        Dialect {
+            delimiter: self.delimiter.unwrap_or(Dialect::for_path(path).delimiter),
+            has_headers: !self.no_output_header,
+        }
+    }
+}
+
+fn parse_column_mapping(s: &str) -> Result<(String, String), String> {
+    let (standard, column) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected STANDARD=COLUMN, got {s}"))?;
+    if !crate::INPUT_COLUMNS.contains(&standard) {
+        return Err(format!(
+            "{standard} is not an input column, expected one of {}",
+            crate::INPUT_COLUMNS.join(", ")
+        ));
+    }
+    Ok((standard.to_string(), column.to_string()))
+}
+
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(format!("expected a single ascii character, got {s}")),
+    }
+}
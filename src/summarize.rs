@@ -0,0 +1,454 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use ethers::types::{Address, H256, I256, U256};
+use serde::Serialize;
+
+use crate::chain::ChainProfile;
+use crate::ens::EnsNames;
+use crate::{read_output_file, read_output_totals, OutputFileEntry, Tolerance};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    Builder,
+    Relay,
+    /// Staking entity of the proposer, see `--entities`.
+    Entity,
+}
+
+impl GroupBy {
+    pub fn key(&self, entry: &OutputFileEntry) -> String {
+        match self {
+            GroupBy::Builder => entry.builder.clone().unwrap_or_default(),
+            GroupBy::Relay => entry.relay.clone().unwrap_or_default(),
+            GroupBy::Entity => entry.entity.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Whether the row belongs to a group. A missed slot has no block, so no builder or relay.
+    pub fn includes(&self, entry: &OutputFileEntry) -> bool {
+        !entry.is_missed() || *self == GroupBy::Entity
+    }
+}
+
+/// Time bucket rows are aggregated by, from the slot so rows without a timestamp still count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Bucket {
+    /// UTC day, `2024-01-31`.
+    Day,
+    /// ISO week, `2024-W05`.
+    Week,
+    Epoch,
+}
+
+impl Bucket {
+    pub fn key(&self, chain: &ChainProfile, slot: u64) -> String {
+        let time = chrono::DateTime::from_timestamp(chain.slot_timestamp(slot) as i64, 0)
+            .unwrap_or_default();
+        match self {
+            Bucket::Day => time.format("%Y-%m-%d").to_string(),
+            Bucket::Week => time.format("%G-W%V").to_string(),
+            Bucket::Epoch => (slot / chain.slots_per_epoch).to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GroupTotals {
+    pub blocks: u64,
+    pub bid_value: U256,
+    pub balance_diff: I256,
+    pub observed: I256,
+    pub delta: I256,
+    pub payment_types: BTreeMap<String, u64>,
+    /// Vertically integrated blocks, left out of the underpayment and overpayment statistics.
+    pub integrated: u64,
+    pub underpaid: u64,
+    pub overpaid: u64,
+    /// Slots the proposer missed, written by `--missed-slots`. They aren't blocks.
+    pub missed: u64,
+}
+
+impl GroupTotals {
+    pub fn add(&mut self, entry: &OutputFileEntry) {
+        if entry.is_missed() {
+            self.missed += 1;
+            return;
+        }
+        self.blocks += 1;
+        self.bid_value += entry.bid_value;
+        self.balance_diff += entry.balance_diff;
+        self.observed += entry.observed_payment;
+        self.delta += entry.delta_wei;
+        *self
+            .payment_types
+            .entry(entry.payment_type.clone())
+            .or_default() += 1;
+        if entry.vertically_integrated {
+            self.integrated += 1;
+            return;
+        }
+        if entry.underpaid == Some(true) {
+            self.underpaid += 1;
+        }
+        if entry.overpaid_by.is_some() {
+            self.overpaid += 1;
+        }
+    }
+
+    pub fn payment_type(&self, payment_type: &str) -> u64 {
+        self.payment_types
+            .get(payment_type)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn avg_delta(&self) -> I256 {
+        if self.blocks == 0 {
+            return I256::zero();
+        }
+        self.delta / I256::from_raw(U256::from(self.blocks))
+    }
+
+    /// Share of the blocks that aren't vertically integrated paid at least the bid, `None`
+    /// without such blocks.
+    pub fn paid_in_full_pct(&self) -> Option<f64> {
+        let blocks = self.blocks - self.integrated;
+        if blocks == 0 {
+            return None;
+        }
+        Some((blocks - self.underpaid) as f64 * 100.0 / blocks as f64)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct GroupRow {
+    group: String,
+    blocks: u64,
+    total_bid_value: String,
+    total_balance_diff: String,
+    avg_delta: String,
+    last_tx_direct: u64,
+    last_tx_contract: u64,
+    last_tx_reverted: u64,
+    coinbase: u64,
+    searcher: u64,
+    entry_point: u64,
+    rule: u64,
+    unknown: u64,
+    vertically_integrated: u64,
+    underpaid: u64,
+    /// Blocks where the proposer received more than the bid by over the threshold.
+    overpaid: u64,
+    /// Share of blocks, not counting vertically integrated ones, where the proposer received at
+    /// least the bid value. Empty without such blocks.
+    paid_in_full_pct: Option<f64>,
+    missed: u64,
+}
+
+/// Aggregates an output file by `group_by` and writes one CSV row per group to stdout.
+pub fn summarize(input: &Path, group_by: GroupBy, tolerance: &Tolerance) -> eyre::Result<()> {
+    let mut groups: BTreeMap<String, GroupTotals> = BTreeMap::new();
+    for entry in read_output_totals(input, tolerance)? {
+        if group_by.includes(&entry) {
+            groups.entry(group_by.key(&entry)).or_default().add(&entry);
+        }
+    }
+
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    for (group, totals) in groups {
+        writer.serialize(GroupRow {
+            group: if group.is_empty() {
+                "(unidentified)".to_string()
+            } else {
+                group
+            },
+            blocks: totals.blocks,
+            total_bid_value: totals.bid_value.to_string(),
+            total_balance_diff: totals.balance_diff.to_string(),
+            avg_delta: totals.avg_delta().to_string(),
+            last_tx_direct: totals.payment_type("last_tx_direct"),
+            last_tx_contract: totals.payment_type("last_tx_contract"),
+            last_tx_reverted: totals.payment_type("last_tx_reverted"),
+            coinbase: totals.payment_type("coinbase"),
+            searcher: totals.payment_type("searcher"),
+            entry_point: totals.payment_type("entry_point"),
+            rule: totals.payment_type("rule"),
+            unknown: totals.payment_type("unknown"),
+            vertically_integrated: totals.integrated,
+            underpaid: totals.underpaid,
+            overpaid: totals.overpaid,
+            paid_in_full_pct: totals.paid_in_full_pct(),
+            missed: totals.missed,
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct BucketRow {
+    bucket: String,
+    first_slot: u64,
+    last_slot: u64,
+    blocks: u64,
+    total_bid_value: String,
+    total_observed_payment: String,
+    unknown: u64,
+    vertically_integrated: u64,
+    underpaid: u64,
+    overpaid: u64,
+    missed: u64,
+}
+
+/// Aggregates an output file into `bucket` periods and writes one CSV row per period to stdout.
+pub fn aggregate(
+    input: &Path,
+    bucket: Bucket,
+    chain: &ChainProfile,
+    tolerance: &Tolerance,
+) -> eyre::Result<()> {
+    let mut buckets: HashMap<String, (u64, u64, GroupTotals)> = HashMap::new();
+    for entry in read_output_totals(input, tolerance)? {
+        let (first, last, totals) = buckets.entry(bucket.key(chain, entry.slot)).or_insert((
+            entry.slot,
+            entry.slot,
+            GroupTotals::default(),
+        ));
+        *first = (*first).min(entry.slot);
+        *last = (*last).max(entry.slot);
+        totals.add(&entry);
+    }
+
+    // buckets are contiguous ranges of slots, so this is chronological
+    let mut buckets: Vec<_> = buckets.into_iter().collect();
+    buckets.sort_unstable_by_key(|(_, (first_slot, ..))| *first_slot);
+
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    for (bucket, (first_slot, last_slot, totals)) in buckets {
+        writer.serialize(BucketRow {
+            bucket,
+            first_slot,
+            last_slot,
+            blocks: totals.blocks,
+            total_bid_value: totals.bid_value.to_string(),
+            total_observed_payment: totals.observed.to_string(),
+            unknown: totals.payment_type("unknown"),
+            vertically_integrated: totals.integrated,
+            underpaid: totals.underpaid,
+            overpaid: totals.overpaid,
+            missed: totals.missed,
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct DiscrepancyRow {
+    slot: u64,
+    block_number: u64,
+    bid_value: String,
+    observed_payment: String,
+    shortfall: String,
+    payment_type: String,
+    payment_tx_hash: Option<H256>,
+    builder: Option<String>,
+    relay: Option<String>,
+}
+
+/// Writes the `n` rows with the largest shortfall of the observed payment below the bid to stdout
+/// as CSV, worst first. Vertically integrated blocks are left out.
+pub fn top_discrepancies(input: &Path, n: usize) -> eyre::Result<()> {
+    let mut entries: Vec<OutputFileEntry> = read_output_file(input)?
+        .into_iter()
+        .filter(|entry| !entry.vertically_integrated && entry.delta_wei.is_negative())
+        .collect();
+    entries.sort_unstable_by_key(|entry| entry.delta_wei);
+
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    for entry in entries.into_iter().take(n) {
+        writer.serialize(DiscrepancyRow {
+            slot: entry.slot,
+            block_number: entry.block_number,
+            bid_value: entry.bid_value.to_string(),
+            observed_payment: entry.observed_payment.to_string(),
+            shortfall: (-entry.delta_wei).to_string(),
+            payment_type: entry.payment_type,
+            payment_tx_hash: entry.payment_tx_hash,
+            builder: entry.builder,
+            relay: entry.relay,
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct RecipientRow {
+    fee_recipient: Address,
+    fee_recipient_label: Option<String>,
+    blocks: u64,
+    first_slot: u64,
+    last_slot: u64,
+    total_bid_value: String,
+    total_observed_payment: String,
+    total_withdrawals: String,
+    underpaid: u64,
+    /// Largest shortfall of the observed payment below the bid, not counting vertically
+    /// integrated blocks. Empty if none fell short.
+    worst_shortfall: Option<String>,
+    worst_shortfall_slot: Option<u64>,
+}
+
+/// Writes the totals of each fee recipient to stdout as CSV, for proposers checking whether their
+/// addresses received everything they were bid. Rows written before outputs had the
+/// `fee_recipient` column are left out.
+pub fn by_recipient(input: &Path, tolerance: &Tolerance) -> eyre::Result<()> {
+    let mut recipients: BTreeMap<Address, (RecipientRow, GroupTotals, U256, Option<I256>)> =
+        BTreeMap::new();
+    let mut skipped = 0;
+    for entry in read_output_totals(input, tolerance)? {
+        // a missed slot has no block and so no fee recipient
+        if entry.is_missed() {
+            continue;
+        }
+        let Some(fee_recipient) = entry.fee_recipient else {
+            skipped += 1;
+            continue;
+        };
+        let (row, totals, withdrawals, worst) =
+            recipients.entry(fee_recipient).or_insert_with(|| {
+                let row = RecipientRow {
+                    fee_recipient,
+                    fee_recipient_label: entry.fee_recipient_label.clone(),
+                    blocks: 0,
+                    first_slot: entry.slot,
+                    last_slot: entry.slot,
+                    total_bid_value: String::new(),
+                    total_observed_payment: String::new(),
+                    total_withdrawals: String::new(),
+                    underpaid: 0,
+                    worst_shortfall: None,
+                    worst_shortfall_slot: None,
+                };
+                (row, GroupTotals::default(), U256::zero(), None)
+            });
+        row.first_slot = row.first_slot.min(entry.slot);
+        row.last_slot = row.last_slot.max(entry.slot);
+        totals.add(&entry);
+        *withdrawals += entry.withdrawals_value;
+        if !entry.vertically_integrated
+            && entry.delta_wei.is_negative()
+            && worst.map_or(true, |worst| entry.delta_wei < worst)
+        {
+            *worst = Some(entry.delta_wei);
+            row.worst_shortfall_slot = Some(entry.slot);
+        }
+    }
+    if skipped > 0 {
+        tracing::warn!(
+            skipped,
+            "rows without a fee_recipient column, backfill the output to include them"
+        );
+    }
+
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    for (mut row, totals, withdrawals, worst) in recipients.into_values() {
+        row.blocks = totals.blocks;
+        row.total_bid_value = totals.bid_value.to_string();
+        row.total_observed_payment = totals.observed.to_string();
+        row.total_withdrawals = withdrawals.to_string();
+        row.underpaid = totals.underpaid;
+        row.worst_shortfall = worst.map(|delta| (-delta).to_string());
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct PayoutAddressRow {
+    builder: String,
+    payment_from: Address,
+    payment_from_label: Option<String>,
+    /// Empty unless ENS names are resolved.
+    payment_from_ens: Option<String>,
+    blocks: u64,
+    first_slot: u64,
+    last_slot: u64,
+}
+
+/// Writes the distinct addresses each builder paid proposers from to stdout as CSV, with the
+/// slots they were first and last seen in. Builders are identified by name, or by pubkey when
+/// the name is unknown. With `ens` the addresses are also resolved to their ENS names.
+pub async fn builder_payouts(input: &Path, ens: Option<&EnsNames>) -> eyre::Result<()> {
+    let mut payouts: BTreeMap<(String, Address), PayoutAddressRow> = BTreeMap::new();
+    for entry in read_output_file(input)? {
+        let Some(from) = entry.payment_from else {
+            continue;
+        };
+        let builder = entry
+            .builder
+            .or(entry.builder_pubkey)
+            .unwrap_or_else(|| "unknown".to_string());
+        let row = payouts
+            .entry((builder.clone(), from))
+            .or_insert_with(|| PayoutAddressRow {
+                builder,
+                payment_from: from,
+                payment_from_label: entry.payment_from_label,
+                payment_from_ens: None,
+                blocks: 0,
+                first_slot: entry.slot,
+                last_slot: entry.slot,
+            });
+        row.blocks += 1;
+        row.first_slot = row.first_slot.min(entry.slot);
+        row.last_slot = row.last_slot.max(entry.slot);
+    }
+
+    let mut rows: Vec<_> = payouts.into_values().collect();
+    rows.sort_by(|a, b| (&a.builder, a.first_slot).cmp(&(&b.builder, b.first_slot)));
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    for mut row in rows {
+        if let Some(ens) = ens {
+            row.payment_from_ens = ens.name(row.payment_from).await;
+        }
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Prints whole-file totals of an output file.
+pub fn summary(input: &Path, tolerance: &Tolerance) -> eyre::Result<()> {
+    let mut totals = GroupTotals::default();
+    for entry in read_output_totals(input, tolerance)? {
+        totals.add(&entry);
+    }
+
+    println!("blocks:             {}", totals.blocks);
+    println!("total bid value:    {}", totals.bid_value);
+    println!("total balance diff: {}", totals.balance_diff);
+    println!(
+        "difference:         {}",
+        totals.balance_diff - I256::from_raw(totals.bid_value)
+    );
+    println!("average delta:      {}", totals.avg_delta());
+    println!("vert. integrated:   {}", totals.integrated);
+    println!("underpaid:          {}", totals.underpaid);
+    println!("overpaid:           {}", totals.overpaid);
+    println!("unknown:            {}", totals.payment_type("unknown"));
+    println!("missed slots:       {}", totals.missed);
+    println!("payment types:");
+    for (payment_type, count) in &totals.payment_types {
+        println!(
+            "  {:<18}{:>10} ({:.2}%)",
+            payment_type,
+            count,
+            *count as f64 * 100.0 / totals.blocks as f64
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,406 @@
+use ethers::prelude::*;
+use ethers::providers::{JsonRpcError, RpcError};
+use ethers::types::{Call, Create, CreateResult, Res, Reward, Suicide};
+use serde::{Deserialize, Serialize};
+
+use crate::rpc::EthProvider;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferData {
+    pub block_number: u64,
+    pub tx_hash: H256,
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceBackend {
+    /// `trace_block` (Erigon, reth, Nethermind).
+    #[default]
+    Parity,
+    /// `debug_traceBlockByNumber` with the `callTracer` (Geth).
+    Geth,
+    /// `trace_filter` for only the traces from or to the fee recipient, the coinbase and the
+    /// contract called by the last tx (Erigon, reth). Much less data than `trace_block` for busy
+    /// blocks; transfers between other addresses are not seen.
+    Filter,
+}
+
+/// Trace action that can move value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferAction {
+    Call,
+    #[value(name = "callcode")]
+    CallCode,
+    /// Carries the value of the calling frame, counting it double counts that value.
+    #[value(name = "delegatecall")]
+    DelegateCall,
+    #[value(name = "staticcall")]
+    StaticCall,
+    /// Contract creation with an endowment.
+    Create,
+    #[value(name = "selfdestruct")]
+    SelfDestruct,
+    /// Pre-merge block and uncle rewards, parity traces only.
+    Reward,
+}
+
+/// Trace actions counted as value transfers, only plain calls by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferActions(pub Vec<TransferAction>);
+
+impl Default for TransferActions {
+    fn default() -> Self {
+        Self(vec![TransferAction::Call])
+    }
+}
+
+impl TransferActions {
+    fn contains(&self, action: TransferAction) -> bool {
+        self.0.contains(&action)
+    }
+}
+
+/// Traces of a block as returned by the node.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Traces {
+    Parity(Vec<Trace>),
+    Geth(Vec<GethTxTrace>),
+}
+
+impl Traces {
+    pub fn transfers(
+        &self,
+        actions: &TransferActions,
+        block_number: u64,
+    ) -> eyre::Result<Vec<TransferData>> {
+        match self {
+            Traces::Parity(traces) => Ok(extract_transfers(traces, actions)),
+            Traces::Geth(traces) => extract_geth_transfers(traces, actions, block_number),
+        }
+    }
+}
+
+pub async fn fetch_traces(
+    provider: &EthProvider,
+    backend: TraceBackend,
+    block_number: u64,
+    addresses: &[Address],
+) -> eyre::Result<Traces> {
+    let client = provider.as_ref();
+    if client.traces_unsupported() {
+        return Err(TraceUnsupported.into());
+    }
+    let traces = request_traces(provider, backend, block_number, addresses).await;
+    if let Err(e) = &traces {
+        if is_method_not_found(e) {
+            tracing::warn!("the node doesn't support tracing, classifying without traces");
+            client.set_traces_unsupported();
+        }
+    }
+    traces
+}
+
+async fn request_traces(
+    provider: &EthProvider,
+    backend: TraceBackend,
+    block_number: u64,
+    addresses: &[Address],
+) -> eyre::Result<Traces> {
+    Ok(match backend {
+        TraceBackend::Parity => Traces::Parity(
+            provider
+                .trace_block(BlockNumber::Number(block_number.into()))
+                .await?,
+        ),
+        TraceBackend::Filter => Traces::Parity(
+            provider
+                .trace_filter(
+                    TraceFilter::default()
+                        .from_block(block_number)
+                        .to_block(block_number)
+                        .from_address(addresses.to_vec())
+                        .to_address(addresses.to_vec())
+                        .mode(TraceFilterMode::Union),
+                )
+                .await?,
+        ),
+        TraceBackend::Geth => Traces::Geth(
+            provider
+                .request(
+                    "debug_traceBlockByNumber",
+                    (
+                        BlockNumber::Number(block_number.into()),
+                        serde_json::json!({ "tracer": "callTracer" }),
+                    ),
+                )
+                .await?,
+        ),
+    })
+}
+
+/// JSON-RPC error code of a method the node doesn't have.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The node answered an earlier trace call with [METHOD_NOT_FOUND], so it isn't asked again.
+#[derive(Debug)]
+pub struct TraceUnsupported;
+
+impl std::fmt::Display for TraceUnsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the node doesn't support tracing")
+    }
+}
+
+impl std::error::Error for TraceUnsupported {}
+
+fn error_response(error: &eyre::Report) -> Option<&JsonRpcError> {
+    error
+        .downcast_ref::<ProviderError>()
+        .and_then(RpcError::as_error_response)
+}
+
+/// Whether the node answered with [METHOD_NOT_FOUND].
+pub fn is_method_not_found(error: &eyre::Report) -> bool {
+    error_response(error).map_or(false, |response| response.code == METHOD_NOT_FOUND)
+}
+
+/// Parts of the error messages nodes answer trace calls with when the block state is pruned.
+const PRUNED_STATE_ERRORS: &[&str] = &[
+    "missing trie node",
+    "historical state",
+    "pruned",
+    "state is not available",
+];
+
+/// Whether fetching traces failed because the node can't trace the block at all, i.e. it has no
+/// tracing methods or pruned the block state. Rate limits and other node errors aren't, so the
+/// entry fails and is retried instead of being written without traces.
+pub fn is_trace_unavailable(error: &eyre::Report) -> bool {
+    if error.is::<TraceUnsupported>() || is_method_not_found(error) {
+        return true;
+    }
+    error_response(error).map_or(false, |response| {
+        let message = response.message.to_lowercase();
+        PRUNED_STATE_ERRORS
+            .iter()
+            .any(|pruned| message.contains(pruned))
+    })
+}
+
+pub fn extract_transfers(traces: &[Trace], actions: &TransferActions) -> Vec<TransferData> {
+    let mut transfers = Vec::new();
+    for trace in traces {
+        if trace.error.is_some() {
+            continue;
+        }
+        let (action, from, to, value) = match &trace.action {
+            Action::Call(Call {
+                from,
+                to,
+                value,
+                call_type,
+                ..
+            }) => {
+                let action = match call_type {
+                    CallType::Call => TransferAction::Call,
+                    CallType::CallCode => TransferAction::CallCode,
+                    CallType::DelegateCall => TransferAction::DelegateCall,
+                    CallType::StaticCall => TransferAction::StaticCall,
+                    CallType::None => continue,
+                };
+                (action, *from, *to, *value)
+            }
+            Action::Create(Create { from, value, .. }) => match &trace.result {
+                Some(Res::Create(CreateResult { address, .. })) => {
+                    (TransferAction::Create, *from, *address, *value)
+                }
+                _ => continue,
+            },
+            Action::Suicide(Suicide {
+                address,
+                refund_address,
+                balance,
+            }) => (
+                TransferAction::SelfDestruct,
+                *address,
+                *refund_address,
+                *balance,
+            ),
+            Action::Reward(Reward { author, value, .. }) => {
+                (TransferAction::Reward, Address::zero(), *author, *value)
+            }
+        };
+        if value.is_zero() || !actions.contains(action) {
+            continue;
+        }
+        transfers.push(TransferData {
+            block_number: trace.block_number,
+            // rewards are the only traces outside of a transaction
+            tx_hash: trace.transaction_hash.unwrap_or_default(),
+            from,
+            to,
+            value,
+        });
+    }
+    transfers
+}
+
+/// Per-transaction result of `debug_traceBlockByNumber`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GethTxTrace {
+    pub tx_hash: Option<H256>,
+    pub result: GethCallFrame,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GethCallFrame {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub from: Address,
+    #[serde(default)]
+    pub to: Option<Address>,
+    #[serde(default)]
+    pub value: Option<U256>,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub calls: Vec<GethCallFrame>,
+}
+
+pub fn extract_geth_transfers(
+    traces: &[GethTxTrace],
+    actions: &TransferActions,
+    block_number: u64,
+) -> eyre::Result<Vec<TransferData>> {
+    fn visit(
+        frame: &GethCallFrame,
+        actions: &TransferActions,
+        tx_hash: H256,
+        block_number: u64,
+        out: &mut Vec<TransferData>,
+    ) {
+        // everything below a failed frame is reverted too
+        if frame.error.is_some() {
+            return;
+        }
+        let action = match frame.typ.as_str() {
+            "CALL" => Some(TransferAction::Call),
+            "CALLCODE" => Some(TransferAction::CallCode),
+            "DELEGATECALL" => Some(TransferAction::DelegateCall),
+            "STATICCALL" => Some(TransferAction::StaticCall),
+            "CREATE" | "CREATE2" => Some(TransferAction::Create),
+            "SELFDESTRUCT" => Some(TransferAction::SelfDestruct),
+            _ => None,
+        };
+        if let (Some(action), Some(to), Some(value)) = (action, frame.to, frame.value) {
+            if actions.contains(action) && !value.is_zero() {
+                out.push(TransferData {
+                    block_number,
+                    tx_hash,
+                    from: frame.from,
+                    to,
+                    value,
+                });
+            }
+        }
+        for call in &frame.calls {
+            visit(call, actions, tx_hash, block_number, out);
+        }
+    }
+
+    let mut transfers = Vec::new();
+    for trace in traces {
+        let tx_hash = trace
+            .tx_hash
+            .ok_or_else(|| eyre::eyre!("geth trace without txHash, node too old"))?;
+        visit(
+            &trace.result,
+            actions,
+            tx_hash,
+            block_number,
+            &mut transfers,
+        );
+    }
+    Ok(transfers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A delegated EOA called with 1 ETH whose delegation target pays on from a nested frame,
+    /// next to a reverted payment.
+    fn nested_trace() -> Vec<GethTxTrace> {
+        serde_json::from_value(serde_json::json!([{
+            "txHash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+            "result": {
+                "type": "CALL",
+                "from": "0x1111111111111111111111111111111111111111",
+                "to": "0x2222222222222222222222222222222222222222",
+                "value": "0xde0b6b3a7640000",
+                "calls": [{
+                    "type": "DELEGATECALL",
+                    "from": "0x2222222222222222222222222222222222222222",
+                    "to": "0x3333333333333333333333333333333333333333",
+                    "value": "0xde0b6b3a7640000",
+                    "calls": [{
+                        "type": "CALL",
+                        "from": "0x2222222222222222222222222222222222222222",
+                        "to": "0x4444444444444444444444444444444444444444",
+                        "value": "0x6f05b59d3b20000"
+                    }, {
+                        "type": "CALL",
+                        "from": "0x2222222222222222222222222222222222222222",
+                        "to": "0x5555555555555555555555555555555555555555",
+                        "value": "0x6f05b59d3b20000",
+                        "error": "execution reverted"
+                    }]
+                }]
+            }
+        }]))
+        .unwrap()
+    }
+
+    #[test]
+    fn geth_transfers_include_nested_calls() {
+        let transfers =
+            extract_geth_transfers(&nested_trace(), &TransferActions::default(), 1).unwrap();
+        let paid: Vec<(Address, U256)> = transfers.iter().map(|t| (t.to, t.value)).collect();
+        assert_eq!(
+            paid,
+            vec![
+                (
+                    "0x2222222222222222222222222222222222222222"
+                        .parse()
+                        .unwrap(),
+                    U256::exp10(18)
+                ),
+                (
+                    "0x4444444444444444444444444444444444444444"
+                        .parse()
+                        .unwrap(),
+                    U256::exp10(18) / 2
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn geth_delegatecall_counted_only_when_asked() {
+        let actions = TransferActions(vec![TransferAction::Call, TransferAction::DelegateCall]);
+        let transfers = extract_geth_transfers(&nested_trace(), &actions, 1).unwrap();
+        assert_eq!(transfers.len(), 3);
+        assert_eq!(
+            transfers[1].to,
+            "0x3333333333333333333333333333333333333333"
+                .parse()
+                .unwrap()
+        );
+    }
+}
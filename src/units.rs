@@ -0,0 +1,93 @@
+use ethers::types::{I256, U256};
+use ethers::utils::{format_units, parse_units, ParseUnits};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+
+/// Unit of the value columns of an output. Inputs are always in wei.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueUnit {
+    #[default]
+    Wei,
+    Gwei,
+    /// Fixed 18 decimals, so values stay exact.
+    Eth,
+}
+
+static UNIT: OnceCell<ValueUnit> = OnceCell::new();
+
+/// Sets the unit every output value column is written and read in, wei when never set.
+pub fn set(unit: ValueUnit) {
+    let _ = UNIT.set(unit);
+}
+
+pub fn get() -> ValueUnit {
+    UNIT.get().copied().unwrap_or_default()
+}
+
+impl ValueUnit {
+    fn name(self) -> &'static str {
+        match self {
+            ValueUnit::Wei => "wei",
+            ValueUnit::Gwei => "gwei",
+            ValueUnit::Eth => "ether",
+        }
+    }
+
+    pub fn format_u256(self, wei: U256) -> String {
+        match self {
+            ValueUnit::Wei => wei.to_string(),
+            _ => format_units(wei, self.name()).expect("unit is valid"),
+        }
+    }
+
+    pub fn format_i256(self, wei: I256) -> String {
+        match self {
+            ValueUnit::Wei => wei.to_string(),
+            _ => format_units(wei, self.name()).expect("unit is valid"),
+        }
+    }
+
+    pub fn parse_u256(self, s: &str) -> eyre::Result<U256> {
+        if self == ValueUnit::Wei {
+            return Ok(U256::from_dec_str(s)?);
+        }
+        match parse_units(s, self.name())? {
+            ParseUnits::U256(wei) => Ok(wei),
+            ParseUnits::I256(_) => eyre::bail!("negative value {s}"),
+        }
+    }
+
+    pub fn parse_i256(self, s: &str) -> eyre::Result<I256> {
+        if self == ValueUnit::Wei {
+            return Ok(I256::from_dec_str(s)?);
+        }
+        match parse_units(s, self.name())? {
+            ParseUnits::U256(wei) => Ok(I256::try_from(wei)?),
+            ParseUnits::I256(wei) => Ok(wei),
+        }
+    }
+}
+
+impl std::fmt::Display for ValueUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ValueUnit::Wei => "wei",
+            ValueUnit::Gwei => "gwei",
+            ValueUnit::Eth => "eth",
+        })
+    }
+}
+
+impl std::str::FromStr for ValueUnit {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        match s {
+            "wei" => Ok(ValueUnit::Wei),
+            "gwei" => Ok(ValueUnit::Gwei),
+            "eth" => Ok(ValueUnit::Eth),
+            _ => eyre::bail!("unknown value unit {s}"),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::file::read_entries;
+use crate::streams::{open_input, DialectArgs, InputFormat};
+
+const REQUIRED_COLUMNS: &[&str] = &["slot", "proposer_fee_recipient", "value", "block_number"];
+
+/// Number of individual problems printed per category before only counting them.
+const MAX_REPORTED: usize = 10;
+
+#[derive(Debug, Default)]
+struct Problems {
+    unparsable: usize,
+    duplicate_slots: usize,
+    non_monotonic_blocks: usize,
+}
+
+impl Problems {
+    fn total(&self) -> usize {
+        self.unparsable + self.duplicate_slots + self.non_monotonic_blocks
+    }
+}
+
+/// Checks an input file without touching RPC and prints what a `file` run would process.
+pub fn validate(input: &Path, output: Option<&Path>, dialect: &DialectArgs) -> eyre::Result<()> {
+    let source = dialect.input(input)?;
+    if source.format == InputFormat::Csv && source.dialect.has_headers {
+        let mut reader = source.dialect.reader(open_input(input)?);
+        let headers = source.schema.standard_header(reader.headers()?);
+        let missing = REQUIRED_COLUMNS
+            .iter()
+            .filter(|c| !headers.iter().any(|h| h == **c))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            eyre::bail!("missing required columns: {:?}", missing);
+        }
+    }
+
+    let processed = match output {
+        Some(output) if output.exists() => crate::file::read_slots(output, dialect.output(output))?,
+        _ => Default::default(),
+    };
+
+    let mut problems = Problems::default();
+    let mut rows = 0;
+    let mut slots = HashSet::new();
+    let mut already_processed = 0;
+    let mut last: Option<(u64, u64)> = None;
+    let mut slot_range: Option<(u64, u64)> = None;
+    let first_line = if source.dialect.has_headers { 2 } else { 1 };
+    for (i, entry) in read_entries(input, &source)?.enumerate() {
+        rows += 1;
+        let line = i + first_line;
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                problems.unparsable += 1;
+                if problems.unparsable <= MAX_REPORTED {
+                    println!("line {line}: unparsable row: {e}");
+                }
+                continue;
+            }
+        };
+
+        if !slots.insert(entry.slot) {
+            problems.duplicate_slots += 1;
+            if problems.duplicate_slots <= MAX_REPORTED {
+                println!("line {line}: duplicate slot {}", entry.slot);
+            }
+        } else if processed.contains(entry.slot) {
+            already_processed += 1;
+        }
+
+        if let Some((last_slot, last_block)) = last {
+            // slot-only rows have their block resolved when processed
+            if entry.slot > last_slot && entry.block_number != 0 && entry.block_number <= last_block
+            {
+                problems.non_monotonic_blocks += 1;
+                if problems.non_monotonic_blocks <= MAX_REPORTED {
+                    println!(
+                        "line {line}: block {} at slot {} does not follow block {} at slot {}",
+                        entry.block_number, entry.slot, last_block, last_slot
+                    );
+                }
+            }
+        }
+        last = Some((entry.slot, entry.block_number));
+        slot_range = Some(match slot_range {
+            Some((min, max)) => (min.min(entry.slot), max.max(entry.slot)),
+            None => (entry.slot, entry.slot),
+        });
+    }
+
+    println!("rows:                 {rows}");
+    println!("unparsable rows:      {}", problems.unparsable);
+    println!("duplicate slots:      {}", problems.duplicate_slots);
+    println!("non-monotonic blocks: {}", problems.non_monotonic_blocks);
+    if let Some((min, max)) = slot_range {
+        println!("slot range:           {min}..={max}");
+    }
+    println!("already processed:    {already_processed}");
+    println!("to process:           {}", slots.len() - already_processed);
+
+    if problems.total() > 0 {
+        eyre::bail!("input has {} problems", problems.total());
+    }
+    Ok(())
+}
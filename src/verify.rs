@@ -0,0 +1,130 @@
+use std::path::Path;
+
+use ethers::types::U256;
+
+use crate::{read_output_file, OutputFileEntry, Tolerance};
+
+/// Number of individual violations printed per invariant before only counting them.
+const MAX_REPORTED: usize = 10;
+
+/// Invariant every row of an output is expected to hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Invariant {
+    /// The observed payment is at least the bid, within the tolerance. Vertically integrated
+    /// blocks are exempt.
+    Underpaid,
+    /// The balance change is explained by transfers, withdrawals and fees, within
+    /// `--max-unexplained-wei`. Untraced blocks are exempt.
+    Unexplained,
+    /// A payment was identified.
+    Unknown,
+    /// The input block hash is the canonical block.
+    BlockHash,
+    /// The relay API reports the delivered value the input has.
+    RelayValue,
+    /// The fee recipient is the registered and the expected one.
+    Recipient,
+}
+
+impl Invariant {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Invariant::Underpaid => "underpaid",
+            Invariant::Unexplained => "unexplained",
+            Invariant::Unknown => "unknown",
+            Invariant::BlockHash => "block-hash",
+            Invariant::RelayValue => "relay-value",
+            Invariant::Recipient => "recipient",
+        }
+    }
+
+    /// Why `entry` violates the invariant, `None` if it holds.
+    fn violation(
+        &self,
+        entry: &OutputFileEntry,
+        tolerance: &Tolerance,
+        max_unexplained: U256,
+    ) -> Option<String> {
+        match self {
+            Invariant::Underpaid => (!entry.vertically_integrated
+                && entry.era != "pre_merge"
+                && tolerance.is_underpaid(entry.bid_value, entry.observed_payment))
+            .then(|| {
+                format!(
+                    "observed payment {} wei below bid {} wei",
+                    entry.observed_payment, entry.bid_value
+                )
+            }),
+            Invariant::Unexplained => entry
+                .unexplained_diff
+                .filter(|diff| diff.unsigned_abs() > max_unexplained)
+                .map(|diff| format!("unexplained balance change {diff} wei")),
+            Invariant::Unknown => (entry.payment_type == "unknown").then(|| {
+                format!(
+                    "no payment identified ({})",
+                    entry.unknown_reason.as_deref().unwrap_or("no reason")
+                )
+            }),
+            Invariant::BlockHash => (entry.block_hash_match == Some(false))
+                .then(|| "input block hash is not canonical".to_string()),
+            Invariant::RelayValue => entry.relay_value_mismatch.then(|| {
+                format!(
+                    "relay reports {} wei, input has {} wei",
+                    entry.relay_value.unwrap_or_default(),
+                    entry.bid_value
+                )
+            }),
+            Invariant::Recipient => {
+                (entry.wrong_recipient || entry.registration_mismatch).then(|| {
+                    match (entry.expected_fee_recipient, entry.registered_fee_recipient) {
+                        (Some(expected), _) if entry.wrong_recipient => {
+                            format!("fee recipient is not the expected {expected:?}")
+                        }
+                        (_, Some(registered)) => {
+                            format!("fee recipient is not the registered {registered:?}")
+                        }
+                        _ => "fee recipient mismatch".to_string(),
+                    }
+                })
+            }
+        }
+    }
+}
+
+/// Checks every row of `output` against `invariants` and prints the violations, failing if
+/// there are any so it can run as a scheduled check.
+pub fn verify(
+    output: &Path,
+    invariants: &[Invariant],
+    tolerance: &Tolerance,
+    max_unexplained: U256,
+) -> eyre::Result<()> {
+    let entries = read_output_file(output)?;
+    let mut violations = vec![0usize; invariants.len()];
+    // a missed slot has no block the invariants could hold for
+    for entry in entries.iter().filter(|entry| !entry.is_missed()) {
+        for (invariant, count) in invariants.iter().zip(&mut violations) {
+            if let Some(violation) = invariant.violation(entry, tolerance, max_unexplained) {
+                *count += 1;
+                if *count <= MAX_REPORTED {
+                    println!(
+                        "slot {} (block {}): {}: {violation}",
+                        entry.slot,
+                        entry.block_number,
+                        invariant.as_str()
+                    );
+                }
+            }
+        }
+    }
+
+    println!("rows:                 {}", entries.len());
+    for (invariant, count) in invariants.iter().zip(&violations) {
+        println!("{:<22}{count}", format!("{}:", invariant.as_str()));
+    }
+    let total: usize = violations.iter().sum();
+    if total > 0 {
+        eyre::bail!("{total} invariant violations");
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::file::{self, InputFilter};
+use crate::rpc::EthProvider;
+use crate::streams::DialectArgs;
+use crate::ProcessingOptions;
+
+/// Extensions of the files picked up, also compressed with `.gz` or `.zst`.
+const INPUT_EXTENSIONS: &[&str] = &["csv", "tsv", "json"];
+
+/// Names of the files in the watched directory that are fully processed, one per line in
+/// `<output>.watched`.
+struct Watched {
+    path: PathBuf,
+    names: BTreeSet<String>,
+}
+
+impl Watched {
+    fn load(output: &Path) -> eyre::Result<Self> {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".watched");
+        let path = PathBuf::from(path);
+        let names = match std::fs::read_to_string(&path) {
+            Ok(names) => names.lines().map(str::to_string).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, names })
+    }
+
+    fn insert(&mut self, name: String) -> eyre::Result<()> {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{name}")?;
+        self.names.insert(name);
+        Ok(())
+    }
+}
+
+fn is_input(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    // editors and partial downloads leave hidden files behind
+    !name.starts_with('.')
+        && name
+            .split('.')
+            .skip(1)
+            .any(|ext| INPUT_EXTENSIONS.contains(&ext))
+}
+
+/// Processes every relay export dropped into `dir` into `output`, polling every `interval`.
+///
+/// A file is picked up once its size stayed the same between two polls, so files still being
+/// written are left alone. Files whose run failed or deferred some entries are processed again on
+/// the next poll, which only retries those slots. A file that can't be processed at all, e.g. a
+/// malformed one, is skipped until it changes. Returns `true` when stopped by a signal.
+#[allow(clippy::too_many_arguments)]
+pub async fn watch(
+    provider: EthProvider,
+    options: Arc<ProcessingOptions>,
+    dir: &Path,
+    output: &Path,
+    filter: InputFilter,
+    dialect: &DialectArgs,
+    rpc_parallel: usize,
+    interval: Duration,
+) -> eyre::Result<bool> {
+    let output_dir = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if output_dir.canonicalize()? == dir.canonicalize()? {
+        eyre::bail!("the output and its sidecar files have to be outside of the watched directory");
+    }
+    let mut watched = Watched::load(output)?;
+    let mut sizes: HashMap<PathBuf, u64> = HashMap::new();
+    // size of the files whose run returned an error
+    let mut broken: HashMap<PathBuf, u64> = HashMap::new();
+    tracing::info!(dir = %dir.display(), done = watched.names.len(), "watching for relay exports");
+    loop {
+        let mut ready = Vec::new();
+        let mut seen = HashMap::new();
+        for dir_entry in std::fs::read_dir(dir)? {
+            let path = dir_entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if !path.is_file() || !is_input(&path) || watched.names.contains(name) {
+                continue;
+            }
+            let size = std::fs::metadata(&path)?.len();
+            if broken.get(&path) == Some(&size) {
+                continue;
+            }
+            if sizes.get(&path) == Some(&size) {
+                ready.push(path.clone());
+            }
+            seen.insert(path, size);
+        }
+        sizes = seen;
+        ready.sort();
+
+        for input in ready {
+            tracing::info!(input = %input.display(), "processing");
+            let report = match file::run(
+                provider.clone(),
+                options.clone(),
+                &input,
+                output,
+                filter.clone(),
+                dialect,
+                rpc_parallel,
+                false,
+                None,
+            )
+            .await
+            {
+                Ok(report) => report,
+                Err(e) => {
+                    tracing::error!(input = %input.display(), "skipping until it changes: {e:#}");
+                    broken.insert(input.clone(), sizes[&input]);
+                    continue;
+                }
+            };
+            tracing::info!(
+                input = %input.display(),
+                processed = report.processed,
+                skipped = report.skipped,
+                failed = report.failed,
+                unknown = report.unknown,
+                deferred = report.deferred,
+                "processed"
+            );
+            if report.interrupted {
+                return Ok(true);
+            }
+            if report.failed == 0 && report.deferred == 0 {
+                let name = input
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .expect("listed files have a name");
+                watched.insert(name.to_string())?;
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = file::shutdown_signal() => return Ok(true),
+        }
+    }
+}